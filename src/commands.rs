@@ -1,47 +1,116 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::path::Path;
+use std::time::Duration;
 
-use anyhow::Result;
-use base64::prelude::*;
-use tracing::{info, warn};
+use chrono::{Datelike, NaiveDate};
 
-use crate::client::NubappClient;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::client::{
+    decode_jwt_payload, is_success, parse_bookings, NubappClient, SlotCache, SlotFilter,
+};
 use crate::config;
-use crate::models::{SlotConfig, User};
+use crate::history::{format_latency, History, HistoryEntry};
+use crate::ics;
+use crate::models::{
+    guest_suffix, id_string, ApiConfig, AppConfig, Booking, BookingsResponse, Choices, Config,
+    Slot, SlotConfig, User, WaitingEntry,
+};
 use crate::scheduler;
+use crate::web::metrics::Metrics;
+use crate::web::slot_scheduler::{self, PlannedBooking};
+use crate::web::watcher::try_book_from_waiting_list;
+
+/// The configured user `--user` names (by `name` or `login`, ignoring case),
+/// or the first configured user when it names none or isn't given. One-off
+/// commands take the password and gym the flags don't give from this user.
+pub fn flag_user<'a>(cfg: &'a Config, user_flag: &Option<String>) -> Option<&'a User> {
+    user_flag
+        .as_deref()
+        .and_then(|name| cfg.users.iter().find(|u| u.is_named(name.trim())))
+        .or(cfg.users.first())
+}
 
-/// Resolve login/password from CLI flags or first user in config
+/// Resolve login/password from CLI flags or the configured user from [`flag_user`].
+/// A `--user` giving that user's name logs in with their `login`.
 pub fn resolve_credentials<'a>(
     user_flag: &'a Option<String>,
     pass_flag: &'a Option<String>,
-    first_user: Option<&'a User>,
+    configured: Option<&'a User>,
 ) -> Result<(&'a str, &'a str)> {
     let login = match user_flag {
-        Some(u) => u.as_str(),
-        None => first_user
+        Some(u) => match configured.filter(|c| c.is_named(u.trim())) {
+            Some(c) => c.login.as_str(),
+            None => u.as_str(),
+        },
+        None => configured
             .map(|u| u.login.as_str())
             .ok_or_else(|| anyhow::anyhow!("No users in config and no --user provided"))?,
     };
     let pass = match pass_flag {
         Some(p) => p.as_str(),
-        None => first_user
+        None => configured
             .map(|u| u.password.as_str())
             .ok_or_else(|| anyhow::anyhow!("No users in config and no --password provided"))?,
     };
     Ok((login, pass))
 }
 
+/// Apply one-off `--time`/`--activity`/`--guests` overrides to the slot config of each of `days`.
+pub fn override_slots(
+    slot_configs: &HashMap<String, SlotConfig>,
+    days: &[String],
+    time: Option<&str>,
+    activity: Option<&str>,
+    guests: Option<u32>,
+) -> Result<HashMap<String, SlotConfig>> {
+    if let Some(t) = time {
+        if let Err(e) = scheduler::parse_slot_time(t) {
+            bail!("Invalid --time {e}");
+        }
+    }
+
+    let mut slots = slot_configs.clone();
+    for day in days {
+        let cfg = match (slots.get_mut(day), time) {
+            (Some(cfg), _) => cfg,
+            (None, Some(t)) => slots
+                .entry(day.clone())
+                .or_insert_with(|| SlotConfig::new(t)),
+            (None, None) => continue,
+        };
+        if let Some(t) = time {
+            cfg.time = Choices::Single(t.to_string());
+        }
+        if let Some(a) = activity {
+            cfg.activity = Some(Choices::Single(a.to_string()));
+        }
+        if let Some(g) = guests {
+            cfg.guests = g;
+        }
+        // An explicit one-off request books the day even if it is paused in config
+        cfg.enabled = true;
+    }
+    Ok(slots)
+}
+
+/// Book `user`'s slots for the coming week with `nubapp`, a client for their
+/// gym, joining waiting lists for full classes as `app` and the user allow.
+/// Returns whether any of them ended on a waiting list.
 pub async fn run_for_user(
-    application_id: &str,
-    category_activity_id: &str,
+    mut nubapp: NubappClient,
     verbose: bool,
     debug: bool,
     user: &User,
-    slot_configs: &std::collections::HashMap<String, SlotConfig>,
-) -> Result<()> {
+    slot_configs: &HashMap<String, SlotConfig>,
+    app: &AppConfig,
+    history: &History,
+) -> Result<bool> {
     info!("Processing user: {}", user.name);
 
-    let mut nubapp = NubappClient::new(application_id, category_activity_id)?;
-
     let login_resp = nubapp.login(&user.login, &user.password).await?;
     if verbose {
         println!(
@@ -50,11 +119,12 @@ pub async fn run_for_user(
         );
     }
 
-    let today = scheduler::now().date_naive();
-    let mut calendar: Vec<(String, String)> = Vec::new(); // (day, slot_id)
+    let now = scheduler::now(app.tz()).naive_local();
+    // (day, date, slot_ids by preference, guests)
+    let mut calendar: Vec<(String, NaiveDate, Vec<String>, u32)> = Vec::new();
 
     for day_name in &user.slots {
-        let weekday = match scheduler::parse_weekday(day_name) {
+        let weekday = match scheduler::slot_weekday(day_name) {
             Some(wd) => wd,
             None => {
                 warn!("Unknown day '{}', skipping", day_name);
@@ -70,345 +140,1851 @@ pub async fn run_for_user(
             }
         };
 
-        let target_date = scheduler::next_weekday(today, weekday);
-        let date_str = target_date.format("%d-%m-%Y").to_string();
-
-        info!(
-            "{}: looking for slot at {} ({}) on {} ({})",
-            user.name,
-            slot_cfg.time,
-            slot_cfg.activity.as_deref().unwrap_or("any"),
-            target_date,
-            date_str
-        );
-
-        let slots = nubapp.get_slots(&date_str).await?;
-
-        if verbose {
-            for slot in &slots {
-                println!(
-                    "  Available: {} - {} — {} (ID: {})",
-                    slot.start,
-                    slot.end,
-                    slot.name.as_deref().unwrap_or("?"),
-                    slot.id_activity_calendar
-                );
-            }
+        if !slot_cfg.enabled {
+            info!("Slot for '{}' is disabled, skipping", day_name);
+            continue;
         }
 
-        match NubappClient::find_slot(&slots, &slot_cfg.time, slot_cfg.activity.as_deref()) {
-            Some(slot) => {
-                let slot_id = slot.id_activity_calendar.to_string();
-                let slot_id = slot_id.trim_matches('"').to_string();
-                info!(
-                    "Found slot: {} — {} (ID: {})",
-                    slot.start,
-                    slot.name.as_deref().unwrap_or("?"),
-                    slot_id,
-                );
-                calendar.push((day_name.clone(), slot_id));
-            }
-            None => {
-                warn!(
-                    "No slot found for {} at {} ({}) on {}",
-                    user.name,
-                    slot_cfg.time,
-                    slot_cfg.activity.as_deref().unwrap_or("any"),
-                    target_date
-                );
+        let target_date =
+            scheduler::next_class_date(now, weekday, scheduler::same_day_until(app, slot_cfg));
+        let slot_ids = find_slot_ids(&mut nubapp, verbose, user, slot_cfg, target_date).await?;
+
+        if slot_ids.is_empty() {
+            warn!(
+                "No slot found for {} at {} ({}) on {}",
+                user.name,
+                slot_cfg.time,
+                slot_cfg.activity_label(),
+                target_date
+            );
+            if !debug {
+                history.record(&HistoryEntry::new(
+                    scheduler::now(app.tz()),
+                    &user.name,
+                    day_name,
+                    target_date,
+                    "slot_not_found",
+                ));
             }
+        } else {
+            calendar.push((day_name.clone(), target_date, slot_ids, slot_cfg.guests));
         }
     }
 
-    for (day, slot_id) in &calendar {
+    let mut waiting = false;
+    for (day, date, slot_ids, guests) in &calendar {
+        let guests_label = guest_suffix(*guests);
         if debug {
             println!(
-                "[DRY RUN] Would book {} for {} (slot ID: {})",
-                day, user.name, slot_id
+                "[DRY RUN] Would book {}{} for {} (slot ID: {})",
+                day,
+                guests_label,
+                user.name,
+                slot_ids.join(", falling back to ")
             );
-        } else {
-            info!("Booking {} for {} (slot ID: {})", day, user.name, slot_id);
-            let resp = nubapp.book(slot_id).await?;
-            let success = resp
-                .get("success")
-                .and_then(|v| v.as_bool())
-                .unwrap_or(false);
-            if verbose {
-                println!(
-                    "Booking response: {}",
-                    serde_json::to_string_pretty(&resp)?
-                );
-            }
-            if success {
-                println!("Booked {} for {}", day, user.name);
-            } else {
-                let msg = resp
-                    .get("message")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("unknown error");
-                warn!("Failed to book {} for {}: {}", day, user.name, msg);
-                // Try waiting list
-                info!("Trying waiting list for {} ...", day);
-                let wl_resp = nubapp.book_waiting_list(slot_id).await?;
-                let wl_success = wl_resp
-                    .get("success")
-                    .and_then(|v| v.as_bool())
-                    .unwrap_or(false);
-                if verbose {
-                    println!(
-                        "Waiting list response: {}",
-                        serde_json::to_string_pretty(&wl_resp)?
-                    );
-                }
-                if wl_success {
-                    println!("Added to waiting list for {} for {}", day, user.name);
-                } else {
-                    let wl_msg = wl_resp
-                        .get("message")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("unknown error");
-                    warn!(
-                        "Failed to join waiting list for {} for {}: {}",
-                        day, user.name, wl_msg
-                    );
-                }
-            }
+            continue;
         }
+
+        waiting |= book_preferred(
+            &mut nubapp,
+            verbose,
+            user,
+            day,
+            day,
+            *date,
+            slot_ids,
+            *guests,
+            app,
+            history,
+        )
+        .await?;
     }
 
     if calendar.is_empty() {
         println!("No slots to book for {}", user.name);
     }
 
-    Ok(())
+    Ok(waiting)
 }
 
-pub async fn run_discover(
-    application_id: &str,
-    username: &str,
-    password: &str,
+/// Check `users`' waiting lists every `[watcher].active_interval_secs` for
+/// `[app].waiting_list_poll_minutes` and book any spot that frees up, the way
+/// `serve`'s watcher does. Stops early once no user is on a waiting list any more.
+pub async fn poll_waiting_lists(cfg: &Config, users: &[User]) {
+    let minutes = cfg.app.waiting_list_poll_minutes;
+    if minutes == 0 || users.is_empty() {
+        return;
+    }
+    let deadline = std::time::Instant::now() + Duration::from_secs(minutes as u64 * 60);
+    println!(
+        "Watching waiting lists for up to {} minutes (Ctrl-C to stop)",
+        minutes
+    );
+    let metrics = Metrics::default();
+    let mut alerted = HashSet::new();
+    let mut waiting: Vec<&User> = users.iter().collect();
+    let interval = cfg.watcher.active_interval();
+    while !waiting.is_empty() && std::time::Instant::now() + interval <= deadline {
+        tokio::time::sleep(interval).await;
+        let slot_cache = SlotCache::default();
+        let mut still_waiting = Vec::new();
+        for user in waiting {
+            match try_book_from_waiting_list(cfg, user, &metrics, &slot_cache, &mut alerted).await {
+                Ok(true) => still_waiting.push(user),
+                Ok(false) => println!("{} is no longer on any waiting list", user.name),
+                Err(e) => {
+                    error!("Waiting-list check failed for {}: {:#}", user.name, e);
+                    still_waiting.push(user);
+                }
+            }
+        }
+        waiting = still_waiting;
+    }
+    if !waiting.is_empty() {
+        println!("Stopped watching waiting lists after {} minutes", minutes);
+    }
+}
+
+/// IDs of the slots on `date` matching `slot_cfg`, one per configured time in order of preference.
+async fn find_slot_ids(
+    nubapp: &mut NubappClient,
     verbose: bool,
-) -> Result<()> {
-    let mut nubapp = NubappClient::new(application_id, "0")?;
+    user: &User,
+    slot_cfg: &SlotConfig,
+    date: NaiveDate,
+) -> Result<Vec<String>> {
+    let date_str = date.format("%d-%m-%Y").to_string();
 
-    println!("Logging in as {}...", username);
-    let login_resp = nubapp.login(username, password).await?;
+    info!(
+        "{}: looking for slot at {} ({}) on {} ({})",
+        user.name,
+        slot_cfg.time,
+        slot_cfg.activity_label(),
+        date,
+        date_str
+    );
 
-    // Decode JWT token to extract account info
-    if let Some(token) = login_resp.get("token").and_then(|t| t.as_str()) {
-        let parts: Vec<&str> = token.split('.').collect();
-        if parts.len() >= 2 {
-            if let Ok(payload_bytes) = BASE64_URL_SAFE_NO_PAD.decode(parts[1]) {
-                if let Ok(payload) =
-                    serde_json::from_slice::<serde_json::Value>(&payload_bytes)
-                {
-                    println!("\n=== Account Information ===");
-                    if let Some(id) = payload.get("id_application") {
-                        println!("  application_id: {}", id);
-                    }
-                    if let Some(id) = payload.get("id_user") {
-                        println!("  user_id:        {}", id);
-                    }
-                    if let Some(name) = payload.get("username") {
-                        println!("  username:       {}", name.as_str().unwrap_or("?"));
-                    }
-                    if verbose {
-                        println!(
-                            "\n  Full JWT payload:\n  {}",
-                            serde_json::to_string_pretty(&payload)?
-                        );
-                    }
-                }
+    let slots = nubapp
+        .get_slots_in(&date_str, slot_cfg.category.as_deref())
+        .await?;
+
+    if verbose {
+        for slot in &slots {
+            println!(
+                "  Available: {} - {} — {} (ID: {})",
+                slot.start,
+                slot.end,
+                slot.name.as_deref().unwrap_or("?"),
+                slot.id_activity_calendar
+            );
+        }
+    }
+
+    let mut slot_ids: Vec<String> = Vec::new();
+    let choices = slot_cfg
+        .time
+        .all()
+        .iter()
+        .flat_map(|time| slot_cfg.activities().into_iter().map(move |a| (time, a)));
+    for (time, activity) in choices {
+        if let Some(slot) =
+            NubappClient::find_slot(&slots, time, &SlotFilter::for_slot(slot_cfg, activity))
+        {
+            let slot_id = slot.calendar_id();
+            // A loose activity name can find the same class twice
+            if slot_ids.contains(&slot_id) {
+                continue;
             }
+            info!(
+                "Found slot: {} — {} (ID: {})",
+                slot.start,
+                slot.name.as_deref().unwrap_or("?"),
+                slot_id,
+            );
+            slot_ids.push(slot_id);
         }
     }
+    Ok(slot_ids)
+}
 
-    // Fetch activity categories
-    println!("\n=== Activity Categories ===");
-    match nubapp.get_categories().await {
-        Ok(resp) => {
-            let cats = resp.get("data").unwrap_or(&resp);
-            match cats {
-                serde_json::Value::Array(arr) if !arr.is_empty() => {
-                    for cat in arr {
-                        let id = cat
-                            .get("id_category_activity")
-                            .or_else(|| cat.get("id"))
-                            .map(|v| v.to_string())
-                            .unwrap_or_else(|| "?".into());
-                        let name = cat
-                            .get("name")
-                            .or_else(|| cat.get("title"))
-                            .and_then(|v| v.as_str())
-                            .unwrap_or("?");
-                        println!("  [{}] {}", id.trim_matches('"'), name);
-                    }
-                }
-                other => {
-                    if verbose {
-                        println!(
-                            "  Raw response:\n  {}",
-                            serde_json::to_string_pretty(other)?
-                        );
-                    } else {
-                        println!("  Could not list categories. Re-run with -v for details.");
-                    }
-                }
+/// Book the first of `slot_ids` on `date` that has room, joining the waiting
+/// list for the last one when none does and the user and `app` allow it.
+/// `day` labels the booking in the output; every attempt goes to `history`
+/// under the `[slots]` key `slot_key`. Returns whether the user ended on the
+/// waiting list.
+#[allow(clippy::too_many_arguments)]
+async fn book_preferred(
+    nubapp: &mut NubappClient,
+    verbose: bool,
+    user: &User,
+    day: &str,
+    slot_key: &str,
+    date: NaiveDate,
+    slot_ids: &[String],
+    guests: u32,
+    app: &AppConfig,
+    history: &History,
+) -> Result<bool> {
+    let guests_label = guest_suffix(guests);
+    let join_waiting_list = user.joins_waiting_list(app);
+    let entry = |outcome: &str, slot_id: &str| {
+        HistoryEntry::new(
+            scheduler::now(app.tz()),
+            &user.name,
+            slot_key,
+            date,
+            outcome,
+        )
+        .with_slot(slot_id)
+    };
+    for (i, slot_id) in slot_ids.iter().enumerate() {
+        info!(
+            "Booking {}{} for {} (slot ID: {})",
+            day, guests_label, user.name, slot_id
+        );
+        let resp = match nubapp.book(slot_id, Some(guests)).await {
+            Ok(r) => r,
+            Err(e) => {
+                history.record(&entry("error", slot_id).with_message(format!("{e:#}")));
+                return Err(e);
+            }
+        };
+        let success = is_success(&resp);
+        if verbose {
+            println!("Booking response: {}", serde_json::to_string_pretty(&resp)?);
+        }
+        if success {
+            history.record(&entry("booked", slot_id));
+            println!("Booked {}{} for {}", day, guests_label, user.name);
+            break;
+        }
+
+        let msg = resp
+            .get("message")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown error");
+        history.record(&entry("failed", slot_id).with_message(msg));
+        warn!("Failed to book {} for {}: {}", day, user.name, msg);
+        if i < slot_ids.len() - 1 {
+            info!("Trying next preferred time for {} ...", day);
+            continue;
+        }
+
+        if !join_waiting_list {
+            history.record(&entry("waiting_list_disabled", slot_id).with_message(msg));
+            println!("{} is full for {}, waiting list disabled", day, user.name);
+            break;
+        }
+
+        // Last choice — try waiting list
+        info!("Trying waiting list for {} ...", day);
+        let wl_resp = match nubapp.book_waiting_list(slot_id).await {
+            Ok(r) => r,
+            Err(e) => {
+                history.record(&entry("error", slot_id).with_message(format!("{e:#}")));
+                return Err(e);
             }
+        };
+        let wl_success = is_success(&wl_resp);
+        if verbose {
+            println!(
+                "Waiting list response: {}",
+                serde_json::to_string_pretty(&wl_resp)?
+            );
         }
-        Err(e) => {
-            println!("  Could not fetch categories: {}", e);
+        if wl_success {
+            history.record(&entry("waiting_list", slot_id));
+            println!("Added to waiting list for {} for {}", day, user.name);
+            return Ok(true);
         }
+        let wl_msg = wl_resp
+            .get("message")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown error");
+        history.record(&entry("failed", slot_id).with_message(wl_msg));
+        warn!(
+            "Failed to join waiting list for {} for {}: {}",
+            day, user.name, wl_msg
+        );
     }
+    Ok(false)
+}
 
-    println!("\nUse these values in your config.toml under [app].");
-    Ok(())
+/// The user a one-off command books for: `--user`/`--password`, or the
+/// first configured user, at the gym of the user [`flag_user`] finds.
+fn cli_user(
+    cfg: &Config,
+    user: &Option<String>,
+    password: &Option<String>,
+    day: &str,
+) -> Result<User> {
+    let configured = flag_user(cfg, user);
+    let (login, pass) = resolve_credentials(user, password, configured)?;
+    Ok(User {
+        name: login.to_string(),
+        login: login.to_string(),
+        password: pass.to_string(),
+        slots: vec![day.to_string()],
+        gym: configured.and_then(|u| u.gym.clone()),
+        watch_only: None,
+        join_waiting_list: None,
+        max_per_week: None,
+        notify: None,
+    })
 }
 
-fn print_booking(b: &serde_json::Value) {
-    let start = b
-        .get("start_timestamp")
-        .or_else(|| b.get("start"))
-        .and_then(|v| v.as_str())
-        .unwrap_or("?");
-    let end = b
-        .get("end_timestamp")
-        .or_else(|| b.get("end"))
-        .and_then(|v| v.as_str())
-        .unwrap_or("");
-    let name = b
-        .get("name")
-        .or_else(|| b.get("name_activity"))
-        .and_then(|v| v.as_str())
-        .unwrap_or("?")
-        .trim();
-    let spots = b.get("n_inscribed").and_then(|v| v.as_u64());
-    let capacity = b.get("n_capacity").and_then(|v| v.as_u64());
-
-    print!("  {} to {} — {}", start, end, name);
-    if let (Some(s), Some(c)) = (spots, capacity) {
-        print!(" ({}/{})", s, c);
+/// Book a single class on `date`, whatever the weekday. `time`, `activity`
+/// and `guests` override the `[slots]` config of that weekday, which
+/// supplies whatever isn't given.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_book_date(
+    verbose: bool,
+    config_path: &Path,
+    user: &Option<String>,
+    password: &Option<String>,
+    date: &str,
+    time: Option<&str>,
+    activity: Option<&str>,
+    guests: Option<u32>,
+    debug: bool,
+) -> Result<()> {
+    let cfg = config::load_config(config_path)?;
+    let today = scheduler::now(cfg.app.tz()).date_naive();
+    let date = scheduler::parse_date(date, today).ok_or_else(|| {
+        anyhow::anyhow!("Invalid date '{}', expected DD-MM-YYYY or YYYY-MM-DD", date)
+    })?;
+    if date < today {
+        bail!("{} is in the past", date);
     }
-    println!();
+    let day = scheduler::canonical_day(&date.weekday().to_string()).unwrap_or_default();
+    let slots = override_slots(&cfg.slots, &[day.to_string()], time, activity, guests)?;
+    let Some(slot_cfg) = slots.get(day) else {
+        bail!("No slot configured for {} — pass --time", day);
+    };
+
+    let u = cli_user(&cfg, user, password, day)?;
+    let mut nubapp = NubappClient::from_config(&cfg, u.gym.as_deref())?;
+    nubapp.login(&u.login, &u.password).await?;
+
+    let label = format!("{} {}", day, date);
+    let slot_ids = find_slot_ids(&mut nubapp, verbose, &u, slot_cfg, date).await?;
+    if slot_ids.is_empty() {
+        bail!(
+            "No slot found at {} ({}) on {}",
+            slot_cfg.time,
+            slot_cfg.activity_label(),
+            date
+        );
+    }
+    if debug {
+        println!(
+            "[DRY RUN] Would book {}{} for {} (slot ID: {})",
+            label,
+            guest_suffix(slot_cfg.guests),
+            u.name,
+            slot_ids.join(", falling back to ")
+        );
+        return Ok(());
+    }
+    let history = History::next_to(config_path);
+    let waiting = book_preferred(
+        &mut nubapp,
+        verbose,
+        &u,
+        &label,
+        day,
+        date,
+        &slot_ids,
+        slot_cfg.guests,
+        &cfg.app,
+        &history,
+    )
+    .await?;
+    if waiting {
+        poll_waiting_lists(&cfg, std::slice::from_ref(&u)).await;
+    }
+    Ok(())
 }
 
-fn print_waiting_list_entry(b: &serde_json::Value) {
-    let start = b
-        .get("start_timestamp")
-        .and_then(|v| v.as_str())
-        .unwrap_or("?");
-    let end = b
-        .get("end_timestamp")
-        .and_then(|v| v.as_str())
-        .unwrap_or("");
-    let name = b
-        .get("name_activity")
-        .or_else(|| b.get("name"))
-        .and_then(|v| v.as_str())
-        .unwrap_or("?")
-        .trim();
-
-    print!("  {} to {} — {}", start, end, name);
-    let inscribed = b.get("n_inscribed").and_then(|v| v.as_u64());
-    let capacity = b.get("n_capacity").and_then(|v| v.as_u64());
-    if let (Some(s), Some(c)) = (inscribed, capacity) {
-        let free = c.saturating_sub(s);
-        print!(" ({}/{}, {} free)", s, c, free);
+/// Every `weekday` from `from` to `to`, both included.
+fn weekday_dates(from: NaiveDate, to: NaiveDate, weekday: chrono::Weekday) -> Vec<NaiveDate> {
+    let mut dates = Vec::new();
+    let mut date = scheduler::next_weekday(from - chrono::Duration::days(1), weekday);
+    while date <= to {
+        dates.push(date);
+        date = scheduler::next_weekday(date, weekday);
     }
-    println!();
+    dates
 }
 
-pub async fn run_bookings(
+/// Book `day` for every week between `from` (default today) and `to`, as
+/// `book` would for a single week. Dates whose booking window hasn't opened
+/// yet are reported as not yet open and left for a later run or `serve`.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_book_range(
     verbose: bool,
     config_path: &Path,
     user: &Option<String>,
     password: &Option<String>,
+    day: &str,
+    from: Option<&str>,
+    to: &str,
+    debug: bool,
 ) -> Result<()> {
     let cfg = config::load_config(config_path)?;
-    let (login, pass) = resolve_credentials(user, password, cfg.users.first())?;
+    let (Some(weekday), Some(day)) = (
+        scheduler::slot_weekday(day),
+        scheduler::canonical_slot_key(day),
+    ) else {
+        bail!("Unknown day '{}'", day.trim());
+    };
+    let Some(slot_cfg) = cfg.slots.get(&day) else {
+        bail!("No slot configured for '{}'", day);
+    };
+    let window = scheduler::window_schedule(&cfg, slot_cfg)
+        .ok_or_else(|| anyhow::anyhow!("Cannot parse slot time '{}'", slot_cfg.time))?;
 
-    let mut nubapp =
-        NubappClient::new(&cfg.app.application_id, &cfg.app.category_activity_id)?;
-    nubapp.login(login, pass).await?;
+    let today = scheduler::now(cfg.app.tz()).date_naive();
+    let parse = |flag: &str, s: &str| {
+        scheduler::parse_date(s, today).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Invalid --{} '{}', expected DD-MM-YYYY, today, tomorrow or a day name",
+                flag,
+                s
+            )
+        })
+    };
+    let from = match from {
+        Some(s) => parse("from", s)?,
+        None => today,
+    };
+    let to = parse("to", to)?;
+    let dates = weekday_dates(from.max(today), to, weekday);
+    if dates.is_empty() {
+        bail!("No {} between {} and {}", day, from, to);
+    }
 
-    let resp = nubapp.get_bookings().await?;
+    let u = cli_user(&cfg, user, password, &day)?;
+    let mut nubapp = NubappClient::from_config(&cfg, u.gym.as_deref())?;
+    nubapp.login(&u.login, &u.password).await?;
 
-    if verbose {
-        println!("{}", serde_json::to_string_pretty(&resp)?);
-        return Ok(());
+    let history = History::next_to(config_path);
+    let mut waiting = false;
+    let now = scheduler::now(cfg.app.tz());
+    for date in dates {
+        let label = format!("{} {}", day, date);
+        let opens_at = scheduler::booking_opens_at(date, window, now.timezone());
+        if opens_at > now {
+            println!(
+                "{}: not yet open (bookings open {})",
+                label,
+                opens_at.format("%Y-%m-%d %H:%M %Z")
+            );
+            continue;
+        }
+
+        let slot_ids = find_slot_ids(&mut nubapp, verbose, &u, slot_cfg, date).await?;
+        if slot_ids.is_empty() {
+            println!("{}: no slot found at {}", label, slot_cfg.time);
+            continue;
+        }
+        if debug {
+            println!(
+                "[DRY RUN] Would book {}{} for {} (slot ID: {})",
+                label,
+                guest_suffix(slot_cfg.guests),
+                u.name,
+                slot_ids.join(", falling back to ")
+            );
+            continue;
+        }
+        waiting |= book_preferred(
+            &mut nubapp,
+            verbose,
+            &u,
+            &label,
+            &day,
+            date,
+            &slot_ids,
+            slot_cfg.guests,
+            &cfg.app,
+            &history,
+        )
+        .await?;
+    }
+    if waiting {
+        poll_waiting_lists(&cfg, std::slice::from_ref(&u)).await;
+    }
+    Ok(())
+}
+
+/// Log in and show the gym's IDs. With `output`, also write them as an
+/// `[app]` section to that file (`-` for stdout, the IDs then going to
+/// stderr); an existing file is only replaced with `force`.
+pub async fn run_discover(
+    api: &ApiConfig,
+    application_id: &str,
+    username: &str,
+    password: &str,
+    verbose: bool,
+    output: Option<&Path>,
+    force: bool,
+) -> Result<()> {
+    let to_file = output.filter(|p| *p != Path::new("-"));
+    if let Some(path) = to_file {
+        if path.exists() && !force {
+            bail!(
+                "{} already exists — use --force to overwrite it",
+                path.display()
+            );
+        }
     }
+    let mut nubapp = NubappClient::new(application_id, "0")?.with_api(api)?;
+    // With the section on stdout, everything else goes to stderr
+    let mut report: Box<dyn Write> = if output.is_some() && to_file.is_none() {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::io::stdout())
+    };
 
-    let data = resp.get("data");
+    writeln!(report, "Logging in as {}...", username)?;
+    let login_resp = nubapp.login(username, password).await?;
 
-    match data
-        .and_then(|d| d.get("bookings"))
-        .and_then(|v| v.as_array())
-    {
-        Some(arr) if !arr.is_empty() => {
-            println!("Bookings for {}:\n", login);
-            for b in arr {
-                print_booking(b);
+    let jwt_app = print_account_info(&mut report, &login_resp, verbose)?;
+    let categories = print_categories(&mut report, &nubapp, verbose).await?;
+
+    let section = discovered_app_toml(jwt_app.as_deref().unwrap_or(application_id), &categories);
+    match (output, to_file) {
+        (_, Some(path)) => {
+            std::fs::write(path, &section)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("\nWrote the [app] section to {}.", path.display());
+        }
+        (Some(_), None) => print!("{section}"),
+        (None, None) => println!("\nUse these values in your config.toml under [app]."),
+    }
+    Ok(())
+}
+
+/// A ready-to-paste `[app]` section for `application_id`. The categories are
+/// listed as commented-out `category_activity_id` lines to pick from, unless
+/// there is only one.
+fn discovered_app_toml(application_id: &str, categories: &[(String, String)]) -> String {
+    let quote = |s: &str| toml::Value::String(s.to_string()).to_string();
+    let mut out = format!("[app]\napplication_id = {}\n", quote(application_id));
+    match categories {
+        [] => out.push_str("# No activity categories found — fill in category_activity_id\n"),
+        [(id, name)] => out.push_str(&format!(
+            "category_activity_id = {}  # {}\n",
+            quote(id),
+            one_line(name)
+        )),
+        _ => {
+            out.push_str("# Uncomment the activity category to book:\n");
+            for (id, name) in categories {
+                out.push_str(&format!(
+                    "# category_activity_id = {}  # {}\n",
+                    quote(id),
+                    one_line(name)
+                ));
             }
         }
-        _ => println!("No upcoming bookings for {}.", login),
     }
+    out
+}
 
-    if let Some(arr) = data
-        .and_then(|d| d.get("in_waiting_list"))
-        .and_then(|v| v.as_array())
-    {
-        if !arr.is_empty() {
-            // Collect unique dates to fetch slot capacity
-            let mut dates: Vec<String> = arr
-                .iter()
-                .filter_map(|b| {
-                    b.get("start_timestamp")
-                        .and_then(|v| v.as_str())
-                        .and_then(|s| s.get(..10))
-                        .map(|s| s.to_string())
-                })
-                .collect();
-            dates.sort();
-            dates.dedup();
-
-            // Fetch slots for each date and build a lookup by id_activity_calendar
-            let mut capacity_map: std::collections::HashMap<String, (u64, u64)> =
-                std::collections::HashMap::new();
-            for date in &dates {
-                // Convert YYYY-MM-DD to DD-MM-YYYY for the API
-                if let Some(api_date) = date
-                    .get(8..10)
-                    .zip(date.get(5..7))
-                    .zip(date.get(0..4))
-                    .map(|((d, m), y)| format!("{}-{}-{}", d, m, y))
-                {
-                    if let Ok(slots) = nubapp.get_slots(&api_date).await {
-                        for slot in &slots {
-                            let id = slot.id_activity_calendar.to_string();
-                            let id = id.trim_matches('"').to_string();
-                            if let (Some(ins), Some(cap)) = (slot.n_inscribed, slot.n_capacity) {
-                                capacity_map.insert(id, (ins as u64, cap as u64));
-                            }
-                        }
-                    }
-                }
+fn one_line(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Run discovery for every configured user, flagging accounts whose JWT
+/// `id_application` differs from the configured one (`app_override`, or
+/// the user's gym).
+pub async fn run_discover_all(
+    cfg: &Config,
+    app_override: Option<&str>,
+    verbose: bool,
+) -> Result<()> {
+    let mut mismatched: Vec<String> = Vec::new();
+    let mut failed: Vec<String> = Vec::new();
+
+    for (i, user) in cfg.users.iter().enumerate() {
+        println!("\n##### {} ({}) #####", user.name, user.login);
+
+        let gym = cfg.gym(user.gym.as_deref())?;
+        let application_id = app_override.unwrap_or(&gym.application_id);
+        let mut nubapp = NubappClient::new(application_id, "0")?.with_api(&cfg.api)?;
+        let login_resp = match nubapp.login(&user.login, &user.password).await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Login failed for {}: {:#}", user.name, e);
+                failed.push(user.name.clone());
+                continue;
             }
+        };
 
-            println!("\nWaiting list:\n");
-            for b in arr {
-                print_waiting_list_entry(b);
-                // Look up capacity by id_activity_calendar
-                if let Some(id) = b.get("id_activity_calendar") {
-                    let id_str = id.to_string();
-                    let id_str = id_str.trim_matches('"');
-                    if let Some(&(ins, cap)) = capacity_map.get(id_str) {
-                        let free = cap.saturating_sub(ins);
-                        println!("    ^ {}/{} booked, {} free", ins, cap, free);
-                    }
-                }
+        match print_account_info(&mut std::io::stdout(), &login_resp, verbose)? {
+            Some(jwt_app) if jwt_app != application_id => {
+                println!(
+                    "\n  !! MISMATCH: token id_application {} differs from configured {}",
+                    jwt_app, application_id
+                );
+                mismatched.push(user.name.clone());
             }
+            Some(_) => {}
+            None => println!("\n  !! Could not read id_application from token"),
         }
+        print_categories(&mut std::io::stdout(), &nubapp, verbose).await?;
+
+        if i < cfg.users.len() - 1 {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+    }
+
+    println!("\n=== Summary ===");
+    println!("  users checked:  {}", cfg.users.len());
+    if mismatched.is_empty() {
+        println!("  application_id: all match their gym");
+    } else {
+        println!("  application_id mismatch: {}", mismatched.join(", "));
+    }
+    if !failed.is_empty() {
+        println!("  login failed:   {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+/// The decoded JWT claims of a login response's token.
+fn token_payload(login_resp: &serde_json::Value) -> Option<serde_json::Value> {
+    login_resp
+        .get("token")
+        .and_then(|t| t.as_str())
+        .and_then(decode_jwt_payload)
+}
+
+/// The gym the login token was issued for (its `id_application` claim).
+fn token_application_id(payload: &serde_json::Value) -> Option<String> {
+    payload.get("id_application").map(id_string)
+}
+
+/// Print account details from the login token. Returns the token's `id_application`.
+fn print_account_info(
+    out: &mut dyn Write,
+    login_resp: &serde_json::Value,
+    verbose: bool,
+) -> Result<Option<String>> {
+    let Some(payload) = token_payload(login_resp) else {
+        return Ok(None);
+    };
+
+    writeln!(out, "\n=== Account Information ===")?;
+    if let Some(id) = payload.get("id_application") {
+        writeln!(out, "  application_id: {}", id)?;
+    }
+    if let Some(id) = payload.get("id_user") {
+        writeln!(out, "  user_id:        {}", id)?;
+    }
+    if let Some(name) = payload.get("username") {
+        writeln!(out, "  username:       {}", name.as_str().unwrap_or("?"))?;
+    }
+    if verbose {
+        writeln!(
+            out,
+            "\n  Full JWT payload:\n  {}",
+            serde_json::to_string_pretty(&payload)?
+        )?;
     }
 
+    Ok(token_application_id(&payload))
+}
+
+/// Log in as `user` (a configured name or login; the first user by
+/// default) and print every claim of the session token, with its expiry.
+pub async fn run_token(config_path: &Path, user: Option<&str>) -> Result<()> {
+    let cfg = config::load_config(config_path)?;
+    let u = match user {
+        Some(name) => cfg
+            .users
+            .iter()
+            .find(|u| u.name.eq_ignore_ascii_case(name) || u.login.eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow::anyhow!("No user '{}' in {}", name, config_path.display()))?,
+        None => cfg
+            .users
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("No users in {}", config_path.display()))?,
+    };
+    let mut nubapp = NubappClient::from_config(&cfg, u.gym.as_deref())?;
+    println!("Logging in as {}...", u.login);
+    nubapp.login(&u.login, &u.password).await?;
+    let Some(payload) = nubapp.token().and_then(decode_jwt_payload) else {
+        bail!("The login token of {} is not a readable JWT", u.name);
+    };
+    println!(
+        "\n=== Token claims ===\n{}",
+        token_report(&payload, scheduler::now(cfg.app.tz()))
+    );
     Ok(())
 }
+
+/// A token's claims, one per line, then when it expires as seen from `now`,
+/// e.g. `  expires:        2024-01-15 18:00:00 CET (expires in 59m)`.
+fn token_report(payload: &serde_json::Value, now: chrono::DateTime<chrono_tz::Tz>) -> String {
+    let mut lines: Vec<String> = payload
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(claim, value)| {
+            let value = value
+                .as_str()
+                .map_or_else(|| value.to_string(), str::to_string);
+            format!("  {:<15} {}", format!("{claim}:"), value)
+        })
+        .collect();
+    let exp = payload.get("exp").and_then(|e| e.as_i64());
+    let expires = match exp.and_then(|exp| chrono::DateTime::from_timestamp(exp, 0)) {
+        Some(at) => {
+            let at = at.with_timezone(&now.timezone());
+            format!(
+                "{} ({})",
+                at.format("%Y-%m-%d %H:%M:%S %Z"),
+                expiry(at - now)
+            )
+        }
+        None => "no exp claim".to_string(),
+    };
+    lines.push(format!("  {:<15} {}", "expires:", expires));
+    lines.join("\n")
+}
+
+/// "expires in 2h 13m", or "expired 5m ago" once `left` is negative.
+fn expiry(left: chrono::Duration) -> String {
+    let span = |d: chrono::Duration| match (d.num_days(), d.num_hours() % 24, d.num_minutes() % 60)
+    {
+        (0, 0, 0) => format!("{}s", d.num_seconds()),
+        (0, 0, m) => format!("{m}m"),
+        (0, h, m) => format!("{h}h {m}m"),
+        (d, h, _) => format!("{d}d {h}h"),
+    };
+    if left < chrono::Duration::zero() {
+        format!("expired {} ago", span(-left))
+    } else {
+        format!("expires in {}", span(left))
+    }
+}
+
+/// Tally of `doctor` checks, each printed as it completes.
+#[derive(Default)]
+struct DoctorReport {
+    failures: usize,
+}
+
+impl DoctorReport {
+    fn check(&mut self, ok: bool, what: &str) {
+        println!("  {} {}", if ok { "✓" } else { "✗" }, what);
+        if !ok {
+            self.failures += 1;
+        }
+    }
+}
+
+/// Verify the setup end to end: the config loads, and for every user the
+/// login works, the token belongs to the configured gym, and the configured
+/// category lists slots on the user's next booking day. Fails if any check does.
+pub async fn run_doctor(config_path: &Path) -> Result<()> {
+    let mut report = DoctorReport::default();
+    let cfg = match config::load_config(config_path) {
+        Ok(cfg) => {
+            report.check(true, &format!("config {} is valid", config_path.display()));
+            cfg
+        }
+        Err(e) => {
+            report.check(false, &format!("{e:#}"));
+            bail!("config check failed");
+        }
+    };
+    if cfg.users.is_empty() {
+        report.check(false, "no [[users]] configured");
+    }
+
+    let skew = match NubappClient::from_config(&cfg, None) {
+        Ok(client) => client.clock_skew().await,
+        Err(e) => Err(e),
+    };
+    match skew {
+        Ok(skew) => report.check(
+            skew.abs() <= chrono::Duration::seconds(cfg.app.clock_skew_warn_secs as i64),
+            &format!(
+                "clock is {} from the gym server's (warning above {}s)",
+                scheduler::format_offset(-skew),
+                cfg.app.clock_skew_warn_secs
+            ),
+        ),
+        Err(e) => report.check(false, &format!("gym server clock: {e:#}")),
+    }
+
+    for user in &cfg.users {
+        println!("\n{} ({})", user.name, user.login);
+        let gym = match cfg.gym(user.gym.as_deref()) {
+            Ok(gym) => gym,
+            Err(e) => {
+                report.check(false, &format!("{e:#}"));
+                continue;
+            }
+        };
+        let mut nubapp = NubappClient::for_gym(&cfg, &gym)?;
+        let login_resp = match nubapp.login(&user.login, &user.password).await {
+            Ok(r) => r,
+            Err(e) => {
+                report.check(false, &format!("login: {e:#}"));
+                continue;
+            }
+        };
+        report.check(true, "login");
+
+        match token_payload(&login_resp)
+            .as_ref()
+            .and_then(token_application_id)
+        {
+            Some(id) if id == gym.application_id => {
+                report.check(true, &format!("application_id {id} matches the token"))
+            }
+            Some(id) => report.check(
+                false,
+                &format!(
+                    "application_id {} differs from the token's {id}",
+                    gym.application_id
+                ),
+            ),
+            None => report.check(false, "could not read id_application from the login token"),
+        }
+
+        let categories = nubapp.get_categories().await.ok();
+        for category in gym.category_activity_id.all() {
+            let listed = categories
+                .as_ref()
+                .map(|resp| resp.get("data").unwrap_or(resp))
+                .and_then(|cats| cats.as_array())
+                .is_some_and(|arr| {
+                    arr.iter().any(|cat| {
+                        cat.get("id_category_activity")
+                            .or_else(|| cat.get("id"))
+                            .is_some_and(|v| id_string(v) == *category)
+                    })
+                });
+            report.check(
+                listed,
+                &format!("category_activity_id {category} is one of the gym's categories"),
+            );
+        }
+
+        // The user's next configured booking day, or tomorrow
+        let today = scheduler::now(cfg.app.tz()).date_naive();
+        let date = user
+            .slots
+            .iter()
+            .filter_map(|d| scheduler::slot_weekday(d))
+            .map(|wd| scheduler::next_weekday(today, wd))
+            .min()
+            .unwrap_or(today + chrono::Duration::days(1));
+        let api_date = date.format("%d-%m-%Y").to_string();
+        match nubapp.get_slots(&api_date).await {
+            Ok(slots) if !slots.is_empty() => report.check(
+                true,
+                &format!("{} slots listed on {}", slots.len(), api_date),
+            ),
+            Ok(_) => report.check(false, &format!("no slots listed on {api_date}")),
+            Err(e) => report.check(false, &format!("fetching slots for {api_date}: {e:#}")),
+        }
+    }
+
+    if report.failures > 0 {
+        bail!("{} check(s) failed", report.failures);
+    }
+    println!("\nAll checks passed.");
+    Ok(())
+}
+
+/// Print the gym's activity categories and return them as `(id, name)` pairs.
+async fn print_categories(
+    out: &mut dyn Write,
+    nubapp: &NubappClient,
+    verbose: bool,
+) -> Result<Vec<(String, String)>> {
+    let mut categories = Vec::new();
+    writeln!(out, "\n=== Activity Categories ===")?;
+    match nubapp.get_categories().await {
+        Ok(resp) => {
+            let cats = resp.get("data").unwrap_or(&resp);
+            match cats {
+                serde_json::Value::Array(arr) if !arr.is_empty() => {
+                    for cat in arr {
+                        let id = cat
+                            .get("id_category_activity")
+                            .or_else(|| cat.get("id"))
+                            .map(id_string)
+                            .unwrap_or_else(|| "?".into());
+                        let name = cat
+                            .get("name")
+                            .or_else(|| cat.get("title"))
+                            .and_then(|v| v.as_str())
+                            .unwrap_or("?");
+                        writeln!(out, "  [{}] {}", id, name)?;
+                        categories.push((id, name.to_string()));
+                    }
+                }
+                other => {
+                    if verbose {
+                        writeln!(
+                            out,
+                            "  Raw response:\n  {}",
+                            serde_json::to_string_pretty(other)?
+                        )?;
+                    } else {
+                        writeln!(
+                            out,
+                            "  Could not list categories. Re-run with -v for details."
+                        )?;
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            writeln!(out, "  Could not fetch categories: {}", e)?;
+        }
+    }
+    Ok(categories)
+}
+
+fn print_booking(b: &Booking) {
+    let start = if b.start.is_empty() { "?" } else { &b.start };
+    print!("  {} to {} — {}", start, b.end, b.activity());
+    if let (Some(s), Some(c)) = (b.n_inscribed, b.n_capacity) {
+        print!(" ({}/{})", s, c);
+    }
+    println!();
+}
+
+fn print_waiting_list_entry(entry: &WaitingEntry) {
+    let b = &entry.class;
+    let start = if b.start.is_empty() { "?" } else { &b.start };
+    print!("  {} to {} — {}", start, b.end, b.activity());
+    if let (Some(s), Some(c)) = (b.n_inscribed, b.n_capacity) {
+        let free = c.saturating_sub(s);
+        print!(" ({}/{}, {} free)", s, c, free);
+    }
+    if let Some(pos) = entry.position {
+        print!(" — #{} in queue", pos);
+    }
+    println!();
+}
+
+/// One row of `bookings --json` output. Field names are part of the CLI's
+/// stable output and deliberately independent of the API's.
+#[derive(Serialize)]
+struct BookingJson {
+    start: String,
+    end: String,
+    activity: String,
+    inscribed: Option<u32>,
+    capacity: Option<u32>,
+    on_waiting_list: bool,
+    /// Place in the waiting-list queue, when the gym reports it.
+    queue_position: Option<u32>,
+}
+
+impl BookingJson {
+    fn from_api(
+        b: &Booking,
+        capacity: Option<(u32, u32)>,
+        queue_position: Option<u32>,
+        on_waiting_list: bool,
+    ) -> Self {
+        let (inscribed, capacity) = match capacity {
+            Some((i, c)) => (Some(i), Some(c)),
+            None => (b.n_inscribed, b.n_capacity),
+        };
+        Self {
+            start: b.start.clone(),
+            end: b.end.clone(),
+            activity: b.name.as_deref().unwrap_or_default().trim().to_string(),
+            inscribed,
+            capacity,
+            on_waiting_list,
+            queue_position,
+        }
+    }
+}
+
+/// Fetch current slot capacity for waiting-list entries, keyed by `id_activity_calendar`.
+async fn waiting_list_capacity(
+    nubapp: &mut NubappClient,
+    entries: &[WaitingEntry],
+) -> HashMap<String, (u32, u32)> {
+    // Collect unique dates to fetch slot capacity
+    let mut dates: Vec<NaiveDate> = entries.iter().filter_map(|e| e.class.date()).collect();
+    dates.sort();
+    dates.dedup();
+
+    // Fetch slots for each date and build a lookup by id_activity_calendar
+    let mut capacity_map: HashMap<String, (u32, u32)> = HashMap::new();
+    for date in &dates {
+        if let Ok(slots) = nubapp.get_slots(&date.format("%d-%m-%Y").to_string()).await {
+            for slot in &slots {
+                let id = slot.calendar_id();
+                if let (Some(ins), Some(cap)) = (slot.n_inscribed, slot.n_capacity) {
+                    capacity_map.insert(id, (ins, cap));
+                }
+            }
+        }
+    }
+    capacity_map
+}
+
+pub async fn run_bookings(
+    verbose: bool,
+    json: bool,
+    limit: u32,
+    config_path: &Path,
+    user: &Option<String>,
+    password: &Option<String>,
+) -> Result<()> {
+    let cfg = config::load_config(config_path)?;
+    let configured = flag_user(&cfg, user);
+    let (login, pass) = resolve_credentials(user, password, configured)?;
+
+    let gym = configured.and_then(|u| u.gym.as_deref());
+    let mut nubapp = NubappClient::from_config(&cfg, gym)?;
+    nubapp.login(login, pass).await?;
+
+    let resp = nubapp.get_bookings_with_limit(limit).await?;
+
+    if verbose && !json {
+        println!("{}", serde_json::to_string_pretty(&resp)?);
+        return Ok(());
+    }
+
+    let BookingsResponse {
+        bookings,
+        waiting_list: waiting,
+    } = parse_bookings(&resp);
+    let capacity_map = if waiting.is_empty() {
+        HashMap::new()
+    } else {
+        waiting_list_capacity(&mut nubapp, &waiting).await
+    };
+
+    if json {
+        let rows: Vec<BookingJson> = bookings
+            .iter()
+            .map(|b| BookingJson::from_api(b, None, None, false))
+            .chain(waiting.iter().map(|e| {
+                let cap = e
+                    .class
+                    .calendar_id()
+                    .and_then(|id| capacity_map.get(&id).copied());
+                BookingJson::from_api(&e.class, cap, e.position, true)
+            }))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+
+    if bookings.is_empty() {
+        println!("No upcoming bookings for {}.", login);
+    } else {
+        println!("Bookings for {}:\n", login);
+        for b in &bookings {
+            print_booking(b);
+        }
+    }
+
+    if !waiting.is_empty() {
+        println!("\nWaiting list:\n");
+        for e in &waiting {
+            print_waiting_list_entry(e);
+            // Look up capacity by id_activity_calendar
+            if let Some(&(ins, cap)) = e.class.calendar_id().and_then(|id| capacity_map.get(&id)) {
+                let free = cap.saturating_sub(ins);
+                println!("    ^ {}/{} booked, {} free", ins, cap, free);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The bookings on days before `before`, or all of them. Bookings without a
+/// readable date are kept only when there is no cutoff.
+fn bookings_before(bookings: &[Booking], before: Option<NaiveDate>) -> Vec<&Booking> {
+    bookings
+        .iter()
+        .filter(|b| match before {
+            Some(cutoff) => b.date().is_some_and(|d| d < cutoff),
+            None => true,
+        })
+        .collect()
+}
+
+/// Pick the booking (or waiting-list entry, named by `kind` in errors) to act
+/// on: by slot ID, or the earliest upcoming one on `day`.
+fn resolve_booking<'a, T: AsRef<Booking>>(
+    bookings: &'a [T],
+    day: Option<&str>,
+    slot_id: Option<&str>,
+    kind: &str,
+) -> Result<&'a T> {
+    if let Some(id) = slot_id {
+        return bookings
+            .iter()
+            .find(|b| b.as_ref().calendar_id().as_deref() == Some(id))
+            .ok_or_else(|| anyhow::anyhow!("No {} found for slot ID {}", kind, id));
+    }
+
+    let day = day.unwrap_or_default();
+    let weekday =
+        scheduler::parse_weekday(day).ok_or_else(|| anyhow::anyhow!("Unknown day '{}'", day))?;
+    let on_day: Vec<(chrono::NaiveDate, &T)> = bookings
+        .iter()
+        .filter_map(|b| b.as_ref().date().map(|d| (d, b)))
+        .filter(|(d, _)| d.weekday() == weekday)
+        .collect();
+    let Some(earliest) = on_day.iter().map(|(d, _)| *d).min() else {
+        bail!("No upcoming {} on {}", kind, day);
+    };
+    let matches: Vec<&T> = on_day
+        .into_iter()
+        .filter(|(d, _)| *d == earliest)
+        .map(|(_, b)| b)
+        .collect();
+    if matches.len() > 1 {
+        println!("Several matches on {}:", earliest);
+        for b in matches.iter().map(|b| b.as_ref()) {
+            print_booking(b);
+            println!("    slot ID: {}", b.calendar_id().unwrap_or_default());
+        }
+        bail!("Ambiguous {} — re-run with --slot-id", kind);
+    }
+    Ok(matches[0])
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn run_cancel(
+    verbose: bool,
+    config_path: &Path,
+    user: &Option<String>,
+    password: &Option<String>,
+    day: Option<&str>,
+    slot_id: Option<&str>,
+    yes: bool,
+    debug: bool,
+) -> Result<()> {
+    let cfg = config::load_config(config_path)?;
+    let configured = flag_user(&cfg, user);
+    let (login, pass) = resolve_credentials(user, password, configured)?;
+
+    let gym = configured.and_then(|u| u.gym.as_deref());
+    let mut nubapp = NubappClient::from_config(&cfg, gym)?;
+    nubapp.login(login, pass).await?;
+
+    let bookings = nubapp.get_bookings_typed().await?.bookings;
+
+    let booking = resolve_booking(&bookings, day, slot_id, "booking")?;
+    let id = booking
+        .calendar_id()
+        .ok_or_else(|| anyhow::anyhow!("Booking has no id_activity_calendar"))?;
+
+    println!("Booking to cancel for {}:\n", login);
+    print_booking(booking);
+    println!("    slot ID: {}", id);
+
+    if debug {
+        println!("\n[DRY RUN] Would cancel slot {} for {}", id, login);
+        return Ok(());
+    }
+    if !yes {
+        bail!("Not cancelled — re-run with --yes to confirm");
+    }
+
+    let resp = nubapp.cancel(&id).await?;
+    if verbose {
+        println!("Cancel response: {}", serde_json::to_string_pretty(&resp)?);
+    }
+    if is_success(&resp) {
+        println!("\nCancelled slot {} for {}", id, login);
+    } else {
+        let msg = resp
+            .get("message")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown error");
+        bail!("Failed to cancel slot {} for {}: {}", id, login, msg);
+    }
+    Ok(())
+}
+
+/// Cancel every upcoming booking of the user, or those before `before`,
+/// and report how many went through.
+pub async fn run_cancel_all(
+    verbose: bool,
+    config_path: &Path,
+    user: &Option<String>,
+    password: &Option<String>,
+    before: Option<&str>,
+    yes: bool,
+    debug: bool,
+) -> Result<()> {
+    let cfg = config::load_config(config_path)?;
+    let configured = flag_user(&cfg, user);
+    let (login, pass) = resolve_credentials(user, password, configured)?;
+    let today = scheduler::now(cfg.app.tz()).date_naive();
+    let before = before
+        .map(|s| {
+            scheduler::parse_date(s, today).ok_or_else(|| {
+                anyhow::anyhow!("Invalid date '{}', expected DD-MM-YYYY or YYYY-MM-DD", s)
+            })
+        })
+        .transpose()?;
+
+    let gym = configured.and_then(|u| u.gym.as_deref());
+    let mut nubapp = NubappClient::from_config(&cfg, gym)?;
+    nubapp.login(login, pass).await?;
+
+    let bookings = nubapp.get_bookings_typed().await?.bookings;
+    let to_cancel = bookings_before(&bookings, before);
+    let period = before.map_or(String::new(), |d| format!(" before {}", d));
+    if to_cancel.is_empty() {
+        println!("No upcoming bookings{} for {}", period, login);
+        return Ok(());
+    }
+
+    println!("Bookings to cancel for {}{}:\n", login, period);
+    for b in &to_cancel {
+        print_booking(b);
+        println!("    slot ID: {}", b.calendar_id().unwrap_or_default());
+    }
+    if debug {
+        println!(
+            "\n[DRY RUN] Would cancel {} bookings for {}",
+            to_cancel.len(),
+            login
+        );
+        return Ok(());
+    }
+    if !yes {
+        bail!("Nothing cancelled — re-run with --yes to confirm");
+    }
+
+    println!();
+    let mut failed = 0;
+    for b in &to_cancel {
+        let Some(id) = b.calendar_id() else {
+            warn!("Skipping a booking without id_activity_calendar");
+            failed += 1;
+            continue;
+        };
+        match nubapp.cancel(&id).await {
+            Ok(resp) => {
+                if verbose {
+                    println!("Cancel response: {}", serde_json::to_string_pretty(&resp)?);
+                }
+                if is_success(&resp) {
+                    println!("Cancelled slot {}", id);
+                    continue;
+                }
+                let msg = resp
+                    .get("message")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("unknown error");
+                println!("Failed to cancel slot {}: {}", id, msg);
+                failed += 1;
+            }
+            Err(e) => {
+                println!("Failed to cancel slot {}: {:#}", id, e);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "\nCancelled {} of {} bookings for {}",
+        to_cancel.len() - failed,
+        to_cancel.len(),
+        login
+    );
+    if failed > 0 {
+        bail!("{} cancellations failed", failed);
+    }
+    Ok(())
+}
+
+/// Leave one of the user's waiting lists, picked like `run_cancel` picks a booking.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_leave_waitlist(
+    verbose: bool,
+    config_path: &Path,
+    user: &Option<String>,
+    password: &Option<String>,
+    day: Option<&str>,
+    slot_id: Option<&str>,
+    yes: bool,
+    debug: bool,
+) -> Result<()> {
+    let cfg = config::load_config(config_path)?;
+    let configured = flag_user(&cfg, user);
+    let (login, pass) = resolve_credentials(user, password, configured)?;
+
+    let gym = configured.and_then(|u| u.gym.as_deref());
+    let mut nubapp = NubappClient::from_config(&cfg, gym)?;
+    nubapp.login(login, pass).await?;
+
+    let waiting = nubapp.get_bookings_typed().await?.waiting_list;
+    let entry = resolve_booking(&waiting, day, slot_id, "waiting-list entry")?;
+    let id = entry
+        .class
+        .calendar_id()
+        .ok_or_else(|| anyhow::anyhow!("Waiting-list entry has no id_activity_calendar"))?;
+
+    println!("Waiting list to leave for {}:\n", login);
+    print_waiting_list_entry(entry);
+    println!("    slot ID: {}", id);
+
+    if debug {
+        println!(
+            "\n[DRY RUN] Would leave the waiting list of slot {} for {}",
+            id, login
+        );
+        return Ok(());
+    }
+    if !yes {
+        bail!("Still on the waiting list — re-run with --yes to confirm");
+    }
+
+    let resp = nubapp.leave_waiting_list(&id).await?;
+    if verbose {
+        println!("Leave response: {}", serde_json::to_string_pretty(&resp)?);
+    }
+    if is_success(&resp) {
+        println!("\nLeft the waiting list of slot {} for {}", id, login);
+    } else {
+        let msg = resp
+            .get("message")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown error");
+        bail!(
+            "Failed to leave the waiting list of slot {} for {}: {}",
+            id,
+            login,
+            msg
+        );
+    }
+    Ok(())
+}
+
+/// The slot to switch a booking starting at `start` to: same start, an
+/// activity matching `to_activity`, not the booked slot itself, and with a
+/// free place. Errors explain why no switch is possible.
+fn switch_target(slots: &[Slot], start: &str, to_activity: &str, current_id: &str) -> Result<Slot> {
+    let others: Vec<Slot> = slots
+        .iter()
+        .filter(|s| s.start.trim() == start && s.calendar_id() != current_id)
+        .cloned()
+        .collect();
+    let filter = SlotFilter {
+        activity: Some(to_activity),
+        ..Default::default()
+    };
+    let Some(slot) = NubappClient::find_slot(&others, start, &filter) else {
+        bail!("No other '{}' slot starts at {}", to_activity, start);
+    };
+    match (slot.n_inscribed, slot.n_capacity) {
+        (Some(ins), Some(cap)) if ins < cap => Ok(slot.clone()),
+        (Some(ins), Some(cap)) => bail!(
+            "{} at {} is full ({}/{})",
+            slot.name.as_deref().unwrap_or("?").trim(),
+            start,
+            ins,
+            cap
+        ),
+        _ => bail!(
+            "Can't confirm free places for slot {} — not switching",
+            slot.calendar_id()
+        ),
+    }
+}
+
+/// Move a booking to another activity at the same time: the new slot is
+/// checked for free places before the old booking is cancelled, and if
+/// booking it then fails the old slot is booked again.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_switch(
+    verbose: bool,
+    config_path: &Path,
+    user: &Option<String>,
+    password: &Option<String>,
+    day: &str,
+    to_activity: &str,
+    yes: bool,
+    debug: bool,
+) -> Result<()> {
+    let cfg = config::load_config(config_path)?;
+    let configured = flag_user(&cfg, user);
+    let (login, pass) = resolve_credentials(user, password, configured)?;
+
+    let gym = configured.and_then(|u| u.gym.as_deref());
+    let mut nubapp = NubappClient::from_config(&cfg, gym)?;
+    nubapp.login(login, pass).await?;
+
+    let bookings = nubapp.get_bookings_typed().await?.bookings;
+    let booking = resolve_booking(&bookings, Some(day), None, "booking")?;
+    let current_id = booking
+        .calendar_id()
+        .ok_or_else(|| anyhow::anyhow!("Booking has no id_activity_calendar"))?;
+    let start = booking.start.as_str();
+    if start.is_empty() {
+        bail!("Booking has no start time");
+    }
+
+    // YYYY-MM-DD → DD-MM-YYYY for the API
+    let api_date = booking
+        .date()
+        .map(|d| d.format("%d-%m-%Y").to_string())
+        .ok_or_else(|| anyhow::anyhow!("Unexpected booking start '{}'", start))?;
+    let slots = nubapp.get_slots(&api_date).await?;
+    let target = switch_target(&slots, start, to_activity, &current_id)?;
+    let target_id = target.calendar_id();
+
+    println!("Current booking for {}:\n", login);
+    print_booking(booking);
+    println!(
+        "\nSwitch to: {} at {} ({}/{}), slot ID {}",
+        target.name.as_deref().unwrap_or("?").trim(),
+        target.start,
+        target.n_inscribed.unwrap_or_default(),
+        target.n_capacity.unwrap_or_default(),
+        target_id
+    );
+
+    if debug {
+        println!(
+            "\n[DRY RUN] Would cancel slot {} and book slot {} for {}",
+            current_id, target_id, login
+        );
+        return Ok(());
+    }
+    if !yes {
+        bail!("Not switched — re-run with --yes to confirm");
+    }
+
+    let resp = nubapp.cancel(&current_id).await?;
+    if verbose {
+        println!("Cancel response: {}", serde_json::to_string_pretty(&resp)?);
+    }
+    if !is_success(&resp) {
+        let msg = resp
+            .get("message")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown error");
+        bail!(
+            "Failed to cancel slot {}: {} — nothing changed",
+            current_id,
+            msg
+        );
+    }
+
+    let booked = nubapp.book(&target_id, None).await;
+    if let Ok(resp) = &booked {
+        if verbose {
+            println!("Booking response: {}", serde_json::to_string_pretty(resp)?);
+        }
+        if is_success(resp) {
+            println!("\nSwitched {} to slot {}", login, target_id);
+            return Ok(());
+        }
+    }
+    let reason = match booked {
+        Ok(resp) => resp
+            .get("message")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown error")
+            .to_string(),
+        Err(e) => format!("{e:#}"),
+    };
+
+    // The new slot filled up in between — get the old place back
+    warn!(
+        "Booking slot {} failed ({}), rebooking slot {}",
+        target_id, reason, current_id
+    );
+    match nubapp.book(&current_id, None).await {
+        Ok(resp) if is_success(&resp) => {
+            bail!(
+                "Failed to book slot {}: {} — kept the original booking",
+                target_id,
+                reason
+            )
+        }
+        _ => bail!(
+            "Failed to book slot {}: {} — and could not rebook the original slot {}",
+            target_id,
+            reason,
+            current_id
+        ),
+    }
+}
+
+/// One row of `list-slots --json` output.
+#[derive(Serialize)]
+struct SlotJson<'a> {
+    start: &'a str,
+    end: &'a str,
+    activity: &'a str,
+    coach: Option<&'a str>,
+    inscribed: Option<u32>,
+    capacity: Option<u32>,
+    id_activity_calendar: String,
+    id_activity: Option<String>,
+}
+
+pub async fn run_list_slots(
+    json: bool,
+    config_path: &Path,
+    user: &Option<String>,
+    password: &Option<String>,
+    date: &str,
+    min_free: Option<u32>,
+) -> Result<()> {
+    let cfg = config::load_config(config_path)?;
+    let configured = flag_user(&cfg, user);
+    let (login, pass) = resolve_credentials(user, password, configured)?;
+
+    let today = scheduler::now(cfg.app.tz()).date_naive();
+    let Some(target_date) = scheduler::parse_date(date, today) else {
+        bail!(
+            "Invalid date '{}', expected DD-MM-YYYY, today, tomorrow or a day name",
+            date
+        );
+    };
+    let date_str = target_date.format("%d-%m-%Y").to_string();
+
+    let gym = configured.and_then(|u| u.gym.as_deref());
+    let mut nubapp = NubappClient::from_config(&cfg, gym)?;
+    nubapp.login(login, pass).await?;
+    let mut slots = nubapp.get_slots(&date_str).await?;
+    if let Some(min) = min_free {
+        slots.retain(|s| s.has_free_spots(min));
+    }
+
+    if json {
+        let rows: Vec<SlotJson> = slots
+            .iter()
+            .map(|s| SlotJson {
+                start: &s.start,
+                end: &s.end,
+                activity: s.name.as_deref().unwrap_or_default().trim(),
+                coach: s.coach.as_deref(),
+                inscribed: s.n_inscribed,
+                capacity: s.n_capacity,
+                id_activity_calendar: s.calendar_id(),
+                id_activity: s.activity_id(),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+
+    if slots.is_empty() {
+        let filter = min_free.map_or_else(String::new, |min| format!(" with {min}+ free spots"));
+        println!(
+            "No slots{} on {} ({}).",
+            filter,
+            target_date.format("%A"),
+            date_str
+        );
+        return Ok(());
+    }
+
+    println!("Slots on {} ({}):\n", target_date.format("%A"), date_str);
+    println!(
+        "  {:<20} {:<20} {:<25} {:<20} {:>9}  ID",
+        "START", "END", "ACTIVITY", "COACH", "CAPACITY"
+    );
+    for s in &slots {
+        let capacity = match (s.n_inscribed, s.n_capacity) {
+            (Some(i), Some(c)) => format!("{i}/{c}"),
+            _ => String::new(),
+        };
+        println!(
+            "  {:<20} {:<20} {:<25} {:<20} {:>9}  {}",
+            s.start,
+            s.end,
+            s.name.as_deref().unwrap_or("?").trim(),
+            s.coach.as_deref().unwrap_or_default(),
+            capacity,
+            s.calendar_id(),
+        );
+    }
+    Ok(())
+}
+
+/// Print the user's upcoming bookings as an iCalendar file.
+pub async fn run_export_ics(
+    config_path: &Path,
+    user: &Option<String>,
+    password: &Option<String>,
+) -> Result<()> {
+    let cfg = config::load_config(config_path)?;
+    let configured = flag_user(&cfg, user);
+    let (login, pass) = resolve_credentials(user, password, configured)?;
+
+    let gym = configured.and_then(|u| u.gym.as_deref());
+    let mut nubapp = NubappClient::from_config(&cfg, gym)?;
+    nubapp.login(login, pass).await?;
+
+    let bookings = nubapp.get_bookings_typed().await?.bookings;
+    print!(
+        "{}",
+        ics::bookings_to_ics(&bookings, scheduler::now(cfg.app.tz()))
+    );
+    Ok(())
+}
+
+/// Print the last `limit` booking attempts from the history next to the config.
+pub fn run_history(config_path: &Path, limit: usize) -> Result<()> {
+    let history = History::next_to(config_path);
+    let entries = history.last(limit)?;
+    if entries.is_empty() {
+        println!("No booking history yet in {}", history.path().display());
+        return Ok(());
+    }
+    for e in &entries {
+        println!("{}", history_line(e));
+    }
+    Ok(())
+}
+
+/// Print what the schedulers would book next for every user and day, and
+/// when, straight from the config.
+pub fn run_plan(config_path: &Path) -> Result<()> {
+    let cfg = config::load_config(config_path)?;
+    let now = scheduler::now(cfg.app.tz());
+    let planned = slot_scheduler::plan(&cfg, now);
+    if planned.is_empty() {
+        println!(
+            "No user has any days configured in {}",
+            config_path.display()
+        );
+        return Ok(());
+    }
+    println!("Booking plan as of {}:\n", now.format("%Y-%m-%d %H:%M %Z"));
+    for p in &planned {
+        println!("{}", plan_line(p, now));
+    }
+    Ok(())
+}
+
+/// One planned booking as a table row, e.g.
+/// `alice        tuesday   18:00     WOD             2024-01-16  books at 2024-01-09 18:01:00 CET`.
+fn plan_line(p: &PlannedBooking, now: chrono::DateTime<chrono_tz::Tz>) -> String {
+    let window = match p.window {
+        Ok((date, books_at)) => format!(
+            "{}  {} {}",
+            date.format("%Y-%m-%d"),
+            if books_at > now {
+                "books at"
+            } else {
+                "open since"
+            },
+            books_at.format("%Y-%m-%d %H:%M:%S %Z")
+        ),
+        Err(reason) => reason.to_string(),
+    };
+    format!(
+        "{:<12} {:<9} {:<9} {:<15} {}",
+        p.user, p.day, p.time, p.activity, window
+    )
+}
+
+/// One history entry as a table row, e.g.
+/// `2024-01-09 18:00:01  alice  tuesday 2024-01-16  booked  (slot 42)`.
+fn history_line(e: &HistoryEntry) -> String {
+    let when = chrono::DateTime::parse_from_rfc3339(&e.timestamp)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|_| e.timestamp.clone());
+    let mut line = format!(
+        "{}  {:<12} {:<9} {}  {:<18}",
+        when, e.user, e.day, e.target_date, e.outcome
+    );
+    if let Some(id) = &e.slot_id {
+        line.push_str(&format!(" (slot {id})"));
+    }
+    if let Some(ms) = e.latency_ms {
+        line.push_str(&format!(" {}", format_latency(ms)));
+    }
+    if let Some(msg) = &e.message {
+        line.push_str(&format!(" {msg}"));
+    }
+    line.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(id: u32, name: &str, inscribed: u32, capacity: u32) -> Slot {
+        Slot {
+            start: "2024-01-15 18:00:00".into(),
+            end: "2024-01-15 19:00:00".into(),
+            id_activity_calendar: serde_json::json!(id),
+            id_activity: serde_json::Value::Null,
+            name: Some(name.into()),
+            coach: None,
+            n_inscribed: Some(inscribed),
+            n_capacity: Some(capacity),
+            n_waiting_list: None,
+            n_capacity_waiting_list: None,
+        }
+    }
+
+    #[test]
+    fn test_user_flag_picks_that_users_gym() {
+        let cfg: Config = toml::from_str(
+            r#"
+            [app]
+            [[users]]
+            name = "Alice"
+            login = "alice@example.com"
+            password = "a"
+            slots = []
+            gym = "north"
+            [[users]]
+            name = "Bob"
+            login = "bob@example.com"
+            password = "b"
+            slots = []
+            gym = "south"
+            [slots]
+            "#,
+        )
+        .unwrap();
+        let flag = |s: &str| Some(s.to_string());
+
+        let u = cli_user(&cfg, &flag("BOB@example.com"), &None, "monday").unwrap();
+        assert_eq!(
+            (u.login.as_str(), u.password.as_str()),
+            ("bob@example.com", "b")
+        );
+        assert_eq!(u.gym.as_deref(), Some("south"));
+        // By name, logging in with their login
+        let u = cli_user(&cfg, &flag("bob"), &None, "monday").unwrap();
+        assert_eq!(
+            (u.login.as_str(), u.gym.as_deref()),
+            ("bob@example.com", Some("south"))
+        );
+        // Anyone else books at the first user's gym, with their own password
+        let u = cli_user(&cfg, &flag("carol@example.com"), &flag("c"), "monday").unwrap();
+        assert_eq!(
+            (u.password.as_str(), u.gym.as_deref()),
+            ("c", Some("north"))
+        );
+        assert_eq!(
+            flag_user(&cfg, &None).map(|u| u.name.as_str()),
+            Some("Alice")
+        );
+    }
+
+    #[test]
+    fn test_token_report_shows_claims_and_expiry() {
+        use chrono::TimeZone;
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 15, 17, 0, 0)
+            .unwrap();
+        let exp = (now + chrono::Duration::minutes(59)).timestamp();
+        let payload = serde_json::json!({"id_application": "36307036", "id_user": 42, "exp": exp});
+        let report = token_report(&payload, now);
+        assert!(
+            report.contains("  id_application: 36307036\n  id_user:        42"),
+            "{report}"
+        );
+        assert!(report.contains(&format!("  exp:            {exp}")));
+        assert!(
+            report.ends_with("expires:        2024-01-15 17:59:00 CET (expires in 59m)"),
+            "{report}"
+        );
+
+        let report = token_report(
+            &serde_json::json!({"exp": exp}),
+            now + chrono::Duration::hours(3),
+        );
+        assert!(report.ends_with("(expired 2h 1m ago)"), "{report}");
+        assert!(token_report(&serde_json::json!({}), now).ends_with("no exp claim"));
+    }
+
+    #[test]
+    fn test_discovered_app_toml_is_valid_config() {
+        let cats = [
+            ("2179".to_string(), "CrossFit WOD".to_string()),
+            ("2180".to_string(), "Open\nGym".to_string()),
+        ];
+        let out = discovered_app_toml("36307036", &cats);
+        assert!(
+            out.contains("# category_activity_id = \"2180\"  # Open Gym\n"),
+            "{out}"
+        );
+        let parsed: toml::Value = toml::from_str(&out).unwrap();
+        assert_eq!(parsed["app"]["application_id"].as_str(), Some("36307036"));
+        assert!(parsed["app"].get("category_activity_id").is_none());
+
+        let parsed: toml::Value = toml::from_str(&discovered_app_toml("1", &cats[..1])).unwrap();
+        assert_eq!(parsed["app"]["category_activity_id"].as_str(), Some("2179"));
+    }
+
+    #[test]
+    fn test_weekday_dates_include_both_ends() {
+        let date = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        // 2024-01-02 and 2024-01-30 are Tuesdays
+        assert_eq!(
+            weekday_dates(date(2), date(30), chrono::Weekday::Tue),
+            [date(2), date(9), date(16), date(23), date(30)]
+        );
+        assert_eq!(
+            weekday_dates(date(3), date(15), chrono::Weekday::Tue),
+            [date(9)]
+        );
+        assert!(weekday_dates(date(10), date(15), chrono::Weekday::Tue).is_empty());
+    }
+
+    #[test]
+    fn test_bookings_before_cutoff() {
+        let bookings = parse_bookings(&serde_json::json!({"data": {"bookings": [
+            {"id_activity_calendar": 1, "start_timestamp": "2024-01-15 18:00:00"},
+            {"id_activity_calendar": 2, "start_timestamp": "2024-01-22 07:00:00"},
+            {"id_activity_calendar": 3},
+        ]}}))
+        .bookings;
+        let ids = |before| -> Vec<String> {
+            bookings_before(&bookings, before)
+                .into_iter()
+                .filter_map(Booking::calendar_id)
+                .collect()
+        };
+        assert_eq!(ids(None), ["1", "2", "3"]);
+        assert_eq!(ids(NaiveDate::from_ymd_opt(2024, 1, 22)), ["1"]);
+        assert!(ids(NaiveDate::from_ymd_opt(2024, 1, 15)).is_empty());
+    }
+
+    #[test]
+    fn test_switch_target_requires_free_places() {
+        let start = "2024-01-15 18:00:00";
+        let slots = [slot(1, "WOD", 12, 12), slot(2, "Weightlifting", 5, 10)];
+        assert_eq!(
+            switch_target(&slots, start, "weight", "1")
+                .unwrap()
+                .calendar_id(),
+            "2"
+        );
+        // The booked slot itself is never a target
+        assert!(switch_target(&slots, start, "wod", "1").is_err());
+
+        let full = [slot(1, "WOD", 12, 12), slot(2, "Weightlifting", 10, 10)];
+        let err = switch_target(&full, start, "weight", "1").unwrap_err();
+        assert!(err.to_string().contains("full"), "{err}");
+    }
+}
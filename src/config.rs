@@ -1,13 +1,484 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono_tz::Tz;
+use reqwest::header::HeaderValue;
 
-use crate::models::Config;
+use crate::client;
+use crate::models::{AppConfig, Config};
+use crate::scheduler;
 
 pub fn load_config(path: &Path) -> Result<Config> {
+    let mut config: Config = read_merged(path)?
+        .try_into()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    resolve_passwords(&mut config).with_context(|| format!("Invalid config {}", path.display()))?;
+    normalize_days(&mut config).with_context(|| format!("Invalid config {}", path.display()))?;
+    validate(&config).with_context(|| format!("Invalid config {}", path.display()))?;
+    Ok(config)
+}
+
+/// Keep only the users named in `only` (by `name` or `login`, ignoring
+/// case), for `--only`. An empty list keeps everyone; a name matching no
+/// user, or a list of nothing but blanks (`--only ""`), is an error.
+pub fn only_users(config: &mut Config, only: &[String]) -> Result<()> {
+    if only.is_empty() {
+        return Ok(());
+    }
+    let names: Vec<&str> = only
+        .iter()
+        .map(|n| n.trim())
+        .filter(|n| !n.is_empty())
+        .collect();
+    if names.is_empty() {
+        bail!("--only: no user names given");
+    }
+    if let Some(missing) = names
+        .iter()
+        .find(|n| !config.users.iter().any(|u| u.is_named(n)))
+    {
+        bail!("--only: no user '{missing}' in the config");
+    }
+    config.users.retain(|u| names.iter().any(|n| u.is_named(n)));
+    Ok(())
+}
+
+/// Key of the main config listing more files to merge into it.
+const INCLUDE_KEY: &str = "include";
+
+/// The files named by `include` in the config at `path`, resolved against
+/// its directory, in the order they are merged.
+pub fn included_files(path: &Path) -> Result<Vec<PathBuf>> {
+    includes(path, &read_table(path)?)
+}
+
+/// The config at `path` as a TOML table, with its `include`d files merged
+/// in: tables key by key, `[[users]]` and other lists of tables appended,
+/// any other value replaced by the later file's. `[app]` may only be
+/// defined in one file, and included files can't include further files.
+pub fn read_merged(path: &Path) -> Result<toml::Table> {
+    let mut merged = read_table(path)?;
+    let files = includes(path, &merged)?;
+    merged.remove(INCLUDE_KEY);
+    let mut app_file = merged.contains_key("app").then(|| path.to_path_buf());
+    for file in files {
+        let table = read_table(&file)?;
+        if table.contains_key(INCLUDE_KEY) {
+            bail!(
+                "{}: include is only supported in the main config",
+                file.display()
+            );
+        }
+        if table.contains_key("app") {
+            if let Some(first) = &app_file {
+                bail!(
+                    "[app] is defined in both {} and {}",
+                    first.display(),
+                    file.display()
+                );
+            }
+            app_file = Some(file.clone());
+        }
+        merge(&mut merged, table);
+    }
+    Ok(merged)
+}
+
+fn read_table(path: &Path) -> Result<toml::Table> {
     let content =
         std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
-    let config: Config =
-        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?;
-    Ok(config)
+    toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn includes(path: &Path, table: &toml::Table) -> Result<Vec<PathBuf>> {
+    let Some(value) = table.get(INCLUDE_KEY) else {
+        return Ok(Vec::new());
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+    value
+        .as_array()
+        .and_then(|list| {
+            list.iter()
+                .map(|v| v.as_str().map(|file| dir.join(file)))
+                .collect()
+        })
+        .ok_or_else(|| anyhow!("{}: include must be a list of file paths", path.display()))
+}
+
+fn merge(into: &mut toml::Table, from: toml::Table) {
+    for (key, value) in from {
+        match (into.get_mut(&key), value) {
+            (Some(toml::Value::Table(a)), toml::Value::Table(b)) => merge(a, b),
+            (Some(toml::Value::Array(a)), toml::Value::Array(b))
+                if a.iter().chain(&b).all(toml::Value::is_table) =>
+            {
+                a.extend(b)
+            }
+            (_, value) => {
+                into.insert(key, value);
+            }
+        }
+    }
+}
+
+/// The zone named by `[app].timezone`, or the CET default.
+fn timezone(app: &AppConfig) -> Result<Tz> {
+    match &app.timezone {
+        Some(name) => name.parse().map_err(|_| {
+            anyhow!(
+                "[app]: unknown timezone '{name}' (expected an IANA name like \"Europe/Madrid\")"
+            )
+        }),
+        None => Ok(scheduler::CET),
+    }
+}
+
+/// Prefix marking a password as the name of an environment variable to read it from.
+const ENV_PREFIX: &str = "env:";
+
+/// Replace `password = "env:VAR"` references with the value of `VAR`, so the
+/// rest of the program only ever sees plain passwords.
+fn resolve_passwords(config: &mut Config) -> Result<()> {
+    for user in &mut config.users {
+        if let Some(var) = user.password.strip_prefix(ENV_PREFIX) {
+            user.password = std::env::var(var).with_context(|| {
+                format!(
+                    "user '{}': password environment variable {} is not set",
+                    user.name, var
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Rename `[slots]` days and users' `slots` to full English names, so that
+/// "tue" or "martes" finds `[slots.tuesday]` (and "martes-2"
+/// `[slots.tuesday-2]`). Unknown names are kept for the callers to warn about.
+fn normalize_days(config: &mut Config) -> Result<()> {
+    for (day, slot) in std::mem::take(&mut config.slots) {
+        let name = scheduler::canonical_slot_key(&day).unwrap_or_else(|| day.clone());
+        if config.slots.insert(name.clone(), slot).is_some() {
+            bail!("[slots]: {name} is configured twice (as '{day}')");
+        }
+    }
+    for user in &mut config.users {
+        for day in &mut user.slots {
+            if let Some(name) = scheduler::canonical_slot_key(day) {
+                *day = name;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate(config: &Config) -> Result<()> {
+    check_booking_window(
+        "[app]",
+        Some(config.app.booking_window_days),
+        config.app.booking_opens_at.as_deref(),
+    )?;
+    timezone(&config.app)?;
+    if config.app.slot_retry_interval_ms == 0 {
+        bail!("[app]: slot_retry_interval_ms must be positive");
+    }
+    if config.app.error_backoff_after > 0
+        && (config.app.error_backoff_minutes.is_empty()
+            || config.app.error_backoff_minutes.contains(&0))
+    {
+        bail!("[app]: error_backoff_minutes must list positive intervals");
+    }
+    if config.app.max_concurrent_bookings == 0 {
+        bail!("[app]: max_concurrent_bookings must be positive");
+    }
+    if config.logging.level.parse::<tracing::Level>().is_err() {
+        bail!("[logging]: unknown level '{}'", config.logging.level);
+    }
+    let api = &config.api;
+    if !api.base_url.starts_with("http://") && !api.base_url.starts_with("https://") {
+        bail!("[api]: base_url '{}' must be an http(s) URL", api.base_url);
+    }
+    if api.connect_timeout_secs == 0 || api.request_timeout_secs == 0 {
+        bail!("[api]: connect_timeout_secs and request_timeout_secs must be positive");
+    }
+    client::proxy(api)?;
+    let headers = [
+        ("origin", Some(&api.origin)),
+        ("referer", api.referer.as_ref()),
+        ("nubapp_origin", Some(&api.nubapp_origin)),
+        ("user_agent", Some(&api.user_agent)),
+    ];
+    for (key, value) in headers.into_iter().filter_map(|(k, v)| Some((k, v?))) {
+        if HeaderValue::from_str(value).is_err() {
+            bail!("[api]: {key} contains characters not allowed in an HTTP header");
+        }
+    }
+    if let Some(ntfy) = &config.notifications.ntfy {
+        if !ntfy.server_url.starts_with("http://") && !ntfy.server_url.starts_with("https://") {
+            bail!(
+                "[notifications.ntfy]: server_url '{}' must be an http(s) URL",
+                ntfy.server_url
+            );
+        }
+        if ntfy.topic.is_empty() || ntfy.topic.contains('/') {
+            bail!("[notifications.ntfy]: topic must be a non-empty name without '/'");
+        }
+    }
+    let watcher = &config.watcher;
+    if watcher.active_interval_secs == 0 || watcher.idle_interval_secs == 0 {
+        bail!("[watcher]: active_interval_secs and idle_interval_secs must be positive");
+    }
+    if watcher.active_interval_secs >= watcher.idle_interval_secs {
+        bail!("[watcher]: active_interval_secs must be shorter than idle_interval_secs");
+    }
+    for user in &config.users {
+        config
+            .gym(user.gym.as_deref())
+            .with_context(|| format!("user '{}'", user.name))?;
+        let own_chat = user
+            .notify
+            .as_ref()
+            .and_then(|n| n.telegram_chat_id.as_ref());
+        if own_chat.is_some() && config.notifications.telegram.is_none() {
+            bail!(
+                "user '{}': notify.telegram_chat_id needs [notifications.telegram] for the bot token",
+                user.name
+            );
+        }
+    }
+    for (day, slot) in &config.slots {
+        for t in slot.time.all() {
+            if let Err(e) = scheduler::parse_slot_time(t) {
+                bail!("[slots.{day}]: time {e}");
+            }
+        }
+        check_booking_window(
+            &format!("[slots.{day}]"),
+            slot.booking_window_days,
+            slot.booking_opens_at.as_deref(),
+        )?;
+    }
+    Ok(())
+}
+
+fn check_booking_window(section: &str, days: Option<u32>, opens_at: Option<&str>) -> Result<()> {
+    // Classes are targeted within the coming week, so a longer window would
+    // already be open for every one of them
+    if let Some(days @ 8..) = days {
+        bail!("{section}: booking_window_days must be at most 7, not {days}");
+    }
+    if let Some(t) = opens_at {
+        if let Err(e) = scheduler::parse_slot_time(t) {
+            bail!("{section}: booking_opens_at {e}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with_password(password: &str) -> Config {
+        toml::from_str(&format!(
+            r#"
+            slots = {{}}
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            [[users]]
+            name = "Bob"
+            login = "bob@example.com"
+            password = "{password}"
+            slots = []
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_password_from_env() {
+        std::env::set_var("RESAWOD_TEST_BOB_PASS", "from-env");
+        let mut cfg = config_with_password("env:RESAWOD_TEST_BOB_PASS");
+        resolve_passwords(&mut cfg).unwrap();
+        assert_eq!(cfg.users[0].password, "from-env");
+
+        let mut cfg = config_with_password("plain");
+        resolve_passwords(&mut cfg).unwrap();
+        assert_eq!(cfg.users[0].password, "plain");
+    }
+
+    #[test]
+    fn test_password_env_unset() {
+        let mut cfg = config_with_password("env:RESAWOD_TEST_UNSET_PASS");
+        let err = resolve_passwords(&mut cfg).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("RESAWOD_TEST_UNSET_PASS is not set"));
+    }
+
+    #[test]
+    fn test_only_users() {
+        let mut cfg = config_with_password("plain");
+        let mut alice = cfg.users[0].clone();
+        (alice.name, alice.login) = ("Alice".into(), "alice@example.com".into());
+        cfg.users.push(alice);
+
+        only_users(&mut cfg, &[]).unwrap();
+        assert_eq!(cfg.users.len(), 2);
+        let err = only_users(&mut cfg, &["bob".into(), "carol".into()]).unwrap_err();
+        assert!(err.to_string().contains("no user 'carol'"), "{err}");
+        assert_eq!(cfg.users.len(), 2);
+        let err = only_users(&mut cfg, &["".into(), " ".into()]).unwrap_err();
+        assert!(err.to_string().contains("no user names"), "{err}");
+        only_users(&mut cfg, &["ALICE@example.com".into()]).unwrap();
+        let names: Vec<_> = cfg.users.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["Alice"]);
+    }
+
+    #[test]
+    fn test_day_names_are_normalized() {
+        let mut cfg = config_with_password("plain");
+        cfg.slots
+            .insert("martes".into(), crate::models::SlotConfig::new("18:00"));
+        cfg.users[0].slots = vec!["tue".into(), "someday".into()];
+        normalize_days(&mut cfg).unwrap();
+        assert!(cfg.slots.contains_key("tuesday"));
+        assert_eq!(cfg.users[0].slots, ["tuesday", "someday"]);
+
+        cfg.slots
+            .insert("tue".into(), crate::models::SlotConfig::new("19:00"));
+        assert!(normalize_days(&mut cfg).is_err());
+
+        // A second class on a weekday keeps its label
+        cfg.slots.remove("tue");
+        cfg.slots
+            .insert("martes-2".into(), crate::models::SlotConfig::new("07:00"));
+        cfg.users[0].slots = vec!["Tue-2".into()];
+        normalize_days(&mut cfg).unwrap();
+        assert!(cfg.slots.contains_key("tuesday") && cfg.slots.contains_key("tuesday-2"));
+        assert_eq!(cfg.users[0].slots, ["tuesday-2"]);
+    }
+
+    #[test]
+    fn test_include_merges_files_in_order() {
+        let dir = std::env::temp_dir().join(format!("resawod-include-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, content: &str| std::fs::write(dir.join(name), content).unwrap();
+        write(
+            "config.toml",
+            r#"
+            include = ["users.toml", "slots.toml"]
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            [[users]]
+            name = "Alice"
+            login = "alice@example.com"
+            password = "a"
+            slots = ["monday"]
+            [slots.monday]
+            time = "07:00"
+            "#,
+        );
+        write(
+            "users.toml",
+            r#"
+            [[users]]
+            name = "Bob"
+            login = "bob@example.com"
+            password = "b"
+            slots = ["tue"]
+            "#,
+        );
+        write(
+            "slots.toml",
+            "[slots]
+monday = \"18:00\"
+tuesday = \"19:00\"
+",
+        );
+
+        let cfg = load_config(&dir.join("config.toml")).unwrap();
+        let names: Vec<_> = cfg.users.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["Alice", "Bob"]);
+        assert_eq!(cfg.users[1].slots, ["tuesday"]);
+        assert_eq!(cfg.slots["monday"].time.primary(), "18:00:00");
+        assert_eq!(
+            included_files(&dir.join("config.toml")).unwrap(),
+            [dir.join("users.toml"), dir.join("slots.toml")]
+        );
+
+        write("slots.toml", "[app]\ntimezone = \"Europe/Madrid\"\n");
+        let err = load_config(&dir.join("config.toml")).unwrap_err();
+        assert!(
+            err.to_string().contains("[app] is defined in both"),
+            "{err}"
+        );
+
+        write("users.toml", "include = [\"slots.toml\"]\n");
+        let err = load_config(&dir.join("config.toml")).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("only supported in the main config"),
+            "{err}"
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_timezone_validation() {
+        let mut cfg = config_with_password("plain");
+        assert_eq!(timezone(&cfg.app).unwrap(), scheduler::CET);
+        cfg.app.timezone = Some("America/New_York".into());
+        assert_eq!(timezone(&cfg.app).unwrap(), chrono_tz::America::New_York);
+        assert_eq!(cfg.app.tz(), chrono_tz::America::New_York);
+        cfg.app.timezone = Some("Mars/Olympus".into());
+        assert!(validate(&cfg).is_err());
+
+        let mut cfg = config_with_password("plain");
+        cfg.slots
+            .insert("monday".into(), crate::models::SlotConfig::new("18h"));
+        let err = validate(&cfg).unwrap_err();
+        assert!(err.to_string().contains("time '18h'"));
+    }
+
+    #[test]
+    fn test_watcher_intervals_validated() {
+        let mut cfg = config_with_password("plain");
+        assert!(validate(&cfg).is_ok());
+        cfg.watcher.active_interval_secs = 3600;
+        assert!(validate(&cfg)
+            .unwrap_err()
+            .to_string()
+            .contains("shorter than"));
+        cfg.watcher.active_interval_secs = 0;
+        assert!(validate(&cfg)
+            .unwrap_err()
+            .to_string()
+            .contains("must be positive"));
+    }
+
+    #[test]
+    fn test_booking_window_days_validated() {
+        let mut cfg = config_with_password("plain");
+        cfg.app.booking_window_days = 7;
+        assert!(validate(&cfg).is_ok());
+        // Bookings opening on the day of the class
+        cfg.app.booking_window_days = 0;
+        assert!(validate(&cfg).is_ok());
+        cfg.app.booking_window_days = 14;
+        let err = validate(&cfg).unwrap_err().to_string();
+        assert!(err.contains("[app]: booking_window_days must be at most 7"));
+
+        let mut cfg = config_with_password("plain");
+        let slot = crate::models::SlotConfig {
+            booking_window_days: Some(8),
+            ..crate::models::SlotConfig::new("18:00")
+        };
+        cfg.slots.insert("monday".into(), slot);
+        let err = validate(&cfg).unwrap_err().to_string();
+        assert!(err.contains("[slots.monday]: booking_window_days"));
+    }
 }
@@ -0,0 +1,211 @@
+//! Append-only log of booking attempts, one JSON object per line.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+const FILE_NAME: &str = "booking_history.jsonl";
+
+/// One booking attempt: who, for which class, and what the gym answered.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// When the attempt finished, RFC 3339 in the gym's timezone.
+    pub timestamp: String,
+    pub user: String,
+    /// The `[slots]` key the class is configured under.
+    pub day: String,
+    pub target_date: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot_id: Option<String>,
+    /// `booked`, `waiting_list`, `waiting_list_closed`, `waiting_list_disabled`,
+    /// `failed`, `membership_issue`, `slot_not_found`, `already_booked`,
+    /// `weekly_cap_reached` or `error`.
+    pub outcome: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// For scheduled bookings, how long after the booking window opened the
+    /// gym confirmed the booking, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<i64>,
+}
+
+impl HistoryEntry {
+    /// An attempt that finished `at`, for `user`'s class on `target_date`
+    /// configured under the `[slots]` key `day` (e.g. "monday-2").
+    pub fn new(
+        at: DateTime<Tz>,
+        user: &str,
+        day: &str,
+        target_date: NaiveDate,
+        outcome: &str,
+    ) -> Self {
+        Self {
+            timestamp: at.to_rfc3339(),
+            user: user.to_string(),
+            day: day.to_string(),
+            target_date: target_date.format("%Y-%m-%d").to_string(),
+            slot_id: None,
+            outcome: outcome.to_string(),
+            message: None,
+            latency_ms: None,
+        }
+    }
+
+    pub fn with_slot(mut self, slot_id: &str) -> Self {
+        self.slot_id = Some(slot_id.to_string());
+        self
+    }
+
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        let message = message.into();
+        self.message = (!message.is_empty()).then_some(message);
+        self
+    }
+
+    pub fn with_latency(mut self, latency: chrono::Duration) -> Self {
+        self.latency_ms = Some(latency.num_milliseconds());
+        self
+    }
+}
+
+/// A booking latency as shown to users, e.g. "+3.2s".
+pub fn format_latency(ms: i64) -> String {
+    format!("+{:.1}s", ms.max(0) as f64 / 1000.0)
+}
+
+/// The history file, `booking_history.jsonl` next to the config file.
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn next_to(config_path: &Path) -> Self {
+        Self::at(
+            config_path
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join(FILE_NAME),
+        )
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `entry`. A failed write is logged, never fatal: losing a
+    /// history line must not lose the booking.
+    pub fn record(&self, entry: &HistoryEntry) {
+        let result = serde_json::to_string(entry)
+            .map_err(std::io::Error::from)
+            .and_then(|line| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?
+                    .write_all(format!("{line}\n").as_bytes())
+            });
+        if let Err(e) = result {
+            warn!(
+                "Failed to write booking history to {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// The last `n` entries, oldest first. A missing file is an empty
+    /// history; unreadable lines are skipped.
+    pub fn last(&self, n: usize) -> Result<Vec<HistoryEntry>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.path.display()))
+            }
+        };
+        let entries: Vec<HistoryEntry> = contents
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .filter_map(|l| match serde_json::from_str(l) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    warn!("Skipping unreadable history line: {}", e);
+                    None
+                }
+            })
+            .collect();
+        let skip = entries.len().saturating_sub(n);
+        Ok(entries.into_iter().skip(skip).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_record_and_read_last() {
+        let path =
+            std::env::temp_dir().join(format!("resawod-history-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let history = History::at(path.clone());
+        assert!(history.last(10).unwrap().is_empty());
+
+        let date = NaiveDate::from_ymd_opt(2024, 1, 16).unwrap();
+        let at = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 9, 18, 1, 3)
+            .unwrap();
+        history.record(
+            &HistoryEntry::new(at, "alice", "tuesday", date, "failed")
+                .with_slot("42")
+                .with_message("Full"),
+        );
+        history.record(
+            &HistoryEntry::new(at, "alice", "tuesday", date, "waiting_list").with_slot("42"),
+        );
+        history.record(
+            &HistoryEntry::new(at, "alice", "tuesday", date, "booked")
+                .with_slot("42")
+                .with_latency(chrono::Duration::milliseconds(3249)),
+        );
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"not json\n")
+            .unwrap();
+        history.record(
+            &HistoryEntry::new(at, "bob", "tuesday-2", date, "slot_not_found").with_message(""),
+        );
+
+        let last = history.last(2).unwrap();
+        assert_eq!(last.len(), 2);
+        assert_eq!(last[0].outcome, "booked");
+        assert_eq!(last[0].slot_id.as_deref(), Some("42"));
+        assert_eq!(
+            last[0].latency_ms.map(format_latency).as_deref(),
+            Some("+3.2s")
+        );
+        assert_eq!(last[1].user, "bob");
+        assert_eq!(last[1].day, "tuesday-2");
+        assert_eq!(last[1].target_date, "2024-01-16");
+        assert_eq!(last[1].message, None);
+        assert_eq!(last[1].latency_ms, None);
+        assert_eq!(history.last(10).unwrap().len(), 4);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
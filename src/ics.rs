@@ -0,0 +1,118 @@
+//! iCalendar (RFC 5545) export of bookings.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+use crate::models::Booking;
+use crate::scheduler;
+
+const PRODID: &str = "-//resawod-scheduler//bookings//EN";
+
+/// Render bookings (as returned by `get_bookings`) as a VCALENDAR with one
+/// VEVENT each. UIDs derive from `id_activity_calendar`, so re-importing
+/// the file updates events instead of duplicating them. Entries without a
+/// parseable start are skipped. Booking times are read in the timezone of
+/// `now`, the gym's.
+pub fn bookings_to_ics(bookings: &[Booking], now: DateTime<Tz>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{PRODID}"),
+        "CALSCALE:GREGORIAN".to_string(),
+    ];
+    let stamp = format_utc(now.with_timezone(&Utc));
+
+    for b in bookings {
+        let Some(start) = parse_utc(&b.start, now.timezone()) else {
+            continue;
+        };
+        let end = parse_utc(&b.end, now.timezone()).unwrap_or(start + chrono::Duration::hours(1));
+        let id = b.calendar_id().unwrap_or_else(|| format_utc(start));
+        let summary = b.name.as_deref().map(str::trim).unwrap_or("Training");
+
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{id}@resawod-scheduler"));
+        lines.push(format!("DTSTAMP:{stamp}"));
+        lines.push(format!("DTSTART:{}", format_utc(start)));
+        lines.push(format!("DTEND:{}", format_utc(end)));
+        lines.push(format!("SUMMARY:{}", escape_text(summary)));
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|l| fold_line(l)).collect()
+}
+
+fn parse_utc(s: &str, tz: Tz) -> Option<DateTime<Utc>> {
+    scheduler::parse_timestamp(s, tz).map(|t| t.with_timezone(&Utc))
+}
+
+fn format_utc(t: DateTime<Utc>) -> String {
+    t.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape_text(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Terminate a content line with CRLF, folding it at 75 octets.
+fn fold_line(line: &str) -> String {
+    let mut out = String::new();
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_bookings_to_ics() {
+        let bookings: Vec<Booking> = serde_json::from_value(serde_json::json!([
+            {
+                "id_activity_calendar": 123,
+                "start_timestamp": "2024-01-15 18:30:00",
+                "end_timestamp": "2024-01-15 19:30:00",
+                "name_activity": "CrossFit, WOD",
+            },
+            {"name_activity": "no start"},
+        ]))
+        .unwrap();
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 10, 13, 0, 0)
+            .unwrap();
+        let ics = bookings_to_ics(&bookings, now);
+
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 1);
+        assert!(ics.contains("UID:123@resawod-scheduler\r\n"));
+        // 18:30 CET is 17:30 UTC in winter
+        assert!(ics.contains("DTSTART:20240115T173000Z\r\n"));
+        assert!(ics.contains("DTEND:20240115T183000Z\r\n"));
+        assert!(ics.contains("SUMMARY:CrossFit\\, WOD\r\n"));
+    }
+
+    #[test]
+    fn test_fold_long_lines() {
+        let folded = fold_line(&format!("SUMMARY:{}", "x".repeat(100)));
+        assert!(folded.split("\r\n").all(|l| l.len() <= 75));
+        assert_eq!(
+            folded.replace("\r\n ", ""),
+            format!("SUMMARY:{}\r\n", "x".repeat(100))
+        );
+    }
+}
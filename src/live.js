@@ -0,0 +1,44 @@
+// Live dashboard updates over Server-Sent Events (`/events`). Browsers
+// without EventSource keep the page as loaded; reloading shows the rest.
+(function () {
+  if (!window.EventSource) {
+    return;
+  }
+  var events = new EventSource("/events");
+
+  // A changed scheduler row, rendered by the server: replace it by its key
+  events.addEventListener("scheduler", function (e) {
+    var table = document.getElementById("scheduler-table");
+    if (!table) {
+      // The first entry replaces the "no scheduled bookings" note
+      location.reload();
+      return;
+    }
+    var template = document.createElement("template");
+    template.innerHTML = e.data.trim();
+    var row = template.content.firstElementChild;
+    if (!row) {
+      return;
+    }
+    var rows = table.tBodies[0].rows;
+    for (var i = 0; i < rows.length; i++) {
+      if (rows[i].dataset.key === row.dataset.key) {
+        rows[i].replaceWith(row);
+        return;
+      }
+    }
+    table.tBodies[0].appendChild(row);
+  });
+
+  events.addEventListener("watcher", function (e) {
+    var status = document.querySelector(".watcher-status");
+    if (status) {
+      status.textContent = e.data;
+    }
+  });
+
+  // Changes were missed: start over from a fresh page
+  events.addEventListener("reload", function () {
+    location.reload();
+  });
+})();
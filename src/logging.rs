@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter};
+
+use crate::models::{LogRotation, LoggingConfig};
+
+/// Set up console logging on stderr, leaving stdout to command output (this
+/// crate's debug output and API traffic with `-v`, info otherwise) and, when
+/// `[logging].file` is set, JSON logs to a rotating file. The returned guard
+/// flushes the file writer and must be kept alive until exit.
+pub fn init(verbose: bool, cfg: &LoggingConfig) -> Result<Option<WorkerGuard>> {
+    let console = fmt::layer()
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .with_filter(EnvFilter::new(if verbose {
+            crate_filter("debug")
+        } else {
+            "info".to_string()
+        }));
+
+    let (file, guard) = match &cfg.file {
+        Some(path) => {
+            let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+            let name = path.file_name().with_context(|| {
+                format!("[logging]: file '{}' has no file name", path.display())
+            })?;
+            let rotation = match cfg.rotation {
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+                LogRotation::Never => Rotation::NEVER,
+            };
+            let appender = RollingFileAppender::new(rotation, dir.unwrap_or(Path::new(".")), name);
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = fmt::layer()
+                .json()
+                .with_writer(writer)
+                .with_filter(EnvFilter::new(crate_filter(&cfg.level)));
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .init();
+    Ok(guard)
+}
+
+/// `level` for this crate and its API traffic (`nubapp::http`), info for
+/// the libraries underneath.
+fn crate_filter(level: &str) -> String {
+    format!("info,resawod_scheduler={level},nubapp={level}")
+}
+
+/// Read just the `[logging]` table, before the rest of the config is loaded
+/// and validated (so that loading errors get logged too), from the main file
+/// or any file it includes. A missing or unreadable file yields the defaults;
+/// the command reports it properly later.
+pub fn load_config(path: &Path) -> LoggingConfig {
+    #[derive(Deserialize)]
+    struct Partial {
+        #[serde(default)]
+        logging: LoggingConfig,
+    }
+    crate::config::read_merged(path)
+        .ok()
+        .and_then(|table| table.try_into::<Partial>().ok())
+        .map(|p| p.logging)
+        .unwrap_or_default()
+}
@@ -1,14 +1,18 @@
 mod client;
 mod commands;
 mod config;
+mod history;
+mod ics;
+mod logging;
 mod models;
+mod notify;
 mod scheduler;
 mod web;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Result};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use tracing::{error, info};
 
 use models::User;
@@ -33,6 +37,7 @@ enum Command {
     ///   book tuesday           — book Tuesday for the first user in config
     ///   book tuesday,friday    — book Tuesday and Friday
     ///   book --multi-users     — book configured slots for all users
+    ///   book friday --time 07:00 — book Friday at 07:00 instead of the configured time
     Book {
         /// Days to book (comma-separated, e.g. "tuesday" or "tuesday,friday").
         /// Uses first user from config. Overrides --slots.
@@ -43,6 +48,15 @@ enum Command {
         #[arg(short, long)]
         multi_users: bool,
 
+        /// With --multi-users, only these users (comma-separated names or logins)
+        #[arg(
+            long,
+            value_name = "USERS",
+            value_delimiter = ',',
+            requires = "multi_users"
+        )]
+        only: Vec<String>,
+
         /// Single-user login
         #[arg(short = 'u', long)]
         user: Option<String>,
@@ -70,6 +84,92 @@ enum Command {
         /// Dry run — find slots but do not actually book them
         #[arg(short = 'd', long)]
         debug: bool,
+
+        /// Book this time (HH:MM) instead of the configured one for every listed day
+        #[arg(long, conflicts_with = "multi_users")]
+        time: Option<String>,
+
+        /// Activity filter overriding the configured one for every listed day
+        #[arg(long, conflicts_with = "multi_users")]
+        activity: Option<String>,
+
+        /// Number of guests to book alongside the user for every listed day
+        #[arg(long, conflicts_with = "multi_users")]
+        guests: Option<u32>,
+    },
+
+    /// Book a single class on a given date, whatever its weekday
+    ///
+    /// Examples:
+    ///   book-date 22-03-2025 --time 10:00 --activity "open gym"
+    ///   book-date 2025-03-22             — uses the configured Saturday slot
+    BookDate {
+        /// Date as DD-MM-YYYY or YYYY-MM-DD (or "tomorrow", a day name)
+        #[arg(value_name = "DATE")]
+        date: String,
+
+        /// Slot time (HH:MM); defaults to the configured time for that weekday
+        #[arg(long)]
+        time: Option<String>,
+
+        /// Activity filter; defaults to the configured one for that weekday
+        #[arg(long)]
+        activity: Option<String>,
+
+        /// Number of guests to book alongside the user
+        #[arg(long)]
+        guests: Option<u32>,
+
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// Override login from config (defaults to first user)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+
+        /// Override password from config (defaults to first user)
+        #[arg(short = 'p', long)]
+        password: Option<String>,
+
+        /// Dry run — find the slot but do not actually book it
+        #[arg(short = 'd', long)]
+        debug: bool,
+    },
+
+    /// Book one day of the week for several weeks in a row
+    ///
+    /// Examples:
+    ///   book-range tuesday --to 15-03-2025                     — every Tuesday until then
+    ///   book-range friday --from 01-03-2025 --to 31-03-2025    — every Friday in March
+    BookRange {
+        /// Day to book (e.g. "tuesday"), using its `[slots]` config
+        #[arg(value_name = "DAY")]
+        day: String,
+
+        /// First date as DD-MM-YYYY, YYYY-MM-DD, "today", "tomorrow" or a day name (default: today)
+        #[arg(long)]
+        from: Option<String>,
+
+        /// Last date, in the same formats as --from
+        #[arg(long)]
+        to: String,
+
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// Override login from config (defaults to first user)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+
+        /// Override password from config (defaults to first user)
+        #[arg(short = 'p', long)]
+        password: Option<String>,
+
+        /// Dry run — find slots but do not actually book them
+        #[arg(short = 'd', long)]
+        debug: bool,
     },
 
     /// Show active bookings for a user
@@ -85,6 +185,201 @@ enum Command {
         /// Override password from config (defaults to first user)
         #[arg(short = 'p', long)]
         password: Option<String>,
+
+        /// Print bookings as JSON (see docs/usage.md for the schema)
+        #[arg(long)]
+        json: bool,
+
+        /// Ask the gym for at most this many bookings
+        #[arg(long, default_value_t = client::DEFAULT_BOOKINGS_LIMIT)]
+        limit: u32,
+    },
+
+    /// List all slots the API offers on a date, to help fill in `[slots]`
+    ///
+    /// Examples:
+    ///   list-slots tomorrow       — slots for tomorrow
+    ///   list-slots friday         — slots for next Friday
+    ///   list-slots 14-03-2025     — slots for a given date
+    ListSlots {
+        /// Date as DD-MM-YYYY, YYYY-MM-DD, "today", "tomorrow" or a day name
+        #[arg(value_name = "DATE", default_value = "today")]
+        date: String,
+
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// Override login from config (defaults to first user)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+
+        /// Override password from config (defaults to first user)
+        #[arg(short = 'p', long)]
+        password: Option<String>,
+
+        /// Print slots as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Only show slots with at least this many free spots
+        #[arg(long, value_name = "N")]
+        min_free: Option<u32>,
+    },
+
+    /// Export upcoming bookings for a calendar app
+    ///
+    /// Examples:
+    ///   export > bookings.ics    — write an iCalendar file
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Ics)]
+        format: ExportFormat,
+
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// Override login from config (defaults to first user)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+
+        /// Override password from config (defaults to first user)
+        #[arg(short = 'p', long)]
+        password: Option<String>,
+    },
+
+    /// Cancel a booking
+    ///
+    /// Examples:
+    ///   cancel tuesday               — show the next Tuesday booking to cancel
+    ///   cancel tuesday --yes         — cancel it
+    ///   cancel --slot-id 123 --yes   — cancel by slot ID
+    Cancel {
+        /// Day of the booking to cancel (e.g. "tuesday"); the earliest upcoming one is used
+        #[arg(value_name = "DAY", required_unless_present = "slot_id")]
+        day: Option<String>,
+
+        /// Cancel the booking for this slot ID instead of looking it up by day
+        #[arg(long, conflicts_with = "day")]
+        slot_id: Option<String>,
+
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// Override login from config (defaults to first user)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+
+        /// Override password from config (defaults to first user)
+        #[arg(short = 'p', long)]
+        password: Option<String>,
+
+        /// Confirm the cancellation
+        #[arg(short = 'y', long)]
+        yes: bool,
+
+        /// Dry run — show what would be cancelled without cancelling
+        #[arg(short = 'd', long)]
+        debug: bool,
+    },
+
+    /// Cancel all upcoming bookings, or those before a date
+    ///
+    /// Examples:
+    ///   cancel-all                          — list what would be cancelled
+    ///   cancel-all --before monday --yes    — cancel everything until Monday
+    CancelAll {
+        /// Only cancel bookings on days before this date (DD-MM-YYYY, YYYY-MM-DD, or a day name)
+        #[arg(long, value_name = "DATE")]
+        before: Option<String>,
+
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// Override login from config (defaults to first user)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+
+        /// Override password from config (defaults to first user)
+        #[arg(short = 'p', long)]
+        password: Option<String>,
+
+        /// Confirm the cancellations
+        #[arg(short = 'y', long)]
+        yes: bool,
+
+        /// Dry run — show what would be cancelled without cancelling
+        #[arg(short = 'd', long)]
+        debug: bool,
+    },
+
+    /// Leave a waiting list you no longer want to be on
+    LeaveWaitlist {
+        /// Day of the class (e.g. "tuesday"); the earliest upcoming waiting list that day is used
+        #[arg(value_name = "DAY", required_unless_present = "slot_id")]
+        day: Option<String>,
+
+        /// Leave the waiting list of this slot ID instead of looking it up by day
+        #[arg(long, conflicts_with = "day")]
+        slot_id: Option<String>,
+
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// Override login from config (defaults to first user)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+
+        /// Override password from config (defaults to first user)
+        #[arg(short = 'p', long)]
+        password: Option<String>,
+
+        /// Confirm leaving the waiting list
+        #[arg(short = 'y', long)]
+        yes: bool,
+
+        /// Dry run — show which waiting list would be left
+        #[arg(short = 'd', long)]
+        debug: bool,
+    },
+
+    /// Move a booking to another activity at the same time
+    ///
+    /// Examples:
+    ///   switch tuesday --to weightlifting         — show what would change
+    ///   switch tuesday --to weightlifting --yes   — cancel and rebook
+    Switch {
+        /// Day of the booking to move (e.g. "tuesday"); the earliest upcoming one is used
+        #[arg(value_name = "DAY")]
+        day: String,
+
+        /// Activity to switch to (partial, case-insensitive)
+        #[arg(long = "to", value_name = "ACTIVITY")]
+        to_activity: String,
+
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// Override login from config (defaults to first user)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+
+        /// Override password from config (defaults to first user)
+        #[arg(short = 'p', long)]
+        password: Option<String>,
+
+        /// Confirm the switch
+        #[arg(short = 'y', long)]
+        yes: bool,
+
+        /// Dry run — show what would be switched without changing anything
+        #[arg(short = 'd', long)]
+        debug: bool,
     },
 
     /// Start web dashboard server
@@ -96,6 +391,33 @@ enum Command {
         /// Listen address (e.g. "0.0.0.0:3000")
         #[arg(short = 'a', long, default_value = "0.0.0.0:3009")]
         addr: String,
+
+        /// Only book and show these users (comma-separated names or logins)
+        #[arg(long, value_name = "USERS", value_delimiter = ',')]
+        only: Vec<String>,
+    },
+
+    /// Run the booking schedulers without the dashboard, e.g. from cron
+    ///
+    /// Examples:
+    ///   run-schedulers --once                 — book every window opening now, then exit
+    ///   run-schedulers --once --tolerance 120 — allow cron to fire up to two minutes off
+    RunSchedulers {
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// Book the windows opening around now once, then exit
+        #[arg(long)]
+        once: bool,
+
+        /// With --once, how many seconds before or after its opening a window still counts
+        #[arg(long, value_name = "SECS", default_value_t = 300, requires = "once")]
+        tolerance: u64,
+
+        /// Only book for these users (comma-separated names or logins)
+        #[arg(long, value_name = "USERS", value_delimiter = ',')]
+        only: Vec<String>,
     },
 
     /// Discover gym IDs — log in and show application ID and activity categories
@@ -115,49 +437,314 @@ enum Command {
         /// Override password from config (defaults to first user)
         #[arg(short = 'p', long)]
         password: Option<String>,
+
+        /// Run discovery for every user in config and flag application ID mismatches
+        #[arg(long, conflicts_with_all = ["user", "password"])]
+        all_users: bool,
+
+        /// Write the discovered IDs as a config-ready [app] section to this file ("-" for stdout)
+        #[arg(short = 'o', long, value_name = "FILE", conflicts_with = "all_users")]
+        output: Option<PathBuf>,
+
+        /// Overwrite the --output file if it already exists
+        #[arg(long, requires = "output")]
+        force: bool,
+    },
+
+    /// Check the config, every user's login and the gym IDs before relying on the scheduler
+    Doctor {
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+    },
+
+    /// Log in and print the session token's claims and when it expires
+    Token {
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// Configured user, by name or login (defaults to first user)
+        #[arg(short = 'u', long)]
+        user: Option<String>,
+    },
+
+    /// Show what the schedulers will book next for every user, without contacting the gym
+    Plan {
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+    },
+
+    /// Show the latest booking attempts from the history log next to the config
+    History {
+        /// Path to config file
+        #[arg(short = 'c', long, default_value = "config.toml")]
+        config: PathBuf,
+
+        /// How many entries to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
     },
 }
 
+impl Command {
+    fn config(&self) -> &Path {
+        match self {
+            Command::Book { config, .. }
+            | Command::BookDate { config, .. }
+            | Command::BookRange { config, .. }
+            | Command::Bookings { config, .. }
+            | Command::ListSlots { config, .. }
+            | Command::Export { config, .. }
+            | Command::Cancel { config, .. }
+            | Command::CancelAll { config, .. }
+            | Command::LeaveWaitlist { config, .. }
+            | Command::Switch { config, .. }
+            | Command::Serve { config, .. }
+            | Command::RunSchedulers { config, .. }
+            | Command::Discover { config, .. }
+            | Command::Doctor { config }
+            | Command::Token { config, .. }
+            | Command::Plan { config }
+            | Command::History { config, .. } => config,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    /// iCalendar (.ics)
+    Ics,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    let filter = if cli.verbose { "debug" } else { "info" };
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .init();
+    let _log_guard = logging::init(cli.verbose, &logging::load_config(cli.command.config()))?;
 
     match &cli.command {
-        Command::Serve { config, addr } => {
-            let cfg = config::load_config(config)?;
-            web::serve(cfg, config, addr).await?;
+        Command::Serve { config, addr, only } => {
+            let mut cfg = config::load_config(config)?;
+            config::only_users(&mut cfg, only)?;
+            web::serve(cfg, config, addr, only).await?;
+        }
+        Command::RunSchedulers {
+            config,
+            once,
+            tolerance,
+            only,
+        } => {
+            let mut cfg = config::load_config(config)?;
+            config::only_users(&mut cfg, only)?;
+            web::run_schedulers(
+                cfg,
+                config,
+                *once,
+                std::time::Duration::from_secs(*tolerance),
+            )
+            .await?;
         }
         Command::Bookings {
             config,
             user,
             password,
+            json,
+            limit,
+        } => {
+            commands::run_bookings(cli.verbose, *json, *limit, config, user, password).await?;
+        }
+        Command::ListSlots {
+            date,
+            config,
+            user,
+            password,
+            json,
+            min_free,
+        } => {
+            commands::run_list_slots(*json, config, user, password, date, *min_free).await?;
+        }
+        Command::Export {
+            format: ExportFormat::Ics,
+            config,
+            user,
+            password,
+        } => {
+            commands::run_export_ics(config, user, password).await?;
+        }
+        Command::Cancel {
+            day,
+            slot_id,
+            config,
+            user,
+            password,
+            yes,
+            debug,
+        } => {
+            commands::run_cancel(
+                cli.verbose,
+                config,
+                user,
+                password,
+                day.as_deref(),
+                slot_id.as_deref(),
+                *yes,
+                *debug,
+            )
+            .await?;
+        }
+        Command::CancelAll {
+            before,
+            config,
+            user,
+            password,
+            yes,
+            debug,
+        } => {
+            commands::run_cancel_all(
+                cli.verbose,
+                config,
+                user,
+                password,
+                before.as_deref(),
+                *yes,
+                *debug,
+            )
+            .await?;
+        }
+        Command::LeaveWaitlist {
+            day,
+            slot_id,
+            config,
+            user,
+            password,
+            yes,
+            debug,
+        } => {
+            commands::run_leave_waitlist(
+                cli.verbose,
+                config,
+                user,
+                password,
+                day.as_deref(),
+                slot_id.as_deref(),
+                *yes,
+                *debug,
+            )
+            .await?;
+        }
+        Command::Switch {
+            day,
+            to_activity,
+            config,
+            user,
+            password,
+            yes,
+            debug,
+        } => {
+            commands::run_switch(
+                cli.verbose,
+                config,
+                user,
+                password,
+                day,
+                to_activity,
+                *yes,
+                *debug,
+            )
+            .await?;
+        }
+        Command::BookDate {
+            date,
+            time,
+            activity,
+            guests,
+            config,
+            user,
+            password,
+            debug,
         } => {
-            commands::run_bookings(cli.verbose, config, user, password).await?;
+            commands::run_book_date(
+                cli.verbose,
+                config,
+                user,
+                password,
+                date,
+                time.as_deref(),
+                activity.as_deref(),
+                *guests,
+                *debug,
+            )
+            .await?;
+        }
+        Command::BookRange {
+            day,
+            from,
+            to,
+            config,
+            user,
+            password,
+            debug,
+        } => {
+            commands::run_book_range(
+                cli.verbose,
+                config,
+                user,
+                password,
+                day,
+                from.as_deref(),
+                to,
+                *debug,
+            )
+            .await?;
+        }
+        Command::Doctor { config } => {
+            commands::run_doctor(config).await?;
+        }
+        Command::Token { config, user } => {
+            commands::run_token(config, user.as_deref()).await?;
+        }
+        Command::Plan { config } => {
+            commands::run_plan(config)?;
+        }
+        Command::History { config, limit } => {
+            commands::run_history(config, *limit)?;
         }
         Command::Discover {
             config,
             application_id,
             user,
             password,
+            all_users,
+            output,
+            force,
         } => {
             let cfg = config::load_config(config)?;
-            let (login, pass) =
-                commands::resolve_credentials(user, password, cfg.users.first())?;
-            let app_id = application_id
-                .as_deref()
-                .unwrap_or(&cfg.app.application_id);
 
-            commands::run_discover(app_id, login, pass, cli.verbose).await?;
+            if *all_users {
+                commands::run_discover_all(&cfg, application_id.as_deref(), cli.verbose).await?;
+            } else {
+                let configured = commands::flag_user(&cfg, user);
+                let gym = cfg.gym(configured.and_then(|u| u.gym.as_deref()))?;
+                let app_id = application_id.as_deref().unwrap_or(&gym.application_id);
+                let (login, pass) = commands::resolve_credentials(user, password, configured)?;
+                commands::run_discover(
+                    &cfg.api,
+                    app_id,
+                    login,
+                    pass,
+                    cli.verbose,
+                    output.as_deref(),
+                    *force,
+                )
+                .await?;
+            }
         }
         Command::Book {
             days,
             multi_users,
+            only,
             user,
             password,
             slots,
@@ -165,29 +752,53 @@ async fn main() -> Result<()> {
             application_id,
             category_activity_id,
             debug,
+            time,
+            activity,
+            guests,
         } => {
-            let cfg = config::load_config(config)?;
-
-            let app_id = application_id
-                .as_deref()
-                .unwrap_or(&cfg.app.application_id);
-            let cat_id = category_activity_id
-                .as_deref()
-                .unwrap_or(&cfg.app.category_activity_id);
+            let mut cfg = config::load_config(config)?;
+            config::only_users(&mut cfg, only)?;
+            let history = history::History::next_to(config);
+            // --application-id / --category-activity-id win over the user's gym
+            let gym_ids = |u: Option<&User>| -> Result<models::GymConfig> {
+                let gym = cfg.gym(u.and_then(|u| u.gym.as_deref()))?;
+                Ok(models::GymConfig {
+                    application_id: application_id.clone().unwrap_or(gym.application_id),
+                    category_activity_id: category_activity_id
+                        .clone()
+                        .map(models::Choices::Single)
+                        .unwrap_or(gym.category_activity_id),
+                })
+            };
 
             if *multi_users {
+                // Users booking the same day share one slot lookup
+                let slot_cache = client::SlotCache::default();
+                let mut waiting = Vec::new();
                 for (i, u) in cfg.users.iter().enumerate() {
-                    if let Err(e) =
-                        commands::run_for_user(app_id, cat_id, cli.verbose, *debug, u, &cfg.slots)
-                            .await
+                    let nubapp = client::NubappClient::for_gym(&cfg, &gym_ids(Some(u))?)?
+                        .with_slot_cache(&slot_cache);
+                    match commands::run_for_user(
+                        nubapp,
+                        cli.verbose,
+                        *debug,
+                        u,
+                        &cfg.slots,
+                        &cfg.app,
+                        &history,
+                    )
+                    .await
                     {
-                        error!("Error processing user {}: {:#}", u.name, e);
+                        Ok(true) => waiting.push(u.clone()),
+                        Ok(false) => {}
+                        Err(e) => error!("Error processing user {}: {:#}", u.name, e),
                     }
                     if i < cfg.users.len() - 1 {
                         info!("Waiting 5 seconds before next user...");
                         tokio::time::sleep(std::time::Duration::from_secs(5)).await;
                     }
                 }
+                commands::poll_waiting_lists(&cfg, &waiting).await;
             } else if days.is_some() || slots.is_some() {
                 // Quick book: positional days or --slots, credentials from config or flags
                 let day_src = days.as_deref().or(slots.as_deref()).unwrap();
@@ -195,25 +806,50 @@ async fn main() -> Result<()> {
                     .split(',')
                     .map(|s| s.trim().to_lowercase())
                     .filter(|s| !s.is_empty())
+                    .map(|s| scheduler::canonical_slot_key(&s).unwrap_or(s))
                     .collect();
 
                 if slot_days.is_empty() {
                     bail!("No days specified");
                 }
 
-                let first_user = cfg.users.first();
-                let (login, pass) =
-                    commands::resolve_credentials(user, password, first_user)?;
+                let configured = commands::flag_user(&cfg, user);
+                let (login, pass) = commands::resolve_credentials(user, password, configured)?;
 
+                let slot_cfgs = commands::override_slots(
+                    &cfg.slots,
+                    &slot_days,
+                    time.as_deref(),
+                    activity.as_deref(),
+                    *guests,
+                )?;
+
+                let nubapp = client::NubappClient::for_gym(&cfg, &gym_ids(configured)?)?;
                 let u = User {
                     name: login.to_string(),
                     login: login.to_string(),
                     password: pass.to_string(),
                     slots: slot_days,
+                    gym: configured.and_then(|u| u.gym.clone()),
+                    watch_only: None,
+                    join_waiting_list: None,
+                    max_per_week: None,
+                    notify: None,
                 };
 
-                commands::run_for_user(app_id, cat_id, cli.verbose, *debug, &u, &cfg.slots)
-                    .await?;
+                if commands::run_for_user(
+                    nubapp,
+                    cli.verbose,
+                    *debug,
+                    &u,
+                    &slot_cfgs,
+                    &cfg.app,
+                    &history,
+                )
+                .await?
+                {
+                    commands::poll_waiting_lists(&cfg, &[u]).await;
+                }
             } else {
                 bail!(
                     "Specify days to book (e.g. `book tuesday`), \
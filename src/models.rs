@@ -1,45 +1,1124 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use serde::de::{self, Deserializer, MapAccess, Visitor};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Debug, Deserialize, Serialize)]
+use crate::scheduler;
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct Config {
     pub app: AppConfig,
+    /// Named gyms for members of several boxes; users pick one with `gym`.
+    #[serde(default)]
+    pub gyms: BTreeMap<String, GymConfig>,
     pub users: Vec<User>,
+    #[serde(deserialize_with = "deserialize_slots")]
     pub slots: HashMap<String, SlotConfig>,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub dashboard: DashboardConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub watcher: WatcherConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+}
+
+/// Optional log file, written in addition to the console.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Base path of the log file; rotated files get a date suffix.
+    pub file: Option<PathBuf>,
+    pub rotation: LogRotation,
+    /// Level for this program's own messages in the file (others log at info).
+    pub level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            rotation: LogRotation::Daily,
+            level: "debug".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// Nubapp endpoint and the client identity sent with each request. The
+/// defaults match the official web app; override them to follow upstream
+/// version bumps without rebuilding.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub base_url: String,
+    pub app_version: String,
+    /// Sent as the `Origin` header (and, with a trailing slash, `Referer`).
+    pub origin: String,
+    /// `Referer` header, when it isn't `origin` with a trailing slash.
+    pub referer: Option<String>,
+    /// `Nubapp-Origin` header, naming the kind of client.
+    pub nubapp_origin: String,
+    pub user_agent: String,
+    /// Seconds to wait for a connection to the API.
+    pub connect_timeout_secs: u64,
+    /// Seconds a whole request may take, response body included.
+    pub request_timeout_secs: u64,
+    /// `http://`, `https://` or `socks5://` proxy for API requests. When
+    /// unset, the `HTTP_PROXY`/`HTTPS_PROXY`/`NO_PROXY` environment is used.
+    pub proxy: Option<String>,
+    /// Comma-separated hosts or domains reached without `proxy`, as in `NO_PROXY`.
+    pub no_proxy: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://sport.nubapp.com/api/v4".to_string(),
+            app_version: "5.13.06".to_string(),
+            origin: "https://box.resawod.com".to_string(),
+            referer: None,
+            nubapp_origin: "user_apps".to_string(),
+            user_agent: "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:147.0) \
+                Gecko/20100101 Firefox/147.0"
+                .to_string(),
+            connect_timeout_secs: 10,
+            request_timeout_secs: 20,
+            proxy: None,
+            no_proxy: None,
+        }
+    }
+}
+
+/// How often `serve`'s waiting-list watcher checks the bookings.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct WatcherConfig {
+    /// Seconds between checks while some user is on a waiting list.
+    pub active_interval_secs: u64,
+    /// Seconds between checks while nobody is.
+    pub idle_interval_secs: u64,
+}
+
+impl WatcherConfig {
+    pub fn active_interval(&self) -> Duration {
+        Duration::from_secs(self.active_interval_secs)
+    }
+
+    pub fn idle_interval(&self) -> Duration {
+        Duration::from_secs(self.idle_interval_secs)
+    }
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        Self {
+            active_interval_secs: 60,
+            idle_interval_secs: 3600,
+        }
+    }
+}
+
+/// Settings for the `serve` web dashboard.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+pub struct DashboardConfig {
+    /// When set, every route requires these HTTP Basic credentials.
+    #[serde(default)]
+    pub auth: Option<DashboardAuth>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct DashboardAuth {
+    pub username: String,
+    pub password: String,
+}
+
+/// Where booking outcome messages are sent. Every backend is optional.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub telegram: Option<TelegramConfig>,
+    #[serde(default)]
+    pub discord: Option<DiscordConfig>,
+    #[serde(default)]
+    pub ntfy: Option<NtfyConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    pub chat_id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct DiscordConfig {
+    /// Channel webhook, from the channel's Integrations settings.
+    pub webhook_url: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct NtfyConfig {
+    /// ntfy.sh or a self-hosted server.
+    #[serde(default = "default_ntfy_server_url")]
+    pub server_url: String,
+    pub topic: String,
+    /// Access token, for topics that require one.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+fn default_ntfy_server_url() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+/// One day under `[slots]`. Times are kept as `HH:MM:SS` whichever
+/// accepted format the config used; an empty `activity` means any.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct SlotConfig {
-    pub time: String,
+    #[serde(deserialize_with = "deserialize_times")]
+    pub time: Choices,
+    /// Activity name filter, or several tried in order at each time.
+    #[serde(default, deserialize_with = "deserialize_activity")]
+    pub activity: Option<Choices>,
+    /// The gym's ID for the activity type, matched exactly instead of `activity`.
+    #[serde(default)]
+    pub id_activity: Option<String>,
+    /// Optional coach name filter (partial, case-insensitive).
+    #[serde(default)]
+    pub coach: Option<String>,
+    /// Set to `false` to pause auto-booking for this day without removing it.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Per-slot override of `[app].booking_window_days`.
+    #[serde(default)]
+    pub booking_window_days: Option<u32>,
+    /// Per-slot override of `[app].booking_opens_at`.
+    #[serde(default)]
+    pub booking_opens_at: Option<String>,
+    /// Per-slot override of `[app].booking_offset_secs`.
+    #[serde(default)]
+    pub booking_offset_secs: Option<i64>,
+    /// Per-slot override of `[app].book_until_minutes_before`.
+    #[serde(default)]
+    pub book_until_minutes_before: Option<u32>,
+    /// Extra people booked alongside the user (sent as `n_guests`).
+    #[serde(default)]
+    pub guests: u32,
+    /// Preference among a user's days whose windows open together; lower books first.
     #[serde(default)]
-    pub activity: Option<String>,
+    pub priority: Option<u32>,
+    /// The one `category_activity_id` this day's classes are listed under,
+    /// instead of all of the gym's.
+    #[serde(default)]
+    pub category: Option<String>,
+    /// With fewer free spots than this, the scheduler joins the waiting
+    /// list (if the user does) instead of taking one of the last places.
+    #[serde(default)]
+    pub min_free: Option<u32>,
+    /// Classes whose name contains any of these (case-insensitive) are never
+    /// booked, whatever `activity` matches.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl SlotConfig {
+    /// A slot at `time` with no activity filter and default settings.
+    pub fn new(time: &str) -> Self {
+        Self {
+            time: Choices::Single(normalize_time(time)),
+            activity: None,
+            id_activity: None,
+            coach: None,
+            enabled: true,
+            booking_window_days: None,
+            booking_opens_at: None,
+            booking_offset_secs: None,
+            book_until_minutes_before: None,
+            guests: 0,
+            priority: None,
+            category: None,
+            min_free: None,
+            exclude: Vec::new(),
+        }
+    }
+
+    /// The activity filters to try at each time, most preferred first;
+    /// `[None]` takes any activity.
+    pub fn activities(&self) -> Vec<Option<&str>> {
+        match &self.activity {
+            Some(a) => a.all().iter().map(|a| Some(a.as_str())).collect(),
+            None => vec![None],
+        }
+    }
+
+    /// The activities for log lines and messages, "any" when unset.
+    pub fn activity_label(&self) -> String {
+        self.activity
+            .as_ref()
+            .map_or_else(|| "any".to_string(), ToString::to_string)
+    }
+}
+
+/// A slot time as `HH:MM:SS`, so "18:00", "18:00:00" and "6:00 PM" are the
+/// same time. Times that don't parse are kept for validation to report.
+fn normalize_time(time: &str) -> String {
+    scheduler::parse_time(time)
+        .map_or_else(|| time.to_string(), |t| t.format("%H:%M:%S").to_string())
+}
+
+fn deserialize_times<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Choices, D::Error> {
+    Ok(match Choices::deserialize(deserializer)? {
+        Choices::Single(time) => Choices::Single(normalize_time(&time)),
+        Choices::Multiple(times) => {
+            Choices::Multiple(times.iter().map(|t| normalize_time(t)).collect())
+        }
+    })
+}
+
+/// `activity = ""` (or a list of only blanks) filters nothing, as if unset.
+fn deserialize_activity<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Choices>, D::Error> {
+    Ok(match Option::<Choices>::deserialize(deserializer)? {
+        Some(Choices::Single(a)) if a.trim().is_empty() => None,
+        Some(Choices::Multiple(all)) => {
+            let all: Vec<String> = all.into_iter().filter(|a| !a.trim().is_empty()).collect();
+            (!all.is_empty()).then_some(Choices::Multiple(all))
+        }
+        other => other,
+    })
+}
+
+/// `[slots]` entries are tables, or a bare time as in older configs
+/// (`monday = "18:00"`), which books any activity at that time. Day names
+/// are trimmed and lowercased.
+fn deserialize_slots<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<HashMap<String, SlotConfig>, D::Error> {
+    struct SlotEntry(SlotConfig);
+
+    impl<'de> Deserialize<'de> for SlotEntry {
+        fn deserialize<D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> std::result::Result<Self, D::Error> {
+            struct EntryVisitor;
+
+            impl<'de> Visitor<'de> for EntryVisitor {
+                type Value = SlotConfig;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("a slot time or a slot table")
+                }
+
+                fn visit_str<E: de::Error>(self, time: &str) -> std::result::Result<SlotConfig, E> {
+                    Ok(SlotConfig::new(time))
+                }
+
+                fn visit_map<A: MapAccess<'de>>(
+                    self,
+                    map: A,
+                ) -> std::result::Result<SlotConfig, A::Error> {
+                    // Keeps the table's own error messages, unlike an untagged enum
+                    SlotConfig::deserialize(de::value::MapAccessDeserializer::new(map))
+                }
+            }
+
+            deserializer.deserialize_any(EntryVisitor).map(SlotEntry)
+        }
+    }
+
+    let mut slots = HashMap::new();
+    for (day, entry) in HashMap::<String, SlotEntry>::deserialize(deserializer)? {
+        let key = day.trim().to_lowercase();
+        if slots.insert(key.clone(), entry.0).is_some() {
+            return Err(de::Error::custom(format!(
+                "{key} is configured twice (as '{day}')"
+            )));
+        }
+    }
+    Ok(slots)
+}
+
+/// A person given as a plain name or as an object with a `name`.
+pub fn person_name(value: &serde_json::Value) -> Option<String> {
+    let name = match value {
+        serde_json::Value::String(s) => s.as_str(),
+        serde_json::Value::Object(obj) => ["name", "full_name"]
+            .iter()
+            .find_map(|k| obj.get(*k).and_then(|v| v.as_str()))?,
+        _ => return None,
+    };
+    Some(name.trim().to_string()).filter(|n| !n.is_empty())
+}
+
+fn deserialize_person<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<String>, D::Error> {
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(value.as_ref().and_then(person_name))
+}
+
+/// Human-readable guest count appended to slot labels, e.g. " (+2 guests)".
+pub fn guest_suffix(guests: u32) -> String {
+    match guests {
+        0 => String::new(),
+        1 => " (+1 guest)".to_string(),
+        n => format!(" (+{n} guests)"),
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// One value, or several in priority order (`time = "18:00"` or
+/// `time = ["18:00", "19:00"]`); used for slot times, activities and
+/// activity categories.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Choices {
+    Single(String),
+    Multiple(Vec<String>),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl Choices {
+    /// All configured values, most preferred first.
+    pub fn all(&self) -> &[String] {
+        match self {
+            Choices::Single(t) => std::slice::from_ref(t),
+            Choices::Multiple(ts) => ts,
+        }
+    }
+
+    /// The most preferred value.
+    pub fn primary(&self) -> &str {
+        self.all().first().map(String::as_str).unwrap_or_default()
+    }
+}
+
+impl Default for Choices {
+    fn default() -> Self {
+        Choices::Single(String::new())
+    }
+}
+
+impl fmt::Display for Choices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.all().join(" / "))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct AppConfig {
+    /// Gym IDs for users without a `gym`; may be left out when `[gyms]` is used.
+    #[serde(default)]
     pub application_id: String,
-    pub category_activity_id: String,
+    /// Activity category, or several whose slots are merged.
+    #[serde(default)]
+    pub category_activity_id: Choices,
+    /// How many days ahead of a class its bookings open.
+    #[serde(default = "default_booking_window_days")]
+    pub booking_window_days: u32,
+    /// Fixed clock time (e.g. "20:00") bookings open at. When unset, bookings
+    /// open `booking_offset_secs` after the class start time.
+    #[serde(default)]
+    pub booking_opens_at: Option<String>,
+    /// Seconds after the class start time bookings open when there is no
+    /// `booking_opens_at`; zero or negative for boxes opening on the dot or earlier.
+    #[serde(default = "default_booking_offset_secs")]
+    pub booking_offset_secs: i64,
+    /// On a class's weekday, target that day's class while it hasn't started
+    /// instead of the next week's.
+    #[serde(default)]
+    pub allow_same_day: bool,
+    /// The waiting-list watcher won't take a freed spot this close to the
+    /// class start.
+    #[serde(default)]
+    pub book_until_minutes_before: Option<u32>,
+    /// IANA name of the gym's timezone (e.g. "Europe/Madrid"); Europe/Berlin by default.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// When a waiting-list class has free spots, notify instead of booking.
+    #[serde(default)]
+    pub watch_only: bool,
+    /// When every preferred slot is full, join the last one's waiting list.
+    #[serde(default = "default_true")]
+    pub join_waiting_list: bool,
+    /// After `book` joins a waiting list, keep checking it this many minutes
+    /// for a freed spot to book; `0` leaves that to `serve`.
+    #[serde(default)]
+    pub waiting_list_poll_minutes: u32,
+    /// Delay between consecutive users' bookings when a window opens.
+    #[serde(default)]
+    pub stagger_ms: u64,
+    /// Up to this long, at random, before a scheduled booking logs in, so
+    /// tasks woken by the same window don't all log in together.
+    #[serde(default)]
+    pub login_jitter_ms: u64,
+    /// Warn at startup when the gym server's clock is this far from ours.
+    #[serde(default = "default_clock_skew_warn_secs")]
+    pub clock_skew_warn_secs: u64,
+    /// Time the scheduled bookings by the gym server's clock, as measured at
+    /// startup, instead of by ours.
+    #[serde(default)]
+    pub follow_server_clock: bool,
+    /// Booking attempts the `serve` scheduler runs at the same time.
+    #[serde(default = "default_max_concurrent_bookings")]
+    pub max_concurrent_bookings: usize,
+    /// For this long after a window opens, a slot that isn't listed yet is
+    /// looked up again every `slot_retry_interval_ms` instead of every minute.
+    #[serde(default = "default_slot_retry_burst_secs")]
+    pub slot_retry_burst_secs: u64,
+    #[serde(default = "default_slot_retry_interval_ms")]
+    pub slot_retry_interval_ms: u64,
+    /// Retries for slot fetches and bookings that hit network errors or 5xx responses.
+    #[serde(default = "default_retry_attempts")]
+    pub retry_attempts: u32,
+    /// Delay before the first retry, doubled on each further attempt.
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+    /// After this many booking errors in a row for a user, `serve` retries
+    /// that user's bookings less often (`0` disables the backoff).
+    #[serde(default = "default_error_backoff_after")]
+    pub error_backoff_after: u32,
+    /// Retry intervals while backing off, widening with each further error.
+    #[serde(default = "default_error_backoff_minutes")]
+    pub error_backoff_minutes: Vec<u64>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl AppConfig {
+    /// The gym's timezone: API timestamps, slot times and booking windows
+    /// are all wall-clock times in this zone. Loading rejects an unknown
+    /// `timezone`, so the CET fallback is only for `[app].timezone` unset.
+    pub fn tz(&self) -> Tz {
+        self.timezone
+            .as_deref()
+            .and_then(|name| name.parse().ok())
+            .unwrap_or(scheduler::CET)
+    }
+}
+
+/// The Nubapp IDs identifying one gym.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct GymConfig {
+    pub application_id: String,
+    pub category_activity_id: Choices,
+}
+
+impl Config {
+    /// IDs of the gym called `name`. Without a name, the `[app]` IDs are used
+    /// when set, otherwise the first of `[gyms]`.
+    pub fn gym(&self, name: Option<&str>) -> Result<GymConfig> {
+        if let Some(name) = name {
+            return self
+                .gyms
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown gym '{}', not defined under [gyms]", name));
+        }
+        if !self.app.application_id.is_empty() {
+            return Ok(GymConfig {
+                application_id: self.app.application_id.clone(),
+                category_activity_id: self.app.category_activity_id.clone(),
+            });
+        }
+        self.gyms.values().next().cloned().ok_or_else(|| {
+            anyhow!("No gym configured: set [app].application_id or add a [gyms.<name>] table")
+        })
+    }
+}
+
+fn default_booking_window_days() -> u32 {
+    7
+}
+
+fn default_booking_offset_secs() -> i64 {
+    60
+}
+
+fn default_clock_skew_warn_secs() -> u64 {
+    2
+}
+
+fn default_max_concurrent_bookings() -> usize {
+    2
+}
+
+fn default_slot_retry_burst_secs() -> u64 {
+    30
+}
+
+fn default_slot_retry_interval_ms() -> u64 {
+    1500
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_base_delay_ms() -> u64 {
+    200
+}
+
+fn default_error_backoff_after() -> u32 {
+    3
+}
+
+fn default_error_backoff_minutes() -> Vec<u64> {
+    vec![5, 15, 60]
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct User {
     pub name: String,
     pub login: String,
     pub password: String,
     pub slots: Vec<String>,
+    /// Key into `[gyms]`; defaults to the `[app]` IDs or the first gym.
+    #[serde(default)]
+    pub gym: Option<String>,
+    /// Per-user override of `[app].watch_only`.
+    #[serde(default)]
+    pub watch_only: Option<bool>,
+    /// Per-user override of `[app].join_waiting_list`.
+    #[serde(default)]
+    pub join_waiting_list: Option<bool>,
+    /// Most classes the scheduler books for this user in one Monday-to-Sunday week.
+    #[serde(default)]
+    pub max_per_week: Option<u32>,
+    /// Where this user's alerts go instead of the shared `[notifications]` targets.
+    #[serde(default)]
+    pub notify: Option<UserNotify>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Per-user alert targets; each one set replaces the shared target of that backend.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+pub struct UserNotify {
+    /// Telegram chat to message, through the `[notifications.telegram]` bot.
+    #[serde(default)]
+    pub telegram_chat_id: Option<String>,
+    #[serde(default)]
+    pub discord_webhook_url: Option<String>,
+}
+
+impl User {
+    /// Whether `name` is this user's `name` or `login`, ignoring case.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.login.eq_ignore_ascii_case(name)
+    }
+
+    /// Whether the waiting-list watcher only alerts this user about free spots.
+    pub fn watch_only(&self, app: &AppConfig) -> bool {
+        self.watch_only.unwrap_or(app.watch_only)
+    }
+
+    /// Whether a full class gets this user onto its waiting list.
+    pub fn joins_waiting_list(&self, app: &AppConfig) -> bool {
+        self.join_waiting_list.unwrap_or(app.join_waiting_list)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Slot {
     #[serde(alias = "start_timestamp", alias = "start")]
     pub start: String,
     #[serde(alias = "end_timestamp", alias = "end")]
     pub end: String,
     pub id_activity_calendar: serde_json::Value,
+    /// The activity type (WOD, Open Gym, ...), shared by all its classes.
+    #[serde(default, alias = "activity_id", alias = "id_activity_type")]
+    pub id_activity: serde_json::Value,
     #[serde(default, alias = "name_activity")]
     pub name: Option<String>,
+    /// Coach/instructor, under whichever name the gym's API uses.
+    #[serde(
+        default,
+        alias = "teacher",
+        alias = "name_coach",
+        alias = "coach_name",
+        alias = "name_teacher",
+        alias = "instructor",
+        deserialize_with = "deserialize_person"
+    )]
+    pub coach: Option<String>,
     #[serde(default)]
     pub n_inscribed: Option<u32>,
     #[serde(default)]
     pub n_capacity: Option<u32>,
+    /// People on the waiting list and how many it takes, when the gym reports them.
+    #[serde(
+        default,
+        alias = "n_inscribed_waiting_list",
+        alias = "waiting_list_count"
+    )]
+    pub n_waiting_list: Option<u32>,
+    #[serde(default, alias = "waiting_list_capacity", alias = "max_waiting_list")]
+    pub n_capacity_waiting_list: Option<u32>,
+}
+
+impl Slot {
+    /// `id_activity_calendar` as plain text, whether the API sent a number or a string.
+    pub fn calendar_id(&self) -> String {
+        id_string(&self.id_activity_calendar)
+    }
+
+    /// `id_activity` as plain text, `None` when the gym didn't send one.
+    pub fn activity_id(&self) -> Option<String> {
+        Some(id_string(&self.id_activity)).filter(|id| !id.is_empty())
+    }
+
+    /// Places still free, `None` unless the gym reports both counts.
+    pub fn free_spots(&self) -> Option<u32> {
+        Some(self.n_capacity?.saturating_sub(self.n_inscribed?))
+    }
+
+    /// Whether at least `min` places are free. A slot without counts
+    /// passes, having nothing to judge it by.
+    pub fn has_free_spots(&self, min: u32) -> bool {
+        self.free_spots().is_none_or(|free| free >= min)
+    }
+
+    /// Whether the gym reports the waiting list as full, or closed with no
+    /// places at all. `false` when it doesn't report either count.
+    pub fn waiting_list_full(&self) -> bool {
+        matches!(
+            (self.n_waiting_list, self.n_capacity_waiting_list),
+            (Some(waiting), Some(capacity)) if waiting >= capacity
+        )
+    }
+}
+
+/// A class the user is booked into, as listed by `get_bookings`. The listing
+/// is looser than `get_slots`, so every field is optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Booking {
+    #[serde(default)]
+    pub id_activity_calendar: serde_json::Value,
+    #[serde(
+        default,
+        alias = "start_timestamp",
+        deserialize_with = "deserialize_text"
+    )]
+    pub start: String,
+    #[serde(
+        default,
+        alias = "end_timestamp",
+        deserialize_with = "deserialize_text"
+    )]
+    pub end: String,
+    #[serde(default, alias = "name_activity")]
+    pub name: Option<String>,
+    #[serde(
+        default,
+        alias = "teacher",
+        alias = "name_coach",
+        alias = "coach_name",
+        alias = "name_teacher",
+        alias = "instructor",
+        deserialize_with = "deserialize_person"
+    )]
+    pub coach: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_count")]
+    pub n_inscribed: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_count")]
+    pub n_capacity: Option<u32>,
+}
+
+impl Booking {
+    /// `id_activity_calendar` as plain text, `None` when the entry has none.
+    pub fn calendar_id(&self) -> Option<String> {
+        Some(id_string(&self.id_activity_calendar)).filter(|id| !id.is_empty())
+    }
+
+    /// The day the class starts on.
+    pub fn date(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(self.start.get(..10)?, "%Y-%m-%d").ok()
+    }
+
+    /// The activity name for display, "?" when the gym didn't send one.
+    pub fn activity(&self) -> &str {
+        self.name.as_deref().map(str::trim).unwrap_or("?")
+    }
+}
+
+impl AsRef<Booking> for Booking {
+    fn as_ref(&self) -> &Booking {
+        self
+    }
+}
+
+/// A place on a class's waiting list: the class itself and, when the gym
+/// reports it, the user's position in the queue.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "serde_json::Value")]
+pub struct WaitingEntry {
+    pub class: Booking,
+    pub position: Option<u32>,
+}
+
+impl AsRef<Booking> for WaitingEntry {
+    fn as_ref(&self) -> &Booking {
+        &self.class
+    }
+}
+
+impl TryFrom<serde_json::Value> for WaitingEntry {
+    type Error = serde_json::Error;
+
+    // Not serde aliases: some gyms send several position fields, some null.
+    fn try_from(value: serde_json::Value) -> std::result::Result<Self, Self::Error> {
+        const POSITION_KEYS: [&str; 5] = [
+            "position",
+            "waiting_list_position",
+            "position_waiting_list",
+            "order",
+            "priority",
+        ];
+        let position = POSITION_KEYS
+            .iter()
+            .find_map(|k| value.get(*k).and_then(count_value));
+        Ok(Self {
+            class: serde_json::from_value(value)?,
+            position,
+        })
+    }
+}
+
+/// A parsed `get_bookings` response.
+#[derive(Debug, Clone, Default)]
+pub struct BookingsResponse {
+    pub bookings: Vec<Booking>,
+    pub waiting_list: Vec<WaitingEntry>,
+}
+
+/// A trimmed string that may be missing or null.
+fn deserialize_text<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<String, D::Error> {
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.map(|s| s.trim().to_string()).unwrap_or_default())
+}
+
+/// A count sent as a number or a numeric string; anything else is `None`.
+fn deserialize_count<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<u32>, D::Error> {
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(value.as_ref().and_then(count_value))
+}
+
+fn count_value(value: &serde_json::Value) -> Option<u32> {
+    match value {
+        serde_json::Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// A JSON ID as plain text: numbers as their digits, strings without
+/// surrounding whitespace or stray quotes (some gyms send `"\"123\""`).
+pub fn id_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.trim().trim_matches('"').to_string(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_time_single_string() {
+        let cfg: SlotConfig = toml::from_str(r#"time = "18:00:00""#).unwrap();
+        assert_eq!(cfg.time.all(), ["18:00:00"]);
+        assert_eq!(cfg.time.to_string(), "18:00:00");
+    }
+
+    #[test]
+    fn test_slot_time_list() {
+        let cfg: SlotConfig = toml::from_str(r#"time = ["18:00:00", "19:00:00"]"#).unwrap();
+        assert_eq!(cfg.time.all(), ["18:00:00", "19:00:00"]);
+        assert_eq!(cfg.time.primary(), "18:00:00");
+        assert_eq!(cfg.time.to_string(), "18:00:00 / 19:00:00");
+    }
+
+    #[test]
+    fn test_guests_default_and_suffix() {
+        let cfg: SlotConfig = toml::from_str(r#"time = "18:00:00""#).unwrap();
+        assert_eq!(cfg.guests, 0);
+        let cfg: SlotConfig = toml::from_str("time = \"18:00:00\"\nguests = 2").unwrap();
+        assert_eq!(cfg.guests, 2);
+
+        assert_eq!(guest_suffix(0), "");
+        assert_eq!(guest_suffix(1), " (+1 guest)");
+        assert_eq!(guest_suffix(2), " (+2 guests)");
+    }
+
+    #[test]
+    fn test_slots_accept_bare_time_or_table() {
+        let cfg: Config = toml::from_str(
+            r#"
+            users = []
+            [app]
+            [slots]
+            monday = "18:00"
+            tuesday = { time = "19:30:00", activity = "Weightlifting" }
+            [slots.friday]
+            time = ["18:00:00", "19:00:00"]
+            guests = 1
+            activity = ["WOD", "Gymnastics"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.slots["monday"].time.all(), ["18:00:00"]);
+        assert!(cfg.slots["monday"].activity.is_none());
+        assert!(cfg.slots["monday"].enabled);
+        assert_eq!(cfg.slots["tuesday"].time.primary(), "19:30:00");
+        assert_eq!(cfg.slots["tuesday"].activities(), [Some("Weightlifting")]);
+        assert_eq!(cfg.slots["friday"].guests, 1);
+        assert_eq!(
+            cfg.slots["friday"].activities(),
+            [Some("WOD"), Some("Gymnastics")]
+        );
+        assert_eq!(cfg.slots["friday"].activity_label(), "WOD / Gymnastics");
+        assert_eq!(cfg.slots["monday"].activities(), [None]);
+        assert_eq!(cfg.slots["monday"].activity_label(), "any");
+
+        let err =
+            toml::from_str::<Config>("users = []\n[app]\n[slots]\nmonday = { activity = \"WOD\" }")
+                .unwrap_err();
+        assert!(err.to_string().contains("missing field `time`"), "{err}");
+    }
+
+    #[test]
+    fn test_slots_normalized() {
+        let cfg: Config = toml::from_str(
+            r#"
+            users = []
+            [app]
+            [slots]
+            " Monday " = "6:30 PM"
+            tuesday = { time = ["7:00", "19:30:00", "18h"], activity = "" }
+            wednesday = { time = "18:00", activity = ["", "WOD"] }
+            thursday = { time = "18:00", activity = [" "] }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.slots["monday"], SlotConfig::new("18:30:00"));
+        // Unparseable times are left for validation to reject
+        assert_eq!(
+            cfg.slots["tuesday"].time.all(),
+            ["07:00:00", "19:30:00", "18h"]
+        );
+        assert!(cfg.slots["tuesday"].activity.is_none());
+        assert_eq!(cfg.slots["wednesday"].activities(), [Some("WOD")]);
+        assert!(cfg.slots["thursday"].activity.is_none());
+        assert_eq!(SlotConfig::new("18:00").time.primary(), "18:00:00");
+
+        let err = toml::from_str::<Config>(
+            "users = []\n[app]\n[slots]\nmonday = \"18:00\"\nMonday = \"19:00\"",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("monday is configured twice"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_slot_coach_field_names() {
+        let slot: Slot = serde_json::from_value(serde_json::json!({
+            "start": "2024-01-15 18:00:00", "end": "", "id_activity_calendar": 1,
+            "teacher": {"id": 3, "name": " Ana "}
+        }))
+        .unwrap();
+        assert_eq!(slot.coach.as_deref(), Some("Ana"));
+        let slot: Slot = serde_json::from_value(serde_json::json!({
+            "start": "2024-01-15 18:00:00", "end": "", "id_activity_calendar": 1, "name_coach": 7
+        }))
+        .unwrap();
+        assert!(slot.coach.is_none());
+    }
+
+    #[test]
+    fn test_slot_waiting_list_full() {
+        let slot = |extra: serde_json::Value| {
+            let mut v = serde_json::json!({"start": "", "end": "", "id_activity_calendar": 1});
+            v.as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            serde_json::from_value::<Slot>(v).unwrap()
+        };
+        assert!(!slot(serde_json::json!({})).waiting_list_full());
+        assert!(!slot(serde_json::json!({"n_waiting_list": 3})).waiting_list_full());
+        assert!(
+            !slot(serde_json::json!({"n_waiting_list": 2, "n_capacity_waiting_list": 3}))
+                .waiting_list_full()
+        );
+        assert!(
+            slot(serde_json::json!({"waiting_list_count": 3, "waiting_list_capacity": 3}))
+                .waiting_list_full()
+        );
+        assert!(
+            slot(serde_json::json!({"n_waiting_list": 0, "n_capacity_waiting_list": 0}))
+                .waiting_list_full()
+        );
+    }
+
+    #[test]
+    fn test_slot_calendar_id_forms() {
+        let id = |v: serde_json::Value| {
+            let slot: Slot = serde_json::from_value(serde_json::json!({
+                "start": "", "end": "", "id_activity_calendar": v
+            }))
+            .unwrap();
+            slot.calendar_id()
+        };
+        assert_eq!(id(serde_json::json!(12345)), "12345");
+        assert_eq!(id(serde_json::json!("12345")), "12345");
+        assert_eq!(id(serde_json::json!(" \"12345\" ")), "12345");
+        assert_eq!(id(serde_json::Value::Null), "");
+    }
+
+    #[test]
+    fn test_booking_field_names() {
+        let booking: Booking = serde_json::from_value(serde_json::json!({
+            "id_activity_calendar": "42",
+            "start_timestamp": " 2024-01-15 18:00:00 ",
+            "end_timestamp": null,
+            "name_activity": "WOD",
+            "teacher": {"name": "Ana"},
+            "n_inscribed": "7",
+            "n_capacity": 12,
+        }))
+        .unwrap();
+        assert_eq!(booking.calendar_id().as_deref(), Some("42"));
+        assert_eq!(booking.start, "2024-01-15 18:00:00");
+        assert_eq!(booking.end, "");
+        assert_eq!(booking.date(), NaiveDate::from_ymd_opt(2024, 1, 15));
+        assert_eq!(booking.activity(), "WOD");
+        assert_eq!(booking.coach.as_deref(), Some("Ana"));
+        assert_eq!(
+            (booking.n_inscribed, booking.n_capacity),
+            (Some(7), Some(12))
+        );
+
+        let bare: Booking = serde_json::from_value(serde_json::json!({"start": "soon"})).unwrap();
+        assert_eq!(bare.calendar_id(), None);
+        assert_eq!(bare.date(), None);
+        assert_eq!(bare.activity(), "?");
+    }
+
+    #[test]
+    fn test_free_spots() {
+        let slot = |counts: serde_json::Value| -> Slot {
+            let mut json = serde_json::json!({"start": "2024-01-10 18:00:00", "end": "", "id_activity_calendar": 1});
+            json.as_object_mut()
+                .unwrap()
+                .extend(counts.as_object().unwrap().clone());
+            serde_json::from_value(json).unwrap()
+        };
+        let open = slot(serde_json::json!({"n_inscribed": 7, "n_capacity": 10}));
+        assert_eq!(open.free_spots(), Some(3));
+        assert!(open.has_free_spots(3));
+        assert!(!open.has_free_spots(4));
+        // Overbooked classes have none free rather than wrapping around
+        let over = slot(serde_json::json!({"n_inscribed": 12, "n_capacity": 10}));
+        assert_eq!(over.free_spots(), Some(0));
+        assert!(!over.has_free_spots(1));
+        // Without both counts there is nothing to filter on
+        for counts in [
+            serde_json::json!({}),
+            serde_json::json!({"n_capacity": 10}),
+            serde_json::json!({"n_inscribed": 3}),
+        ] {
+            let slot = slot(counts);
+            assert_eq!(slot.free_spots(), None);
+            assert!(slot.has_free_spots(5));
+        }
+    }
+
+    #[test]
+    fn test_waiting_entry_position() {
+        let position =
+            |v: serde_json::Value| serde_json::from_value::<WaitingEntry>(v).unwrap().position;
+        assert_eq!(position(serde_json::json!({"position": 3})), Some(3));
+        assert_eq!(position(serde_json::json!({"order": "2"})), Some(2));
+        assert_eq!(
+            position(serde_json::json!({"position": null, "priority": 5})),
+            Some(5)
+        );
+        assert_eq!(position(serde_json::json!({"name": "WOD"})), None);
+
+        let entry: WaitingEntry = serde_json::from_value(serde_json::json!({
+            "id_activity_calendar": 9, "start": "2024-01-15 18:00:00", "waiting_list_position": 1
+        }))
+        .unwrap();
+        assert_eq!(entry.class.calendar_id().as_deref(), Some("9"));
+        assert_eq!(entry.position, Some(1));
+    }
+
+    #[test]
+    fn test_api_config_partial_override() {
+        let api: ApiConfig = toml::from_str(r#"app_version = "6.0.0""#).unwrap();
+        assert_eq!(api.app_version, "6.0.0");
+        assert_eq!(api.base_url, ApiConfig::default().base_url);
+    }
+
+    #[test]
+    fn test_gym_resolution() {
+        let cfg: Config = toml::from_str(
+            r#"
+            users = []
+            slots = {}
+            [app]
+            [gyms.downtown]
+            application_id = "1"
+            category_activity_id = "10"
+            [gyms.uptown]
+            application_id = "2"
+            category_activity_id = ["20", "21"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.gym(Some("uptown")).unwrap().application_id, "2");
+        assert_eq!(
+            cfg.gym(Some("uptown")).unwrap().category_activity_id.all(),
+            ["20", "21"]
+        );
+        // No [app] IDs: falls back to the first gym by name
+        assert_eq!(cfg.gym(None).unwrap().application_id, "1");
+        assert!(cfg.gym(Some("elsewhere")).is_err());
+
+        let legacy: Config = toml::from_str(
+            r#"
+            users = []
+            slots = {}
+            [app]
+            application_id = "5"
+            category_activity_id = "50"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(legacy.gym(None).unwrap().category_activity_id.all(), ["50"]);
+    }
 }
@@ -0,0 +1,321 @@
+use anyhow::{bail, Context, Result};
+use tracing::{debug, warn};
+
+use crate::models::{DiscordConfig, NotificationsConfig, NtfyConfig, TelegramConfig, User};
+
+const TELEGRAM_API: &str = "https://api.telegram.org";
+
+/// Discord rejects webhook messages longer than this many characters.
+const DISCORD_MAX_LEN: usize = 2000;
+
+/// Title of ntfy notifications.
+const NTFY_TITLE: &str = "RESAWOD Scheduler";
+
+/// What happened to a slot, for [`format_outcome`].
+pub enum Outcome<'a> {
+    Booked,
+    WaitingList,
+    /// Full, and the gym reports its waiting list full or closed too.
+    WaitingListClosed,
+    /// Full, and the user doesn't join waiting lists.
+    WaitingListDisabled,
+    /// Booked by the watcher after a waiting-list spot freed up.
+    BookedFromWaitingList,
+    /// A waiting-list spot is free but the user is watch-only.
+    SpotFree {
+        inscribed: u32,
+        capacity: u32,
+    },
+    /// The watcher saw a freed waiting-list spot but couldn't book it.
+    FreedSpotFailed(&'a str),
+    /// The API refused the booking, with its message.
+    Failed(&'a str),
+    /// The API refused the booking over the user's membership or payments;
+    /// the day is paused until resumed.
+    MembershipIssue(&'a str),
+    /// The attempt errored before the API answered.
+    Error(&'a str),
+}
+
+/// The alert text for `outcome`, shared by every backend. `slot` describes
+/// the class, e.g. "Wednesday 18:00 on 2024-01-10".
+pub fn format_outcome(user: &str, slot: &str, outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Booked => format!("Booked {slot} for {user}"),
+        Outcome::WaitingList => format!("{slot} is full — {user} joined the waiting list"),
+        Outcome::WaitingListClosed => {
+            format!("{slot} is full and so is its waiting list — not booked for {user}")
+        }
+        Outcome::WaitingListDisabled => {
+            format!("{slot} is full — not booked for {user}, waiting list disabled")
+        }
+        Outcome::BookedFromWaitingList => format!("Booked {slot} for {user} from the waiting list"),
+        Outcome::SpotFree {
+            inscribed,
+            capacity,
+        } => format!(
+            "Spot free at {slot} for {user} ({inscribed}/{capacity} booked) — watch-only, not booked"
+        ),
+        Outcome::FreedSpotFailed(msg) => {
+            format!("Tried to grab freed spot at {slot} for {user} but failed: {msg}")
+        }
+        Outcome::Failed(msg) => format!("Failed to book {slot} for {user}: {msg}"),
+        Outcome::MembershipIssue(msg) => format!(
+            "Membership issue booking {slot} for {user}: {msg} — paused until resumed from the dashboard or a config reload"
+        ),
+        Outcome::Error(msg) => format!("Error booking {slot} for {user}: {msg}"),
+    }
+}
+
+/// The targets `user`'s alerts go to: their own `notify` chat or webhook
+/// where set, the shared `[notifications]` target of that backend otherwise.
+pub fn targets_for(config: &NotificationsConfig, user: &User) -> NotificationsConfig {
+    let Some(own) = &user.notify else {
+        return config.clone();
+    };
+    let telegram = match (&config.telegram, &own.telegram_chat_id) {
+        (Some(tg), Some(chat_id)) => Some(TelegramConfig {
+            chat_id: chat_id.clone(),
+            ..tg.clone()
+        }),
+        (tg, _) => tg.clone(),
+    };
+    let discord = match &own.discord_webhook_url {
+        Some(url) => Some(DiscordConfig {
+            webhook_url: url.clone(),
+        }),
+        None => config.discord.clone(),
+    };
+    NotificationsConfig {
+        telegram,
+        discord,
+        ntfy: config.ntfy.clone(),
+    }
+}
+
+/// Send `message` about `user` to their targets, see [`targets_for`].
+pub async fn notify_user(config: &NotificationsConfig, user: &User, message: &str) {
+    notify(&targets_for(config, user), message).await
+}
+
+/// Send `message` to every configured backend. Does nothing when no backend is
+/// configured; delivery errors are logged and never returned to the caller.
+pub async fn notify(config: &NotificationsConfig, message: &str) {
+    if let Some(ref tg) = config.telegram {
+        if let Err(e) = send_telegram(tg, message).await {
+            warn!("Telegram notification failed: {:#}", e);
+        }
+    }
+    if let Some(ref discord) = config.discord {
+        if let Err(e) = send_discord(discord, message).await {
+            warn!("Discord notification failed: {:#}", e);
+        }
+    }
+    if let Some(ref ntfy) = config.ntfy {
+        if let Err(e) = send_ntfy(ntfy, message).await {
+            warn!("ntfy notification failed: {:#}", e);
+        }
+    }
+}
+
+async fn send_telegram(tg: &TelegramConfig, message: &str) -> Result<()> {
+    let url = format!("{}/bot{}/sendMessage", TELEGRAM_API, tg.bot_token);
+
+    let resp = reqwest::Client::new()
+        .post(&url)
+        .json(&serde_json::json!({ "chat_id": tg.chat_id, "text": message }))
+        .send()
+        .await
+        .context("Failed to send Telegram request")?;
+
+    let status = resp.status();
+    let text = resp
+        .text()
+        .await
+        .context("Failed to read Telegram response")?;
+    debug!("Telegram response (status {}): {}", status, text);
+
+    if !status.is_success() {
+        bail!("Telegram returned status {status}: {text}");
+    }
+    Ok(())
+}
+
+async fn send_discord(discord: &DiscordConfig, message: &str) -> Result<()> {
+    let content: String = message.chars().take(DISCORD_MAX_LEN).collect();
+
+    let resp = reqwest::Client::new()
+        .post(&discord.webhook_url)
+        .json(&serde_json::json!({ "content": content }))
+        .send()
+        .await
+        .context("Failed to send Discord request")?;
+
+    // Discord answers 204 No Content on success
+    let status = resp.status();
+    let text = resp
+        .text()
+        .await
+        .context("Failed to read Discord response")?;
+    debug!("Discord response (status {}): {}", status, text);
+
+    if !status.is_success() {
+        bail!("Discord returned status {status}: {text}");
+    }
+    Ok(())
+}
+
+async fn send_ntfy(ntfy: &NtfyConfig, message: &str) -> Result<()> {
+    let url = format!("{}/{}", ntfy.server_url.trim_end_matches('/'), ntfy.topic);
+
+    let mut req = reqwest::Client::new()
+        .post(&url)
+        .header("Title", NTFY_TITLE)
+        .body(message.to_string());
+    if let Some(ref token) = ntfy.token {
+        req = req.bearer_auth(token);
+    }
+    let resp = req.send().await.context("Failed to send ntfy request")?;
+
+    let status = resp.status();
+    let text = resp.text().await.context("Failed to read ntfy response")?;
+    debug!("ntfy response (status {}): {}", status, text);
+
+    if !status.is_success() {
+        bail!("ntfy returned status {status}: {text}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_outcome() {
+        let slot = "Wednesday 18:00 on 2024-01-10";
+        assert_eq!(
+            format_outcome("Bob", slot, &Outcome::Booked),
+            "Booked Wednesday 18:00 on 2024-01-10 for Bob"
+        );
+        assert_eq!(
+            format_outcome("Bob", slot, &Outcome::WaitingList),
+            "Wednesday 18:00 on 2024-01-10 is full — Bob joined the waiting list"
+        );
+        assert_eq!(
+            format_outcome("Bob", slot, &Outcome::WaitingListClosed),
+            "Wednesday 18:00 on 2024-01-10 is full and so is its waiting list — not booked for Bob"
+        );
+        assert_eq!(
+            format_outcome("Bob", slot, &Outcome::WaitingListDisabled),
+            "Wednesday 18:00 on 2024-01-10 is full — not booked for Bob, waiting list disabled"
+        );
+        assert_eq!(
+            format_outcome("Bob", slot, &Outcome::Failed("Class cancelled")),
+            "Failed to book Wednesday 18:00 on 2024-01-10 for Bob: Class cancelled"
+        );
+        assert_eq!(
+            format_outcome(
+                "Bob",
+                "2024-01-10 18:00:00",
+                &Outcome::FreedSpotFailed("Class is full")
+            ),
+            "Tried to grab freed spot at 2024-01-10 18:00:00 for Bob but failed: Class is full"
+        );
+    }
+
+    #[test]
+    fn test_targets_for_user() {
+        let shared = NotificationsConfig {
+            telegram: Some(TelegramConfig {
+                bot_token: "token".into(),
+                chat_id: "household".into(),
+            }),
+            discord: Some(DiscordConfig {
+                webhook_url: "https://discord.test/shared".into(),
+            }),
+            ntfy: None,
+        };
+        let mut user: User = toml::from_str(
+            r#"
+            name = "Bob"
+            login = "bob@example.com"
+            password = "x"
+            slots = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(targets_for(&shared, &user), shared);
+
+        user.notify = Some(crate::models::UserNotify {
+            telegram_chat_id: Some("bob".into()),
+            discord_webhook_url: None,
+        });
+        let targets = targets_for(&shared, &user);
+        let tg = targets.telegram.unwrap();
+        assert_eq!(
+            (tg.bot_token.as_str(), tg.chat_id.as_str()),
+            ("token", "bob")
+        );
+        assert_eq!(targets.discord, shared.discord);
+
+        // Without a shared bot there is no Telegram target; Discord needs none
+        user.notify = Some(crate::models::UserNotify {
+            telegram_chat_id: Some("bob".into()),
+            discord_webhook_url: Some("https://discord.test/bob".into()),
+        });
+        let targets = targets_for(&NotificationsConfig::default(), &user);
+        assert!(targets.telegram.is_none());
+        assert_eq!(
+            targets.discord.unwrap().webhook_url,
+            "https://discord.test/bob"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_discord_webhook_receives_content() {
+        use wiremock::matchers::{body_json, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/webhooks/1/abc"))
+            .and(body_json(serde_json::json!({ "content": "Booked" })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let discord = DiscordConfig {
+            webhook_url: format!("{}/api/webhooks/1/abc", server.uri()),
+        };
+        send_discord(&discord, "Booked").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ntfy_topic_receives_message() {
+        use wiremock::matchers::{body_string, header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/bookings"))
+            .and(header("Title", NTFY_TITLE))
+            .and(header("Authorization", "Bearer tk_secret"))
+            .and(body_string("Booked"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let ntfy: NtfyConfig = toml::from_str(&format!(
+            "server_url = \"{}/\"\ntopic = \"bookings\"\ntoken = \"tk_secret\"",
+            server.uri()
+        ))
+        .unwrap();
+        send_ntfy(&ntfy, "Booked").await.unwrap();
+
+        let default: NtfyConfig = toml::from_str("topic = \"bookings\"").unwrap();
+        assert_eq!(default.server_url, "https://ntfy.sh");
+    }
+}
@@ -1,12 +1,72 @@
-use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
+    Weekday,
+};
 use chrono_tz::Tz;
 
+use anyhow::{anyhow, Result};
+
+use crate::models::{AppConfig, Config, SlotConfig};
+
 /// Central European Time (handles CET/CEST daylight saving automatically).
+/// The default when `[app].timezone` isn't set.
 pub const CET: Tz = chrono_tz::Europe::Berlin;
 
-/// Returns the current time in CET.
-pub fn now() -> DateTime<Tz> {
-    Utc::now().with_timezone(&CET)
+/// Returns the current time in `tz`, the gym's timezone (see [`AppConfig::tz`]).
+pub fn now(tz: Tz) -> DateTime<Tz> {
+    Utc::now().with_timezone(&tz)
+}
+
+/// Source of the current time. Injected into the scheduler tasks so that
+/// tests can drive time manually instead of waiting on the wall clock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Tz>;
+}
+
+/// The real wall clock, in the gym's timezone.
+pub struct SystemClock(pub Tz);
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Tz> {
+        now(self.0)
+    }
+}
+
+/// The wall clock moved by a fixed offset, to follow another machine's clock.
+pub struct OffsetClock(pub Duration, pub Tz);
+
+impl Clock for OffsetClock {
+    fn now(&self) -> DateTime<Tz> {
+        now(self.1) + self.0
+    }
+}
+
+/// A clock offset as shown to users, e.g. "+1.2s" or "-0.4s".
+pub fn format_offset(offset: Duration) -> String {
+    format!("{:+.1}s", offset.num_milliseconds() as f64 / 1000.0)
+}
+
+/// A manually advanced clock for tests.
+#[cfg(test)]
+pub struct MockClock(std::sync::Mutex<DateTime<Tz>>);
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(start: DateTime<Tz>) -> Self {
+        Self(std::sync::Mutex::new(start))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.0.lock().unwrap();
+        *now += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Tz> {
+        *self.0.lock().unwrap()
+    }
 }
 
 /// Returns the next occurrence of the given weekday strictly after `from`.
@@ -22,42 +82,228 @@ pub fn next_weekday(from: NaiveDate, target: Weekday) -> NaiveDate {
     from + Duration::days(days_ahead as i64)
 }
 
-/// Parse a day name (e.g. "monday") into a chrono Weekday.
+/// The date of the next `target` class as seen from `now`: as [`next_weekday`],
+/// except that with `same_day_until` set, today while `now` is before it.
+pub fn next_class_date(
+    now: NaiveDateTime,
+    target: Weekday,
+    same_day_until: Option<NaiveTime>,
+) -> NaiveDate {
+    match same_day_until {
+        Some(until) if now.weekday() == target && now.time() < until => now.date(),
+        _ => next_weekday(now.date(), target),
+    }
+}
+
+/// The class time before which `[app].allow_same_day` targets today's class,
+/// or `None` when it is off.
+pub fn same_day_until(app: &AppConfig, slot_cfg: &SlotConfig) -> Option<NaiveTime> {
+    app.allow_same_day
+        .then(|| parse_time(slot_cfg.time.primary()))
+        .flatten()
+}
+
+/// Parse a day name into a chrono Weekday, case-insensitively: English in
+/// full ("monday") or abbreviated ("mon"), or Spanish ("lunes", with or
+/// without accents).
 pub fn parse_weekday(day: &str) -> Option<Weekday> {
-    match day.to_lowercase().as_str() {
-        "monday" => Some(Weekday::Mon),
-        "tuesday" => Some(Weekday::Tue),
-        "wednesday" => Some(Weekday::Wed),
-        "thursday" => Some(Weekday::Thu),
-        "friday" => Some(Weekday::Fri),
-        "saturday" => Some(Weekday::Sat),
-        "sunday" => Some(Weekday::Sun),
+    match day.trim().to_lowercase().as_str() {
+        "monday" | "mon" | "lunes" => Some(Weekday::Mon),
+        "tuesday" | "tue" | "martes" => Some(Weekday::Tue),
+        "wednesday" | "wed" | "miércoles" | "miercoles" => Some(Weekday::Wed),
+        "thursday" | "thu" | "jueves" => Some(Weekday::Thu),
+        "friday" | "fri" | "viernes" => Some(Weekday::Fri),
+        "saturday" | "sat" | "sábado" | "sabado" => Some(Weekday::Sat),
+        "sunday" | "sun" | "domingo" => Some(Weekday::Sun),
         _ => None,
     }
 }
 
-/// Compute start and end UNIX timestamps for a given date in CET.
-/// Start = 00:00:00 CET, End = 22:00:00 CET on the given date.
-pub fn day_timestamps(date: NaiveDate) -> (i64, i64) {
-    let start = date
-        .and_time(NaiveTime::from_hms_opt(0, 0, 0).unwrap())
-        .and_local_timezone(CET)
-        .earliest()
-        .unwrap()
-        .timestamp();
-    let end = date
-        .and_time(NaiveTime::from_hms_opt(22, 0, 0).unwrap())
-        .and_local_timezone(CET)
-        .earliest()
-        .unwrap()
-        .timestamp();
-    (start, end)
+/// The full English name `[slots]` keys are stored under, for any name
+/// [`parse_weekday`] accepts.
+pub fn canonical_day(day: &str) -> Option<&'static str> {
+    Some(match parse_weekday(day)? {
+        Weekday::Mon => "monday",
+        Weekday::Tue => "tuesday",
+        Weekday::Wed => "wednesday",
+        Weekday::Thu => "thursday",
+        Weekday::Fri => "friday",
+        Weekday::Sat => "saturday",
+        Weekday::Sun => "sunday",
+    })
+}
+
+/// The weekday of a `[slots]` key: a day name, optionally followed by `-`
+/// and a label for another class that day ("monday-2", "monday-evening").
+pub fn slot_weekday(key: &str) -> Option<Weekday> {
+    parse_weekday(key.split_once('-').map_or(key, |(day, _)| day))
+}
+
+/// A `[slots]` key with its day name in full (see [`canonical_day`]) and
+/// its label, if any, lowercased: "Lunes-2" → "monday-2".
+pub fn canonical_slot_key(key: &str) -> Option<String> {
+    let Some((day, label)) = key.split_once('-') else {
+        return canonical_day(key).map(str::to_string);
+    };
+    let label = label.trim().to_lowercase();
+    if label.is_empty() {
+        return None;
+    }
+    Some(format!("{}-{label}", canonical_day(day)?))
+}
+
+/// Clock time formats accepted in the config and on the command line.
+const TIME_FORMATS: [&str; 4] = ["%H:%M:%S", "%H:%M", "%I:%M:%S %p", "%I:%M %p"];
+
+/// Parse a clock time given as "HH:MM", "HH:MM:SS" (the hour may drop its
+/// leading zero) or in 12-hour form, "6:00 PM" or "6:30pm".
+pub fn parse_slot_time(time: &str) -> Result<NaiveTime> {
+    let trimmed = time.trim();
+    // "6:30pm" → "6:30 pm"
+    let spaced = match trimmed
+        .len()
+        .checked_sub(2)
+        .filter(|&i| trimmed.is_char_boundary(i))
+    {
+        Some(i) if ["am", "pm"].contains(&trimmed[i..].to_lowercase().as_str()) => {
+            format!("{} {}", trimmed[..i].trim_end(), &trimmed[i..])
+        }
+        _ => trimmed.to_string(),
+    };
+    TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(&spaced, format).ok())
+        .ok_or_else(|| {
+            anyhow!("'{time}' is not a valid time, expected HH:MM, HH:MM:SS or h:mm AM/PM")
+        })
+}
+
+/// [`parse_slot_time`], for callers that only need to know whether it parsed.
+pub fn parse_time(time: &str) -> Option<NaiveTime> {
+    parse_slot_time(time).ok()
+}
+
+/// The time of day of a slot start, given as a full "YYYY-MM-DD HH:MM:SS"
+/// timestamp (with a space or a `T`) or as a bare time.
+pub fn start_time(start: &str) -> Option<NaiveTime> {
+    let start = start.trim();
+    parse_time(start).or_else(|| parse_time(start.rsplit([' ', 'T']).next().unwrap_or(start)))
+}
+
+/// Whether a class starting at `start` is the one wanted at `time`. Both
+/// are compared as times of day, so "8:00" doesn't match an 18:00 class;
+/// when both carry a date, the dates must match too.
+pub fn starts_at(start: &str, time: &str) -> bool {
+    let date = |s: &str| {
+        NaiveDateTime::parse_from_str(s.trim(), "%Y-%m-%d %H:%M:%S")
+            .ok()
+            .map(|t| t.date())
+    };
+    match (start_time(start), start_time(time)) {
+        (Some(a), Some(b)) if a == b => match (date(start), date(time)) {
+            (Some(x), Some(y)) => x == y,
+            _ => true,
+        },
+        _ => false,
+    }
+}
+
+/// Parse a date given as "DD-MM-YYYY", ISO "YYYY-MM-DD", "today", "tomorrow"
+/// or a day name (the next such day strictly after `today`, as when booking).
+pub fn parse_date(input: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let input = input.trim();
+    match input.to_lowercase().as_str() {
+        "today" => Some(today),
+        "tomorrow" => Some(today + Duration::days(1)),
+        day => match parse_weekday(day) {
+            Some(wd) => Some(next_weekday(today, wd)),
+            None => NaiveDate::parse_from_str(input, "%d-%m-%Y")
+                .or_else(|_| NaiveDate::parse_from_str(input, "%Y-%m-%d"))
+                .ok(),
+        },
+    }
+}
+
+/// Interpret a wall-clock time in `tz`. Times repeated when clocks fall back
+/// resolve to the first occurrence; times skipped when clocks spring forward
+/// move past the gap (02:30 becomes 03:30 CEST), so nothing scheduled in the
+/// gap is silently lost.
+pub fn localize(naive: NaiveDateTime, tz: Tz) -> DateTime<Tz> {
+    match naive.and_local_timezone(tz) {
+        LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t,
+        // Spring-forward gaps are one hour long in nearly every zone
+        LocalResult::None => (naive + Duration::hours(1))
+            .and_local_timezone(tz)
+            .earliest()
+            .unwrap_or_else(|| Utc.from_utc_datetime(&naive).with_timezone(&tz)),
+    }
+}
+
+/// Parse an API timestamp such as "2024-01-15 18:30:00", given in the gym's timezone `tz`.
+pub fn parse_timestamp(s: &str, tz: Tz) -> Option<DateTime<Tz>> {
+    NaiveDateTime::parse_from_str(s.trim(), "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|t| localize(t, tz))
+}
+
+/// When a class's bookings open: `days` before its date at `time`, moved
+/// by `offset`, which may cross midnight either way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookingWindow {
+    pub days: u32,
+    pub time: NaiveTime,
+    pub offset: Duration,
+}
+
+impl BookingWindow {
+    /// A window opening `days` before the class at exactly `time`.
+    pub fn at(days: u32, time: NaiveTime) -> Self {
+        Self {
+            days,
+            time,
+            offset: Duration::zero(),
+        }
+    }
+}
+
+/// The instant bookings open for `date` under `window`, in the gym's timezone `tz`.
+pub fn booking_opens_at(date: NaiveDate, window: BookingWindow, tz: Tz) -> DateTime<Tz> {
+    let opens = NaiveDateTime::new(date - Duration::days(window.days as i64), window.time);
+    localize(opens + window.offset, tz)
+}
+
+/// `booking_window_days` and when bookings open for a slot: at
+/// `booking_opens_at` (the slot's, then `[app]`'s), or else
+/// `booking_offset_secs` after the class's own time.
+pub fn window_schedule(config: &Config, slot_cfg: &SlotConfig) -> Option<BookingWindow> {
+    let slot_time = parse_time(slot_cfg.time.primary())?;
+    let days = slot_cfg
+        .booking_window_days
+        .unwrap_or(config.app.booking_window_days);
+    let fixed = slot_cfg
+        .booking_opens_at
+        .as_deref()
+        .or(config.app.booking_opens_at.as_deref())
+        .and_then(parse_time);
+    Some(match fixed {
+        Some(time) => BookingWindow::at(days, time),
+        None => {
+            let offset = slot_cfg
+                .booking_offset_secs
+                .unwrap_or(config.app.booking_offset_secs);
+            BookingWindow {
+                days,
+                time: slot_time,
+                offset: Duration::seconds(offset),
+            }
+        }
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::NaiveDate;
+    use chrono::{NaiveDate, TimeZone};
 
     #[test]
     fn test_next_weekday_same_day() {
@@ -81,17 +327,160 @@ mod tests {
         assert_eq!(next, NaiveDate::from_ymd_opt(2024, 1, 8).unwrap());
     }
 
+    #[test]
+    fn test_next_class_date_same_day() {
+        let wed = NaiveDate::from_ymd_opt(2024, 1, 3).unwrap(); // Wednesday
+        let class = Some(NaiveTime::from_hms_opt(18, 0, 0).unwrap());
+        let at = |h, m| wed.and_hms_opt(h, m, 0).unwrap();
+        // Before the class: today's, after it (or when off): next week's
+        assert_eq!(next_class_date(at(10, 0), Weekday::Wed, class), wed);
+        assert_eq!(next_class_date(at(17, 59), Weekday::Wed, class), wed);
+        let next_wed = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        assert_eq!(next_class_date(at(18, 0), Weekday::Wed, class), next_wed);
+        assert_eq!(next_class_date(at(10, 0), Weekday::Wed, None), next_wed);
+        // Other weekdays are unaffected
+        assert_eq!(
+            next_class_date(at(10, 0), Weekday::Fri, class),
+            NaiveDate::from_ymd_opt(2024, 1, 5).unwrap()
+        );
+    }
+
     #[test]
     fn test_parse_weekday() {
         assert_eq!(parse_weekday("monday"), Some(Weekday::Mon));
         assert_eq!(parse_weekday("FRIDAY"), Some(Weekday::Fri));
+        assert_eq!(parse_weekday("tue"), Some(Weekday::Tue));
+        assert_eq!(parse_weekday("MON"), Some(Weekday::Mon));
+        assert_eq!(parse_weekday("martes"), Some(Weekday::Tue));
+        assert_eq!(parse_weekday("jueves"), Some(Weekday::Thu));
+        assert_eq!(parse_weekday("Miércoles"), Some(Weekday::Wed));
         assert_eq!(parse_weekday("invalid"), None);
+        assert_eq!(canonical_day("sábado"), Some("saturday"));
+    }
+
+    #[test]
+    fn test_slot_keys() {
+        assert_eq!(slot_weekday("monday"), Some(Weekday::Mon));
+        assert_eq!(slot_weekday("lunes-2"), Some(Weekday::Mon));
+        assert_eq!(slot_weekday("fri-evening"), Some(Weekday::Fri));
+        assert_eq!(slot_weekday("someday-2"), None);
+        assert_eq!(canonical_slot_key("tue"), Some("tuesday".to_string()));
+        assert_eq!(
+            canonical_slot_key("Martes-Evening"),
+            Some("tuesday-evening".to_string())
+        );
+        assert_eq!(canonical_slot_key("monday-"), None);
+        assert_eq!(canonical_slot_key("someday"), None);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("18:30:00"), NaiveTime::from_hms_opt(18, 30, 0));
+        assert_eq!(parse_time(" 20:00 "), NaiveTime::from_hms_opt(20, 0, 0));
+        assert_eq!(parse_time("evening"), None);
+    }
+
+    #[test]
+    fn test_parse_slot_time_formats() {
+        let hms = |h, m, s| NaiveTime::from_hms_opt(h, m, s).unwrap();
+        assert_eq!(parse_slot_time("6:00").unwrap(), hms(6, 0, 0));
+        assert_eq!(parse_slot_time("06:00").unwrap(), hms(6, 0, 0));
+        assert_eq!(parse_slot_time("18:30:15").unwrap(), hms(18, 30, 15));
+        assert_eq!(parse_slot_time("6:00 PM").unwrap(), hms(18, 0, 0));
+        assert_eq!(parse_slot_time("6:30pm").unwrap(), hms(18, 30, 0));
+        assert_eq!(parse_slot_time("12:15 am").unwrap(), hms(0, 15, 0));
+        assert_eq!(parse_slot_time("12:00 PM").unwrap(), hms(12, 0, 0));
+        assert_eq!(parse_slot_time("7:45:30 AM").unwrap(), hms(7, 45, 30));
+
+        for garbage in ["evening", "25:00", "18h", "13:00 PM", ""] {
+            let err = parse_slot_time(garbage).unwrap_err().to_string();
+            assert!(err.contains("expected HH:MM"), "{garbage}: {err}");
+        }
+        assert!(starts_at("2024-01-01 18:00:00", "6:00 PM"));
+        assert!(!starts_at("2024-01-01 06:00:00", "6:00 PM"));
+    }
+
+    #[test]
+    fn test_starts_at_compares_times() {
+        assert!(starts_at("2024-01-01 18:00:00", "18:00"));
+        assert!(starts_at("2024-01-01T18:00:00", "18:00:00"));
+        assert!(starts_at(" 08:00 ", "8:00"));
+        assert!(!starts_at("2024-01-01 18:00:00", "8:00"));
+        assert!(!starts_at("2024-01-01 08:00:00", "18:00"));
+        assert!(!starts_at("2024-01-01 18:30:00", "18:00"));
+        assert!(!starts_at("2024-01-01 18:00:00", "evening"));
+        assert!(starts_at("2024-01-01 18:00:00", "2024-01-01 18:00:00"));
+        assert!(!starts_at("2024-01-01 18:00:00", "2024-01-02 18:00:00"));
     }
 
     #[test]
-    fn test_day_timestamps() {
-        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
-        let (start, end) = day_timestamps(date);
-        assert_eq!(end - start, 22 * 3600);
+    fn test_parse_date() {
+        // 2024-01-01 is a Monday
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        assert_eq!(parse_date("today", today), Some(today));
+        assert_eq!(
+            parse_date("Tomorrow", today),
+            NaiveDate::from_ymd_opt(2024, 1, 2)
+        );
+        assert_eq!(
+            parse_date("friday", today),
+            NaiveDate::from_ymd_opt(2024, 1, 5)
+        );
+        assert_eq!(
+            parse_date("monday", today),
+            NaiveDate::from_ymd_opt(2024, 1, 8)
+        );
+        assert_eq!(
+            parse_date("15-03-2024", today),
+            NaiveDate::from_ymd_opt(2024, 3, 15)
+        );
+        assert_eq!(
+            parse_date("2024-03-15", today),
+            NaiveDate::from_ymd_opt(2024, 3, 15)
+        );
+        assert_eq!(parse_date("15/03/2024", today), None);
+    }
+
+    #[test]
+    fn test_mock_clock_advance() {
+        let start = CET.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = MockClock::new(start);
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::hours(36));
+        assert_eq!(
+            clock.now(),
+            CET.with_ymd_and_hms(2024, 1, 3, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_localize_across_dst() {
+        let localize_cet = |t| localize(t, CET);
+        let naive = |d, h, m| {
+            NaiveDate::from_ymd_opt(2024, 3, d)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        // 2024-03-31 02:00–03:00 does not exist in Berlin: 02:30 moves to 03:30 CEST
+        let t = localize_cet(naive(31, 2, 30));
+        assert_eq!(t.naive_local(), naive(31, 3, 30));
+        assert_eq!(t.offset().to_string(), "CEST");
+        // Either side of the gap is unaffected
+        assert_eq!(
+            localize_cet(naive(31, 1, 59)).naive_local(),
+            naive(31, 1, 59)
+        );
+        assert_eq!(
+            localize_cet(naive(30, 2, 30)).naive_local(),
+            naive(30, 2, 30)
+        );
+
+        // 2024-10-27 02:30 happens twice; the first (CEST) one wins
+        let repeated = NaiveDate::from_ymd_opt(2024, 10, 27)
+            .unwrap()
+            .and_hms_opt(2, 30, 0)
+            .unwrap();
+        assert_eq!(localize_cet(repeated).offset().to_string(), "CEST");
     }
 }
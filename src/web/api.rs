@@ -0,0 +1,96 @@
+//! JSON routes for external automation (e.g. Home Assistant), mirroring the
+//! dashboard's data.
+
+use axum::extract::State;
+use axum::Json;
+use serde::Serialize;
+
+use super::dashboard::{cached_dashboards, scheduler_entries, UserDashboard};
+use super::{AppState, SchedulerEntry};
+
+/// `GET /api/bookings` — every user's bookings and waiting lists, from the
+/// same cache as the dashboard.
+pub(super) async fn bookings_handler(State(state): State<AppState>) -> Json<Vec<UserDashboard>> {
+    Json(cached_dashboards(&state).await.1.to_vec())
+}
+
+/// `GET /api/schedule` — the scheduler's entries, as in the dashboard's table.
+pub(super) async fn schedule_handler(State(state): State<AppState>) -> Json<Vec<SchedulerEntry>> {
+    Json(scheduler_entries(&state))
+}
+
+#[derive(Serialize)]
+pub(super) struct Status {
+    /// When the waiting-list watcher last finished a check (RFC 3339), if ever.
+    last_watcher_check: Option<String>,
+    /// Slot booking tasks currently running.
+    scheduler_tasks: i64,
+    users: usize,
+}
+
+/// `GET /api/status` — whether the background work is alive.
+pub(super) async fn status_handler(State(state): State<AppState>) -> Json<Status> {
+    let last_check = *state.last_watcher_check.lock().unwrap();
+    Json(Status {
+        last_watcher_check: last_check.map(|t| t.to_rfc3339()),
+        scheduler_tasks: state.metrics.tasks_active(),
+        users: state.config.get().users.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::web::{dashboard, metrics, LiveConfig};
+
+    #[tokio::test]
+    async fn test_status_and_schedule_json() {
+        let config = toml::from_str(
+            r#"
+            slots = {}
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            [[users]]
+            name = "Bob"
+            login = "bob"
+            password = "x"
+            slots = []
+            "#,
+        )
+        .unwrap();
+        let entry = SchedulerEntry {
+            user_name: "Bob".into(),
+            day: "Monday".into(),
+            time: "18:00".into(),
+            target_date: "2024-01-08".into(),
+            books_at: "2024-01-07 18:01 CET".into(),
+            status: "scheduled".into(),
+        };
+        let state = AppState {
+            config: LiveConfig::new(config),
+            last_watcher_check: Arc::new(Mutex::new(None)),
+            scheduler_entries: Arc::new(Mutex::new(HashMap::from([("Bob:monday".into(), entry)]))),
+            clients: dashboard::ClientCache::default(),
+            dashboards: dashboard::DashboardCache::default(),
+            metrics: Arc::new(metrics::Metrics::default()),
+            resumer: Default::default(),
+            events: Default::default(),
+            clock_skew: None,
+            cancel_token: None,
+        };
+
+        let Json(status) = status_handler(State(state.clone())).await;
+        assert_eq!(
+            serde_json::to_value(status).unwrap(),
+            serde_json::json!({"last_watcher_check": null, "scheduler_tasks": 0, "users": 1})
+        );
+        let Json(schedule) = schedule_handler(State(state)).await;
+        let schedule = serde_json::to_value(schedule).unwrap();
+        assert_eq!(schedule[0]["user_name"], "Bob");
+        assert_eq!(schedule[0]["status"], "scheduled");
+    }
+}
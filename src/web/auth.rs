@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::prelude::*;
+
+use crate::models::DashboardAuth;
+
+/// Reject requests that don't carry the configured HTTP Basic credentials.
+pub(crate) async fn require_basic_auth(
+    State(auth): State<Arc<DashboardAuth>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if is_authorized(&auth, req.headers().get(header::AUTHORIZATION)) {
+        return next.run(req).await;
+    }
+    (
+        StatusCode::UNAUTHORIZED,
+        [(
+            header::WWW_AUTHENTICATE,
+            r#"Basic realm="RESAWOD Dashboard""#,
+        )],
+        "Unauthorized",
+    )
+        .into_response()
+}
+
+fn is_authorized(auth: &DashboardAuth, header: Option<&HeaderValue>) -> bool {
+    let Some(encoded) = header
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Basic "))
+    else {
+        return false;
+    };
+    let Ok(decoded) = BASE64_STANDARD.decode(encoded.trim()) else {
+        return false;
+    };
+    let expected = format!("{}:{}", auth.username, auth.password);
+    constant_time_eq(&decoded, expected.as_bytes())
+}
+
+/// Compare without bailing out at the first differing byte, so response
+/// timing doesn't leak how much of a guess was right.
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        let auth = Arc::new(DashboardAuth {
+            username: "admin".into(),
+            password: "s3cret".into(),
+        });
+        Router::new().route("/", get(|| async { "ok" })).layer(
+            axum::middleware::from_fn_with_state(auth, require_basic_auth),
+        )
+    }
+
+    fn request(authorization: Option<&str>) -> Request {
+        let mut req = Request::builder().uri("/");
+        if let Some(value) = authorization {
+            req = req.header(header::AUTHORIZATION, value);
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth() {
+        let resp = app().oneshot(request(None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(resp.headers().contains_key(header::WWW_AUTHENTICATE));
+
+        let wrong = format!("Basic {}", BASE64_STANDARD.encode("admin:guess"));
+        let resp = app().oneshot(request(Some(&wrong))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let right = format!("Basic {}", BASE64_STANDARD.encode("admin:s3cret"));
+        let resp = app().oneshot(request(Some(&right))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
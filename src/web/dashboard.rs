@@ -1,12 +1,153 @@
 use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use axum::extract::State;
-use axum::response::Html;
+use anyhow::{Context, Result};
+use axum::extract::{Form, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use chrono::{DateTime, NaiveDate};
+use chrono_tz::Tz;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{OwnedMutexGuard, Semaphore};
+use tracing::info;
 
-use super::views::render_page;
-use super::{AppState, SchedulerEntry};
-use crate::client::NubappClient;
+use super::auth::constant_time_eq;
+use super::views::{render_page, render_scheduler_row, watcher_status};
+use super::{AppState, LiveEvent, SchedulerEntry};
+use crate::client::{is_success, parse_bookings, NubappClient, SlotCache};
+use crate::models::{BookingsResponse, Config, User};
+use crate::{ics, scheduler};
 
+/// Logged-in clients reused across dashboard requests, keyed by user login.
+/// Each client sits behind its own async lock so concurrent requests for the
+/// same user take turns instead of logging in twice. The clients also share
+/// their slot lookups, so users waiting on the same date cost one request.
+#[derive(Clone, Default)]
+pub(crate) struct ClientCache {
+    clients: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<NubappClient>>>>>,
+    slots: SlotCache,
+}
+
+impl ClientCache {
+    /// The user's cached client, locked, logging in only when there is no
+    /// session yet or its token has expired.
+    async fn session(&self, cfg: &Config, user: &User) -> Result<OwnedMutexGuard<NubappClient>> {
+        let client = self.get(cfg, user).context("Client init failed")?;
+        let mut nubapp = client.lock_owned().await;
+        if !nubapp.has_valid_session() {
+            nubapp
+                .login(&user.login, &user.password)
+                .await
+                .context("Login failed")?;
+        }
+        Ok(nubapp)
+    }
+
+    fn get(&self, cfg: &Config, user: &User) -> Result<Arc<tokio::sync::Mutex<NubappClient>>> {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&user.login) {
+            return Ok(Arc::clone(client));
+        }
+        let client = Arc::new(tokio::sync::Mutex::new(
+            NubappClient::from_config(cfg, user.gym.as_deref())?.with_slot_cache(&self.slots),
+        ));
+        clients.insert(user.login.clone(), Arc::clone(&client));
+        Ok(client)
+    }
+
+    /// Forget every session, e.g. after a reload changed logins or gyms.
+    pub(crate) fn clear(&self) {
+        self.clients.lock().unwrap().clear();
+    }
+}
+
+/// How long a fetch of every user's bookings is served before it is redone.
+const DASHBOARD_TTL: Duration = Duration::from_secs(60);
+
+/// The last fetch of every user's dashboard, so that reloading the page
+/// doesn't log everyone in again. Once it is older than [`DASHBOARD_TTL`] it
+/// is still served, while a background task fetches a fresh one.
+#[derive(Clone, Default)]
+pub(crate) struct DashboardCache(Arc<Mutex<CachedDashboards>>);
+
+#[derive(Default)]
+struct CachedDashboards {
+    fetched: Option<(DateTime<Tz>, Arc<Vec<UserDashboard>>)>,
+    refreshing: bool,
+    /// Bumped by [`DashboardCache::clear`], so a refresh started before
+    /// it doesn't store what it fetched.
+    generation: u64,
+}
+
+impl DashboardCache {
+    /// Drop the cached fetch, e.g. after a booking changed or a reload
+    /// changed the users.
+    pub(crate) fn clear(&self) {
+        let mut cache = self.0.lock().unwrap();
+        cache.fetched = None;
+        cache.generation += 1;
+    }
+
+    fn store(&self, generation: u64, fetched_at: DateTime<Tz>, data: Arc<Vec<UserDashboard>>) {
+        let mut cache = self.0.lock().unwrap();
+        if cache.generation == generation {
+            cache.fetched = Some((fetched_at, data));
+        }
+    }
+}
+
+/// Clears [`CachedDashboards::refreshing`] on drop, so that a refresh that
+/// panicked doesn't stop the dashboard from ever refreshing again.
+struct RefreshGuard(DashboardCache);
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        let mut cache = self.0 .0.lock().unwrap_or_else(|e| e.into_inner());
+        cache.refreshing = false;
+    }
+}
+
+/// Every user's dashboard and when it was fetched, from the cache when there
+/// is one. A stale cache is returned as is and refreshed in the background;
+/// only an empty one makes the caller wait for the gym.
+pub(super) async fn cached_dashboards(state: &AppState) -> (DateTime<Tz>, Arc<Vec<UserDashboard>>) {
+    let tz = state.config.get().app.tz();
+    let generation = {
+        let mut cache = state.dashboards.0.lock().unwrap();
+        if let Some((fetched_at, data)) = &cache.fetched {
+            let cached = (*fetched_at, Arc::clone(data));
+            let age = (scheduler::now(tz) - *fetched_at)
+                .to_std()
+                .unwrap_or_default();
+            if age >= DASHBOARD_TTL && !cache.refreshing {
+                cache.refreshing = true;
+                let (state, generation) = (state.clone(), cache.generation);
+                let refreshing = RefreshGuard(state.dashboards.clone());
+                tokio::spawn(async move {
+                    let _refreshing = refreshing;
+                    let fetched_at = scheduler::now(tz);
+                    let data = Arc::new(user_dashboards(&state).await);
+                    state.dashboards.store(generation, fetched_at, data);
+                });
+            }
+            return cached;
+        }
+        cache.generation
+    };
+    let fetched_at = scheduler::now(tz);
+    let data = Arc::new(user_dashboards(state).await);
+    state
+        .dashboards
+        .store(generation, fetched_at, Arc::clone(&data));
+    (fetched_at, data)
+}
+
+#[derive(Clone, Serialize)]
 pub(super) struct UserDashboard {
     pub(super) name: String,
     pub(super) bookings: Vec<BookingRow>,
@@ -14,178 +155,486 @@ pub(super) struct UserDashboard {
     pub(super) error: Option<String>,
 }
 
+#[derive(Clone, Serialize)]
 pub(super) struct BookingRow {
+    /// `id_activity_calendar`, needed to cancel the booking.
+    pub(super) id: Option<String>,
     pub(super) start: String,
     pub(super) end: String,
     pub(super) name: String,
+    pub(super) coach: Option<String>,
     pub(super) inscribed: Option<u32>,
     pub(super) capacity: Option<u32>,
 }
 
+#[derive(Clone, Serialize)]
 pub(super) struct WaitingRow {
+    /// `id_activity_calendar`, needed to leave the waiting list.
+    pub(super) id: String,
     pub(super) start: String,
     pub(super) end: String,
     pub(super) name: String,
     pub(super) inscribed: Option<u32>,
     pub(super) capacity: Option<u32>,
+    /// Place in the waiting-list queue, when the API reports it.
+    pub(super) position: Option<u32>,
 }
 
-fn json_str(val: &serde_json::Value, keys: &[&str]) -> String {
-    for key in keys {
-        if let Some(s) = val.get(*key).and_then(|v| v.as_str()) {
-            return s.trim().to_string();
-        }
+/// A booking field as shown on the dashboard, "?" when the gym didn't send it.
+fn or_unknown(s: &str) -> String {
+    if s.is_empty() { "?" } else { s }.to_string()
+}
+
+/// How many users' bookings the dashboard fetches at once.
+const FETCH_CONCURRENCY: usize = 3;
+
+/// How many bookings per user the dashboard asks for, more than the CLI's
+/// default so users with many standing bookings see them all.
+const DASHBOARD_BOOKINGS_LIMIT: u32 = 200;
+
+/// Every configured user's bookings and waiting lists, in config order.
+/// Users are fetched a few at a time.
+pub(super) async fn user_dashboards(state: &AppState) -> Vec<UserDashboard> {
+    let cfg = state.config.get();
+    let permits = Arc::new(Semaphore::new(FETCH_CONCURRENCY));
+    let handles: Vec<_> = cfg
+        .users
+        .iter()
+        .map(|user| {
+            let (cfg, user, clients, permits) = (
+                Arc::clone(&cfg),
+                user.clone(),
+                state.clients.clone(),
+                Arc::clone(&permits),
+            );
+            tokio::spawn(async move {
+                let _permit = permits.acquire().await;
+                user_dashboard(&clients, &cfg, &user).await
+            })
+        })
+        .collect();
+
+    let mut users_data = Vec::with_capacity(handles.len());
+    for (handle, user) in handles.into_iter().zip(&cfg.users) {
+        users_data.push(handle.await.unwrap_or_else(|e| UserDashboard {
+            name: user.name.clone(),
+            bookings: vec![],
+            waiting_list: vec![],
+            error: Some(format!("Fetching bookings failed: {e}")),
+        }));
     }
-    "?".to_string()
+    users_data
 }
 
-pub(crate) async fn dashboard_handler(State(state): State<AppState>) -> Html<String> {
-    let cfg = &state.config;
-    let mut users_data: Vec<UserDashboard> = Vec::new();
-
-    for user in &cfg.users {
-        let mut nubapp =
-            match NubappClient::new(&cfg.app.application_id, &cfg.app.category_activity_id) {
-                Ok(c) => c,
-                Err(e) => {
-                    users_data.push(UserDashboard {
-                        name: user.name.clone(),
-                        bookings: vec![],
-                        waiting_list: vec![],
-                        error: Some(format!("Client init failed: {e}")),
-                    });
-                    continue;
-                }
+async fn user_dashboard(clients: &ClientCache, cfg: &Config, user: &User) -> UserDashboard {
+    let mut nubapp = match clients.session(cfg, user).await {
+        Ok(c) => c,
+        Err(e) => {
+            return UserDashboard {
+                name: user.name.clone(),
+                bookings: vec![],
+                waiting_list: vec![],
+                error: Some(format!("{e:#}")),
             };
+        }
+    };
 
-        if let Err(e) = nubapp.login(&user.login, &user.password).await {
-            users_data.push(UserDashboard {
+    let BookingsResponse {
+        bookings,
+        waiting_list: wl_entries,
+    } = match nubapp
+        .get_bookings_with_limit(DASHBOARD_BOOKINGS_LIMIT)
+        .await
+    {
+        Ok(r) => parse_bookings(&r),
+        Err(e) => {
+            return UserDashboard {
                 name: user.name.clone(),
                 bookings: vec![],
                 waiting_list: vec![],
-                error: Some(format!("Login failed: {e}")),
-            });
-            continue;
+                error: Some(format!("Failed to fetch bookings: {e}")),
+            };
         }
+    };
 
-        let resp = match nubapp.get_bookings().await {
-            Ok(r) => r,
-            Err(e) => {
-                users_data.push(UserDashboard {
-                    name: user.name.clone(),
-                    bookings: vec![],
-                    waiting_list: vec![],
-                    error: Some(format!("Failed to fetch bookings: {e}")),
-                });
-                continue;
-            }
-        };
+    let bookings: Vec<BookingRow> = bookings
+        .into_iter()
+        .map(|b| BookingRow {
+            id: b.calendar_id(),
+            start: or_unknown(&b.start),
+            end: or_unknown(&b.end),
+            name: b.activity().to_string(),
+            coach: b.coach,
+            inscribed: b.n_inscribed,
+            capacity: b.n_capacity,
+        })
+        .collect();
 
-        let data = resp.get("data");
-
-        // Parse bookings
-        let bookings: Vec<BookingRow> = data
-            .and_then(|d| d.get("bookings"))
-            .and_then(|v| v.as_array())
-            .map(|arr| {
-                arr.iter()
-                    .map(|b| BookingRow {
-                        start: json_str(b, &["start_timestamp", "start"]),
-                        end: json_str(b, &["end_timestamp", "end"]),
-                        name: json_str(b, &["name_activity", "name"]),
-                        inscribed: b
-                            .get("n_inscribed")
-                            .and_then(|v| v.as_u64())
-                            .map(|v| v as u32),
-                        capacity: b
-                            .get("n_capacity")
-                            .and_then(|v| v.as_u64())
-                            .map(|v| v as u32),
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
-
-        // Parse waiting list entries
-        let wl_entries: Vec<serde_json::Value> = data
-            .and_then(|d| d.get("in_waiting_list"))
-            .and_then(|v| v.as_array())
-            .cloned()
-            .unwrap_or_default();
-
-        // Fetch slot capacity for waiting list entries
-        let mut capacity_map: HashMap<String, (u32, u32)> = HashMap::new();
-        if !wl_entries.is_empty() {
-            let mut dates: Vec<String> = wl_entries
-                .iter()
-                .filter_map(|b| {
-                    b.get("start_timestamp")
-                        .and_then(|v| v.as_str())
-                        .and_then(|s| s.get(..10))
-                        .map(|s| s.to_string())
-                })
-                .collect();
-            dates.sort();
-            dates.dedup();
-
-            for date in &dates {
-                if let Some(api_date) = date
-                    .get(8..10)
-                    .zip(date.get(5..7))
-                    .zip(date.get(0..4))
-                    .map(|((d, m), y)| format!("{d}-{m}-{y}"))
-                {
-                    if let Ok(slots) = nubapp.get_slots(&api_date).await {
-                        for slot in &slots {
-                            let id = slot
-                                .id_activity_calendar
-                                .to_string()
-                                .trim_matches('"')
-                                .to_string();
-                            if let (Some(ins), Some(cap)) = (slot.n_inscribed, slot.n_capacity) {
-                                capacity_map.insert(id, (ins, cap));
-                            }
-                        }
-                    }
+    // Fetch slot capacity for waiting list entries
+    let mut capacity_map: HashMap<String, (u32, u32)> = HashMap::new();
+    let mut dates: Vec<NaiveDate> = wl_entries.iter().filter_map(|e| e.class.date()).collect();
+    dates.sort();
+    dates.dedup();
+    for date in &dates {
+        if let Ok(slots) = nubapp.get_slots(&date.format("%d-%m-%Y").to_string()).await {
+            for slot in &slots {
+                let id = slot.calendar_id();
+                if let (Some(ins), Some(cap)) = (slot.n_inscribed, slot.n_capacity) {
+                    capacity_map.insert(id, (ins, cap));
                 }
             }
         }
+    }
 
-        let waiting_list: Vec<WaitingRow> = wl_entries
-            .iter()
-            .map(|b| {
-                let slot_id = b
-                    .get("id_activity_calendar")
-                    .map(|v| v.to_string().trim_matches('"').to_string())
-                    .unwrap_or_default();
-                let (ins, cap) = capacity_map.get(&slot_id).copied().unzip();
-                WaitingRow {
-                    start: json_str(b, &["start_timestamp", "start"]),
-                    end: json_str(b, &["end_timestamp", "end"]),
-                    name: json_str(b, &["name_activity", "name"]),
-                    inscribed: ins,
-                    capacity: cap,
-                }
-            })
-            .collect();
+    let waiting_list: Vec<WaitingRow> = wl_entries
+        .into_iter()
+        .map(|e| {
+            let slot_id = e.class.calendar_id().unwrap_or_default();
+            let (ins, cap) = capacity_map.get(&slot_id).copied().unzip();
+            WaitingRow {
+                id: slot_id,
+                start: or_unknown(&e.class.start),
+                end: or_unknown(&e.class.end),
+                name: e.class.activity().to_string(),
+                inscribed: ins,
+                capacity: cap,
+                position: e.position,
+            }
+        })
+        .collect();
 
-        users_data.push(UserDashboard {
-            name: user.name.clone(),
-            bookings,
-            waiting_list,
-            error: None,
-        });
+    UserDashboard {
+        name: user.name.clone(),
+        bookings,
+        waiting_list,
+        error: None,
     }
+}
 
-    let last_check = state.last_watcher_check.lock().unwrap().clone();
-    let mut sched_entries: Vec<SchedulerEntry> = state
+/// The scheduler's entries, soonest target date first.
+pub(super) fn scheduler_entries(state: &AppState) -> Vec<SchedulerEntry> {
+    let mut entries: Vec<SchedulerEntry> = state
         .scheduler_entries
         .lock()
         .unwrap()
         .values()
         .cloned()
         .collect();
-    sched_entries.sort_by(|a, b| a.target_date.cmp(&b.target_date));
-    let html = render_page(cfg, &users_data, last_check, &sched_entries);
+    entries.sort_by(|a, b| a.target_date.cmp(&b.target_date));
+    entries
+}
+
+pub(crate) async fn dashboard_handler(State(state): State<AppState>) -> Html<String> {
+    let (fetched_at, users_data) = cached_dashboards(&state).await;
+    let last_check = *state.last_watcher_check.lock().unwrap();
+    let sched_entries = scheduler_entries(&state);
+    let html = render_page(
+        &state.config.get(),
+        &users_data,
+        fetched_at,
+        last_check,
+        &sched_entries,
+        state.clock_skew,
+        state.cancel_token.as_deref(),
+    );
     Html(html)
 }
+
+/// The form behind the dashboard's per-row buttons (Cancel, Leave).
+#[derive(Deserialize)]
+pub(crate) struct CancelForm {
+    user: String,
+    id_activity_calendar: String,
+    token: String,
+}
+
+/// The form behind a scheduler row's Resume button.
+#[derive(Deserialize)]
+pub(crate) struct ResumeForm {
+    user: String,
+    token: String,
+}
+
+/// Check a button form's token and look up its user. Buttons are only
+/// routed when dashboard auth is on; the token (embedded in the page) keeps
+/// other sites from submitting them with the browser's saved credentials.
+fn form_user<'a>(
+    state: &AppState,
+    cfg: &'a Config,
+    user: &str,
+    token: &str,
+) -> Result<&'a User, (StatusCode, &'static str)> {
+    let Some(expected) = state.cancel_token.as_deref() else {
+        return Err((StatusCode::NOT_FOUND, "Not found"));
+    };
+    if !constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Invalid form token, reload the dashboard",
+        ));
+    }
+    cfg.users
+        .iter()
+        .find(|u| u.name == user)
+        .ok_or((StatusCode::NOT_FOUND, "Unknown user"))
+}
+
+/// `POST /cancel` from a booking's Cancel button.
+pub(crate) async fn cancel_handler(
+    State(state): State<AppState>,
+    Form(form): Form<CancelForm>,
+) -> Response {
+    let cfg = &state.config.get();
+    let user = match form_user(&state, cfg, &form.user, &form.token) {
+        Ok(u) => u,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let result = async {
+        let mut nubapp = state.clients.session(cfg, user).await?;
+        nubapp.cancel(&form.id_activity_calendar).await
+    };
+    match result.await {
+        Ok(resp) if is_success(&resp) => {
+            info!(
+                "Dashboard: cancelled slot {} for {}",
+                form.id_activity_calendar, user.name
+            );
+            state.dashboards.clear();
+            Redirect::to("/").into_response()
+        }
+        Ok(resp) => {
+            let msg = resp
+                .get("message")
+                .and_then(|v| v.as_str())
+                .unwrap_or("unknown error");
+            (
+                StatusCode::BAD_GATEWAY,
+                format!("Cancellation failed: {msg}"),
+            )
+                .into_response()
+        }
+        Err(e) => (StatusCode::BAD_GATEWAY, format!("{e:#}")).into_response(),
+    }
+}
+
+/// `POST /leave-waitlist` from a waiting-list entry's Leave button. The
+/// entry must still be on the user's waiting list.
+pub(crate) async fn leave_waitlist_handler(
+    State(state): State<AppState>,
+    Form(form): Form<CancelForm>,
+) -> Response {
+    let cfg = &state.config.get();
+    let user = match form_user(&state, cfg, &form.user, &form.token) {
+        Ok(u) => u,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let result = async {
+        let mut nubapp = state.clients.session(cfg, user).await?;
+        let waiting = nubapp.get_bookings_typed().await?.waiting_list;
+        let listed = waiting
+            .iter()
+            .any(|e| e.class.calendar_id().as_deref() == Some(form.id_activity_calendar.as_str()));
+        if !listed {
+            return anyhow::Ok(None);
+        }
+        nubapp
+            .leave_waiting_list(&form.id_activity_calendar)
+            .await
+            .map(Some)
+    };
+    match result.await {
+        Ok(None) => (StatusCode::NOT_FOUND, "Not on this waiting list any more").into_response(),
+        Ok(Some(resp)) if is_success(&resp) => {
+            info!(
+                "Dashboard: left the waiting list of slot {} for {}",
+                form.id_activity_calendar, user.name
+            );
+            state.dashboards.clear();
+            Redirect::to("/").into_response()
+        }
+        Ok(Some(resp)) => {
+            let msg = resp
+                .get("message")
+                .and_then(|v| v.as_str())
+                .unwrap_or("unknown error");
+            (
+                StatusCode::BAD_GATEWAY,
+                format!("Leaving the waiting list failed: {msg}"),
+            )
+                .into_response()
+        }
+        Err(e) => (StatusCode::BAD_GATEWAY, format!("{e:#}")).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CalendarQuery {
+    user: Option<String>,
+}
+
+/// `/calendar.ics?user=<name>` — one user's bookings (the first user's by
+/// default) as an iCalendar feed for phone calendar subscriptions.
+pub(crate) async fn calendar_handler(
+    State(state): State<AppState>,
+    Query(query): Query<CalendarQuery>,
+) -> Response {
+    let cfg = &state.config.get();
+    let user = match &query.user {
+        Some(name) => cfg.users.iter().find(|u| &u.name == name),
+        None => cfg.users.first(),
+    };
+    let Some(user) = user else {
+        return (StatusCode::NOT_FOUND, "Unknown user").into_response();
+    };
+
+    let bookings = async {
+        let mut nubapp = state.clients.session(cfg, user).await?;
+        anyhow::Ok(nubapp.get_bookings_typed().await?.bookings)
+    };
+    match bookings.await {
+        Ok(bookings) => (
+            [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+            ics::bookings_to_ics(&bookings, scheduler::now(cfg.app.tz())),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::BAD_GATEWAY, format!("{e:#}")).into_response(),
+    }
+}
+
+/// `POST /resume` from the Resume button of a day paused on a membership
+/// issue: the user's paused days try booking again.
+pub(crate) async fn resume_handler(
+    State(state): State<AppState>,
+    Form(form): Form<ResumeForm>,
+) -> Response {
+    let cfg = &state.config.get();
+    let user = match form_user(&state, cfg, &form.user, &form.token) {
+        Ok(u) => u,
+        Err(rejection) => return rejection.into_response(),
+    };
+    info!("Dashboard: resuming paused bookings for {}", user.name);
+    state.resumer.resume(&user.login);
+    Redirect::to("/").into_response()
+}
+
+/// `GET /events`: Server-Sent Events keeping an open dashboard current.
+/// `scheduler` carries a changed scheduler row's HTML, `watcher` the new
+/// watcher line, and `reload` asks the page to reload after it fell behind.
+pub(crate) async fn events_handler(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = state.events.subscribe();
+    let stream = futures::stream::unfold((events, state), |(mut events, state)| async move {
+        let event = match events.recv().await {
+            Ok(LiveEvent::Scheduler(entry)) => {
+                let now = scheduler::now(state.config.get().app.tz());
+                let row = render_scheduler_row(&entry, now, state.cancel_token.as_deref());
+                Event::default().event("scheduler").data(row)
+            }
+            Ok(LiveEvent::WatcherCheck(at)) => Event::default()
+                .event("watcher")
+                .data(watcher_status(Some(at))),
+            Err(RecvError::Lagged(_)) => Event::default().event("reload").data(""),
+            Ok(LiveEvent::Shutdown) | Err(RecvError::Closed) => return None,
+        };
+        Some((Ok(event), (events, state)))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::web::{metrics, LiveConfig};
+
+    /// A server state for `names`, whose gym is unreachable.
+    fn unreachable_gym(names: &[&str]) -> AppState {
+        let users: String = names
+            .iter()
+            .map(|name| {
+                format!("[[users]]\nname = \"{name}\"\nlogin = \"{name}\"\npassword = \"x\"\nslots = []\n")
+            })
+            .collect();
+        // Nothing listens on port 9: every login fails at once
+        let config: Config = toml::from_str(&format!(
+            "slots = {{}}\n[app]\napplication_id = \"1\"\ncategory_activity_id = \"2\"\nretry_attempts = 0\n\
+             [api]\nbase_url = \"http://127.0.0.1:9\"\n{users}"
+        ))
+        .unwrap();
+        AppState {
+            config: LiveConfig::new(config),
+            last_watcher_check: Arc::new(Mutex::new(None)),
+            scheduler_entries: Arc::new(Mutex::new(HashMap::new())),
+            clients: ClientCache::default(),
+            dashboards: DashboardCache::default(),
+            metrics: Arc::new(metrics::Metrics::default()),
+            resumer: Default::default(),
+            events: Default::default(),
+            clock_skew: None,
+            cancel_token: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_user_dashboards_keep_config_order() {
+        let state = unreachable_gym(&["Dana", "Alice", "Carl", "Bob"]);
+        let dashboards = user_dashboards(&state).await;
+        let names: Vec<_> = dashboards.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["Dana", "Alice", "Carl", "Bob"]);
+        assert!(dashboards.iter().all(|d| d.error.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_dashboards_cached_until_cleared() {
+        let state = unreachable_gym(&["Alice"]);
+        let (fetched_at, first) = cached_dashboards(&state).await;
+        let (again_at, again) = cached_dashboards(&state).await;
+        assert!(Arc::ptr_eq(&first, &again));
+        assert_eq!(fetched_at, again_at);
+
+        state.dashboards.clear();
+        let (_, refetched) = cached_dashboards(&state).await;
+        assert!(!Arc::ptr_eq(&first, &refetched));
+        assert_eq!(refetched[0].name, "Alice");
+    }
+
+    #[tokio::test]
+    async fn test_panicked_refresh_clears_refreshing() {
+        let cache = DashboardCache::default();
+        cache.0.lock().unwrap().refreshing = true;
+        let refreshing = RefreshGuard(cache.clone());
+        let refresh = tokio::spawn(async move {
+            let _refreshing = refreshing;
+            panic!("gym answered nonsense");
+        });
+        assert!(refresh.await.unwrap_err().is_panic());
+        assert!(!cache.0.lock().unwrap().refreshing);
+    }
+
+    #[tokio::test]
+    async fn test_events_stream_until_shutdown() {
+        let state = unreachable_gym(&["Bob"]);
+        let sse = events_handler(State(state.clone())).await;
+        state.events.send(LiveEvent::Scheduler(SchedulerEntry {
+            user_name: "Bob".into(),
+            day: "Monday".into(),
+            time: "18:00".into(),
+            target_date: "2024-01-15".into(),
+            books_at: "2024-01-08 18:00 CET".into(),
+            status: "booked +3.2s".into(),
+        }));
+        state.events.send(LiveEvent::Shutdown);
+
+        let body = axum::body::to_bytes(sse.into_response().into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with("event: scheduler\n"), "{body}");
+        assert!(body.contains(r#"data-key="Bob:Monday""#));
+        assert!(body.contains("booked +3.2s"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+
+use super::AppState;
+
+pub(crate) const BOOKINGS_ATTEMPTED: &str = "bookings_attempted_total";
+pub(crate) const BOOKINGS_SUCCEEDED: &str = "bookings_succeeded_total";
+pub(crate) const WAITING_LIST_JOINED: &str = "waiting_list_joined_total";
+pub(crate) const BOOKING_ERRORS: &str = "booking_errors_total";
+
+/// Counters in exposition order, with their `# HELP` text.
+const COUNTERS: &[(&str, &str)] = &[
+    (
+        BOOKINGS_ATTEMPTED,
+        "Booking attempts by the scheduler and the waiting-list watcher.",
+    ),
+    (
+        BOOKINGS_SUCCEEDED,
+        "Slots booked directly or from a waiting list.",
+    ),
+    (
+        WAITING_LIST_JOINED,
+        "Full slots whose waiting list was joined instead.",
+    ),
+    (BOOKING_ERRORS, "Booking attempts that failed, by outcome."),
+];
+
+/// Minimal Prometheus registry for the `/metrics` route: a fixed set of
+/// labelled counters plus the number of running scheduler tasks.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    /// (metric name, rendered label set) → count
+    counters: Mutex<BTreeMap<(&'static str, String), u64>>,
+    tasks_active: AtomicI64,
+}
+
+impl Metrics {
+    pub(crate) fn inc(&self, name: &'static str, labels: &[(&str, &str)]) {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| format!("{k}=\"{}\"", escape_label(v)))
+            .collect::<Vec<_>>()
+            .join(",");
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry((name, labels))
+            .or_default() += 1;
+    }
+
+    /// Count a scheduler task as active until the returned guard is dropped
+    /// (including when the task panics).
+    pub(crate) fn task_started(self: &Arc<Self>) -> ActiveTask {
+        self.tasks_active.fetch_add(1, Ordering::Relaxed);
+        ActiveTask(Arc::clone(self))
+    }
+
+    pub(crate) fn tasks_active(&self) -> i64 {
+        self.tasks_active.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut out = String::new();
+        for (name, help) in COUNTERS {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for ((_, labels), value) in counters.iter().filter(|((n, _), _)| n == name) {
+                let _ = writeln!(out, "{name}{{{labels}}} {value}");
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP scheduler_tasks_active Slot booking tasks currently running."
+        );
+        let _ = writeln!(out, "# TYPE scheduler_tasks_active gauge");
+        let _ = writeln!(out, "scheduler_tasks_active {}", self.tasks_active());
+        out
+    }
+}
+
+pub(crate) struct ActiveTask(Arc<Metrics>);
+
+impl Drop for ActiveTask {
+    fn drop(&mut self) {
+        self.0.tasks_active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub(crate) async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.inc(BOOKINGS_ATTEMPTED, &[("user", "Bob")]);
+        metrics.inc(BOOKINGS_ATTEMPTED, &[("user", "Bob")]);
+        metrics.inc(
+            BOOKING_ERRORS,
+            &[("user", "A \"B\""), ("outcome", "failed")],
+        );
+        let task = metrics.task_started();
+
+        let text = metrics.render();
+        assert!(text.contains("bookings_attempted_total{user=\"Bob\"} 2\n"));
+        assert!(text.contains("booking_errors_total{user=\"A \\\"B\\\"\",outcome=\"failed\"} 1\n"));
+        assert!(text.contains("# TYPE bookings_succeeded_total counter\n"));
+        assert!(text.contains("scheduler_tasks_active 1\n"));
+
+        drop(task);
+        assert!(metrics.render().contains("scheduler_tasks_active 0\n"));
+    }
+}
@@ -1,23 +1,35 @@
+pub mod api;
+pub mod auth;
 pub mod dashboard;
+pub mod metrics;
+pub mod reload;
 pub mod slot_scheduler;
 pub mod views;
 pub mod watcher;
 
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use anyhow::Result;
-use axum::routing::get;
+use axum::routing::{get, post};
 use axum::Router;
 use chrono::DateTime;
 use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
-use tracing::info;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
 
+use crate::client::NubappClient;
+use crate::history::History;
 use crate::models::Config;
+use crate::scheduler::{self, Clock};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct SchedulerEntry {
     pub(crate) user_name: String,
     pub(crate) day: String,
@@ -29,45 +41,335 @@ pub(crate) struct SchedulerEntry {
 
 pub(crate) type SchedulerState = Arc<Mutex<HashMap<String, SchedulerEntry>>>;
 
+/// A change pushed to the dashboard's `/events` subscribers.
+#[derive(Clone)]
+pub(crate) enum LiveEvent {
+    /// A row of the scheduler table changed.
+    Scheduler(SchedulerEntry),
+    /// The waiting-list watcher finished a check.
+    WatcherCheck(DateTime<Tz>),
+    /// The server is shutting down; subscribers disconnect.
+    Shutdown,
+}
+
+/// Events a slow `/events` subscriber may fall behind by before it is told
+/// to reload instead.
+const LIVE_EVENTS_BUFFER: usize = 64;
+
+/// Where [`LiveEvent`]s are sent; dropped when nobody is subscribed.
+#[derive(Clone)]
+pub(crate) struct LiveEvents(broadcast::Sender<LiveEvent>);
+
+impl Default for LiveEvents {
+    fn default() -> Self {
+        Self(broadcast::channel(LIVE_EVENTS_BUFFER).0)
+    }
+}
+
+impl LiveEvents {
+    pub(crate) fn send(&self, event: LiveEvent) {
+        let _ = self.0.send(event);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<LiveEvent> {
+        self.0.subscribe()
+    }
+}
+
+/// The running config, swapped as a whole when `config.toml` is reloaded.
+/// Readers take a snapshot with `get` and keep it for one request or check.
+#[derive(Clone)]
+pub(crate) struct LiveConfig(Arc<RwLock<Arc<Config>>>);
+
+impl LiveConfig {
+    pub(crate) fn new(config: Config) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub(crate) fn get(&self) -> Arc<Config> {
+        Arc::clone(&self.0.read().unwrap())
+    }
+
+    pub(crate) fn set(&self, config: Arc<Config>) {
+        *self.0.write().unwrap() = config;
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct AppState {
-    pub(crate) config: Arc<Config>,
+    pub(crate) config: LiveConfig,
     pub(crate) last_watcher_check: Arc<Mutex<Option<DateTime<Tz>>>>,
     pub(crate) scheduler_entries: SchedulerState,
+    pub(crate) clients: dashboard::ClientCache,
+    pub(crate) dashboards: dashboard::DashboardCache,
+    pub(crate) metrics: Arc<metrics::Metrics>,
+    /// Restarts scheduler days paused on a membership issue.
+    pub(crate) resumer: slot_scheduler::Resumer,
+    pub(crate) events: LiveEvents,
+    /// How far the gym server's clock was ahead of ours at startup.
+    pub(crate) clock_skew: Option<chrono::Duration>,
+    /// Embedded in Cancel forms and checked on `POST /cancel`; `None` (and no
+    /// cancel buttons) unless dashboard auth is enabled.
+    pub(crate) cancel_token: Option<String>,
 }
 
-pub async fn serve(config: Config, config_path: &Path, addr: &str) -> Result<()> {
-    let last_check: Arc<Mutex<Option<DateTime<Tz>>>> = Arc::new(Mutex::new(None));
-    let scheduler_entries: SchedulerState = Arc::new(Mutex::new(HashMap::new()));
-    let state_path = config_path
+/// How long background tasks get to finish their current iteration on shutdown.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+/// Sleep for `dur`. Returns `false` if shutdown was requested in the meantime.
+pub(crate) async fn sleep_or_shutdown(
+    dur: Duration,
+    shutdown: &mut broadcast::Receiver<()>,
+) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(dur) => true,
+        _ = shutdown.recv() => false,
+    }
+}
+
+/// Whether a shutdown signal has been sent (or its sender is gone).
+pub(crate) fn shutdown_requested(shutdown: &mut broadcast::Receiver<()>) -> bool {
+    !matches!(
+        shutdown.try_recv(),
+        Err(broadcast::error::TryRecvError::Empty)
+    )
+}
+
+/// A per-process secret, from the randomly keyed std hasher.
+fn random_token() -> String {
+    let part = || {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        );
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", part(), part())
+}
+
+/// Resolves on Ctrl-C, after telling every background task to stop and
+/// closing the `/events` streams, which would keep the server waiting.
+async fn shutdown_signal(shutdown: broadcast::Sender<()>, events: LiveEvents) {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("Failed to listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+    info!("Shutting down, saving state...");
+    let _ = shutdown.send(());
+    events.send(LiveEvent::Shutdown);
+}
+
+/// `scheduler_state.json`, next to the config file.
+fn state_path(config_path: &Path) -> PathBuf {
+    config_path
         .parent()
         .unwrap_or(Path::new("."))
-        .join("scheduler_state.json");
+        .join("scheduler_state.json")
+}
+
+/// Compare the gym server's clock with ours, warning when they are more than
+/// `[app].clock_skew_warn_secs` apart. `None` if the server's clock couldn't
+/// be read.
+async fn check_clock_skew(config: &Config) -> Option<chrono::Duration> {
+    let skew = match NubappClient::from_config(config, None) {
+        Ok(client) => client.clock_skew().await,
+        Err(e) => Err(e),
+    };
+    let skew = match skew {
+        Ok(skew) => skew,
+        Err(e) => {
+            warn!("Could not read the gym server's clock: {:#}", e);
+            return None;
+        }
+    };
+    let warn_at = chrono::Duration::seconds(config.app.clock_skew_warn_secs as i64);
+    if skew.abs() > warn_at {
+        warn!(
+            "The gym server's clock is {} from this machine's{}",
+            scheduler::format_offset(skew),
+            if config.app.follow_server_clock {
+                "; bookings are timed by the server's"
+            } else {
+                ", so bookings may fire early or late; sync this clock or set [app].follow_server_clock"
+            }
+        );
+    } else {
+        info!(
+            "Gym server clock: {} from this machine's",
+            scheduler::format_offset(skew)
+        );
+    }
+    Some(skew)
+}
+
+/// The clock the schedulers book by: this machine's, or with
+/// `[app].follow_server_clock` the gym server's as measured at startup.
+fn scheduler_clock(config: &Config, skew: Option<chrono::Duration>) -> Arc<dyn Clock> {
+    match skew {
+        Some(skew) if config.app.follow_server_clock => {
+            Arc::new(scheduler::OffsetClock(skew, config.app.tz()))
+        }
+        _ => Arc::new(scheduler::SystemClock(config.app.tz())),
+    }
+}
+
+/// Run the slot booking schedulers without the dashboard, the waiting-list
+/// watcher or config reloads: until Ctrl-C, or with `once` a single pass
+/// over the booking windows opening within `tolerance` of now.
+pub async fn run_schedulers(
+    config: Config,
+    config_path: &Path,
+    once: bool,
+    tolerance: Duration,
+) -> Result<()> {
+    let clock = scheduler_clock(&config, check_clock_skew(&config).await);
+    let config = Arc::new(config);
+    let history = History::next_to(config_path);
+    if once {
+        return slot_scheduler::run_once(
+            config,
+            state_path(config_path),
+            history,
+            clock,
+            tolerance,
+        )
+        .await;
+    }
+    let schedulers = slot_scheduler::spawn_slot_schedulers(
+        config,
+        Arc::new(Mutex::new(HashMap::new())),
+        state_path(config_path),
+        history,
+        clock,
+        Arc::new(metrics::Metrics::default()),
+        LiveEvents::default(),
+    );
+    tokio::signal::ctrl_c().await?;
+    info!("Shutting down, saving state...");
+    schedulers.finish(SHUTDOWN_GRACE).await;
+    Ok(())
+}
+
+/// Serve the dashboard and run the schedulers for `config`, whose users
+/// were already narrowed to `only` (as are those of reloaded configs).
+pub async fn serve(config: Config, config_path: &Path, addr: &str, only: &[String]) -> Result<()> {
+    let last_check: Arc<Mutex<Option<DateTime<Tz>>>> = Arc::new(Mutex::new(None));
+    let scheduler_entries: SchedulerState = Arc::new(Mutex::new(HashMap::new()));
+    let state_path = state_path(config_path);
+    let cancel_token = config.dashboard.auth.is_some().then(random_token);
+    let clock_skew = check_clock_skew(&config).await;
+    let clock = scheduler_clock(&config, clock_skew);
+    let live = LiveConfig::new(config);
+    let metrics = Arc::new(metrics::Metrics::default());
+    let events = LiveEvents::default();
+
+    // Spawn slot booking schedulers for each user × configured day
+    let schedulers = slot_scheduler::spawn_slot_schedulers(
+        live.get(),
+        Arc::clone(&scheduler_entries),
+        state_path,
+        History::next_to(config_path),
+        clock,
+        Arc::clone(&metrics),
+        events.clone(),
+    );
+
     let state = AppState {
-        config: Arc::new(config),
+        config: live,
         last_watcher_check: Arc::clone(&last_check),
-        scheduler_entries: Arc::clone(&scheduler_entries),
+        scheduler_entries,
+        clients: dashboard::ClientCache::default(),
+        dashboards: dashboard::DashboardCache::default(),
+        metrics,
+        resumer: schedulers.resumer(),
+        events,
+        clock_skew,
+        cancel_token,
     };
 
+    let (shutdown, _) = broadcast::channel(1);
+
     // Spawn background watcher for waiting list auto-booking
-    tokio::spawn(watcher::waiting_list_watcher(
-        Arc::clone(&state.config),
+    let watcher = tokio::spawn(watcher::waiting_list_watcher(
+        state.config.clone(),
         last_check,
+        Arc::clone(&state.metrics),
+        state.events.clone(),
+        shutdown.subscribe(),
     ));
 
-    // Spawn slot booking schedulers for each user × configured day
-    slot_scheduler::spawn_slot_schedulers(
-        Arc::clone(&state.config),
-        scheduler_entries,
-        state_path,
-    );
+    // Follow edits to the config file; stops the schedulers on shutdown
+    let reloader = tokio::spawn(reload::watch_config(
+        config_path.to_path_buf(),
+        only.to_vec(),
+        state.config.clone(),
+        state.clients.clone(),
+        state.dashboards.clone(),
+        schedulers,
+        shutdown.subscribe(),
+    ));
 
-    let app = Router::new()
+    let events = state.events.clone();
+    // Auth wraps the router, so it is fixed until restart
+    let auth = state.config.get().dashboard.auth.clone();
+    let mut app = Router::new();
+    if auth.is_some() {
+        app = app
+            .route("/cancel", post(dashboard::cancel_handler))
+            .route("/leave-waitlist", post(dashboard::leave_waitlist_handler))
+            .route("/resume", post(dashboard::resume_handler));
+    }
+    let mut app = app
         .route("/", get(dashboard::dashboard_handler))
+        .route("/events", get(dashboard::events_handler))
+        .route("/calendar.ics", get(dashboard::calendar_handler))
+        .route("/api/bookings", get(api::bookings_handler))
+        .route("/api/schedule", get(api::schedule_handler))
+        .route("/api/status", get(api::status_handler))
+        .route("/metrics", get(metrics::metrics_handler))
         .with_state(state);
+    if let Some(auth) = auth {
+        info!(
+            "Dashboard: HTTP basic auth enabled for user '{}'",
+            auth.username
+        );
+        app = app.layer(axum::middleware::from_fn_with_state(
+            Arc::new(auth),
+            auth::require_basic_auth,
+        ));
+    }
 
     let listener = TcpListener::bind(addr).await?;
     info!("Dashboard listening on http://{}", addr);
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(shutdown.clone(), events))
+        .await?;
+
+    match reloader.await {
+        Ok(schedulers) => schedulers.finish(SHUTDOWN_GRACE).await,
+        Err(e) => error!("Config watcher failed, scheduler state not saved: {}", e),
+    }
+    if tokio::time::timeout(SHUTDOWN_GRACE, watcher).await.is_err() {
+        warn!("Waiting-list watcher still busy, stopping anyway");
+    }
+    info!("Shutdown complete");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sleep_interrupted_by_shutdown() {
+        let (tx, mut rx) = broadcast::channel(1);
+        assert!(!shutdown_requested(&mut rx));
+        assert!(sleep_or_shutdown(Duration::from_millis(1), &mut rx).await);
+
+        tx.send(()).unwrap();
+        assert!(!sleep_or_shutdown(Duration::from_secs(3600), &mut rx).await);
+    }
+}
@@ -0,0 +1,263 @@
+//! Reloading `config.toml` and the files it includes while `serve` runs.
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, info, warn};
+
+use super::dashboard::{ClientCache, DashboardCache};
+use super::slot_scheduler::Schedulers;
+use super::LiveConfig;
+use crate::config;
+use crate::models::Config;
+
+/// Editors save in bursts (write a temp file, rename it over the old one);
+/// wait this long after the first event before reading the file.
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// Reload the config whenever `config_path` or one of the files it included
+/// at startup changes, keeping only the users in `only` (all when empty),
+/// until shutdown, then stop the scheduler tasks and hand them back to be
+/// finished. A config that fails to load or validate is logged and ignored,
+/// so a typo never takes down the running schedule.
+pub(crate) async fn watch_config(
+    config_path: PathBuf,
+    only: Vec<String>,
+    live: LiveConfig,
+    clients: ClientCache,
+    dashboards: DashboardCache,
+    mut schedulers: Schedulers,
+    mut shutdown: broadcast::Receiver<()>,
+) -> Schedulers {
+    let (tx, mut changes) = mpsc::unbounded_channel();
+    let mut files = vec![config_path.clone()];
+    files.extend(config::included_files(&config_path).unwrap_or_default());
+    let names: HashSet<OsString> = files
+        .iter()
+        .filter_map(|f| f.file_name())
+        .map(Into::into)
+        .collect();
+    let handler = move |res: ::notify::Result<Event>| match res {
+        Ok(event)
+            if matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ) && event
+                .paths
+                .iter()
+                .any(|p| p.file_name().is_some_and(|n| names.contains(n))) =>
+        {
+            let _ = tx.send(());
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Config watcher error: {}", e),
+    };
+    // Watch the directories: a file replaced by rename would drop a watch on the file itself
+    let dirs: HashSet<&Path> = files
+        .iter()
+        .map(|f| {
+            f.parent()
+                .filter(|d| !d.as_os_str().is_empty())
+                .unwrap_or(Path::new("."))
+        })
+        .collect();
+    let watcher = ::notify::recommended_watcher(handler).and_then(|mut w| {
+        for dir in &dirs {
+            w.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        Ok(w)
+    });
+    let _watcher = match watcher {
+        Ok(w) => {
+            info!("Watching {} for config changes", config_path.display());
+            Some(w)
+        }
+        Err(e) => {
+            warn!(
+                "Cannot watch {}, config changes need a restart: {}",
+                config_path.display(),
+                e
+            );
+            None
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = shutdown.recv() => break,
+            Some(()) = changes.recv() => {
+                tokio::time::sleep(SETTLE).await;
+                while changes.try_recv().is_ok() {}
+                reload(&config_path, &only, &live, &clients, &dashboards, &mut schedulers);
+            }
+        }
+    }
+    schedulers.stop_all();
+    schedulers
+}
+
+fn reload(
+    config_path: &Path,
+    only: &[String],
+    live: &LiveConfig,
+    clients: &ClientCache,
+    dashboards: &DashboardCache,
+    schedulers: &mut Schedulers,
+) {
+    let loaded = config::load_config(config_path).and_then(|mut c| {
+        config::only_users(&mut c, only)?;
+        Ok(c)
+    });
+    let mut new = match loaded {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Config reload failed, keeping the running config: {:#}", e);
+            return;
+        }
+    };
+    let old = live.get();
+    // The scheduler clock keeps the zone it started with; so does everything else
+    if new.app.timezone != old.app.timezone {
+        warn!("Config reload: [app].timezone changes take effect after a restart");
+        new.app.timezone = old.app.timezone.clone();
+    }
+    if *old == new {
+        return;
+    }
+    info!("Config reload: {} changed", config_path.display());
+    for change in changes(&old, &new) {
+        info!("Config reload: {}", change);
+    }
+    if old.dashboard != new.dashboard || old.logging != new.logging {
+        warn!("Config reload: [dashboard] and [logging] changes take effect after a restart");
+    }
+    let new = Arc::new(new);
+    live.set(Arc::clone(&new));
+    // Sessions may belong to changed logins, passwords or gyms
+    clients.clear();
+    dashboards.clear();
+    schedulers.reconcile(new);
+}
+
+/// What differs between `old` and `new`, one line per user, day or section.
+fn changes(old: &Config, new: &Config) -> Vec<String> {
+    let mut changes = Vec::new();
+    for user in &new.users {
+        match old.users.iter().find(|u| u.name == user.name) {
+            None => changes.push(format!("user {} added", user.name)),
+            Some(u) if u != user => changes.push(format!("user {} changed", user.name)),
+            Some(_) => {}
+        }
+    }
+    for user in &old.users {
+        if !new.users.iter().any(|u| u.name == user.name) {
+            changes.push(format!("user {} removed", user.name));
+        }
+    }
+
+    let mut days: Vec<&String> = old.slots.keys().chain(new.slots.keys()).collect();
+    days.sort();
+    days.dedup();
+    for day in days {
+        match (old.slots.get(day), new.slots.get(day)) {
+            (None, Some(_)) => changes.push(format!("slot {day} added")),
+            (Some(_), None) => changes.push(format!("slot {day} removed")),
+            (Some(a), Some(b)) if a.time != b.time => {
+                changes.push(format!("slot {day} moved from {} to {}", a.time, b.time))
+            }
+            (Some(a), Some(b)) if a != b => changes.push(format!("slot {day} changed")),
+            _ => {}
+        }
+    }
+
+    for (section, changed) in [
+        ("[app]", old.app != new.app),
+        ("[gyms]", old.gyms != new.gyms),
+        ("[api]", old.api != new.api),
+        ("[notifications]", old.notifications != new.notifications),
+        ("[watcher]", old.watcher != new.watcher),
+    ] {
+        if changed {
+            changes.push(format!("{section} changed"));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_changes_lists_users_slots_and_sections() {
+        let old = config(
+            r#"
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+
+            [[users]]
+            name = "alice"
+            login = "a@example.com"
+            password = "x"
+            slots = ["tuesday"]
+
+            [[users]]
+            name = "bob"
+            login = "b@example.com"
+            password = "x"
+            slots = ["tuesday"]
+
+            [slots]
+            tuesday = { time = "18:00" }
+            friday = { time = "09:00" }
+            "#,
+        );
+        let new = config(
+            r#"
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            stagger_ms = 500
+
+            [[users]]
+            name = "alice"
+            login = "a@example.com"
+            password = "x"
+            slots = ["tuesday", "thursday"]
+
+            [[users]]
+            name = "carol"
+            login = "c@example.com"
+            password = "x"
+            slots = ["thursday"]
+
+            [slots]
+            tuesday = { time = "19:00" }
+            thursday = { time = "18:00" }
+            "#,
+        );
+        assert_eq!(
+            changes(&old, &new),
+            [
+                "user alice changed",
+                "user carol added",
+                "user bob removed",
+                "slot friday removed",
+                "slot thursday added",
+                "slot tuesday moved from 18:00:00 to 19:00:00",
+                "[app] changed",
+            ]
+        );
+        assert!(changes(&new, &new).is_empty());
+    }
+}
@@ -1,59 +1,314 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::Result;
-use chrono::{NaiveDateTime, NaiveTime};
-use tracing::{error, info, warn};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Timelike, Weekday};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use tokio::sync::futures::Notified;
+use tokio::sync::{broadcast, Mutex as AsyncMutex, Notify, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 
+use super::metrics::{self, Metrics};
 use super::views::capitalize;
-use super::{SchedulerEntry, SchedulerState};
-use crate::client::NubappClient;
-use crate::models::{Config, User};
-use crate::scheduler;
+use super::{
+    shutdown_requested, sleep_or_shutdown, LiveEvent, LiveEvents, SchedulerEntry, SchedulerState,
+};
+use crate::client::{
+    is_membership_issue, is_success, is_transient_refusal, NubappClient, SlotFilter,
+};
+use crate::history::{format_latency, History, HistoryEntry};
+use crate::models::{guest_suffix, AppConfig, Booking, Config, SlotConfig, User};
+use crate::notify::{format_outcome, notify_user, Outcome};
+use crate::scheduler::{self, booking_opens_at, window_schedule, BookingWindow, Clock};
 
 enum BookingOutcome {
-    Booked,
+    /// Booked the slot with this ID.
+    Booked(String),
     AlreadyBooked,
-    WaitingList,
+    /// Joined the waiting list of the slot with this ID.
+    WaitingList(String),
+    /// Every choice is full and so is the last one's waiting list, so it
+    /// wasn't joined.
+    WaitingListClosed(String),
+    /// Every choice was full, and the user doesn't join waiting lists.
+    WaitingListDisabled(String),
     SlotNotFound,
-    Failed(String),
+    /// The user already has `max_per_week` bookings in the target week.
+    WeeklyCapReached,
+    Failed {
+        slot_id: String,
+        message: String,
+    },
+    /// Refused over the user's membership or payments; retries won't help.
+    MembershipIssue {
+        slot_id: String,
+        message: String,
+    },
 }
 
-fn load_booked_slots(path: &Path) -> HashSet<String> {
-    match std::fs::read_to_string(path) {
-        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
-        Err(_) => HashSet::new(),
+/// Wakes the tasks paused on a membership issue, per user login.
+#[derive(Clone, Default)]
+pub(crate) struct Resumer(Arc<Mutex<HashMap<String, Arc<Notify>>>>);
+
+impl Resumer {
+    /// What the paused tasks of `login` wait on. A task takes and enables its
+    /// `notified()` before it shows as paused, so a Resume in between isn't lost.
+    fn signal(&self, login: &str) -> Arc<Notify> {
+        Arc::clone(self.0.lock().unwrap().entry(login.to_string()).or_default())
+    }
+
+    /// Let the paused tasks of the user with `login` try again.
+    pub(crate) fn resume(&self, login: &str) {
+        if let Some(signal) = self.0.lock().unwrap().get(login) {
+            signal.notify_waiters();
+        }
     }
+
+    fn resume_all(&self) {
+        for signal in self.0.lock().unwrap().values() {
+            signal.notify_waiters();
+        }
+    }
+}
+
+/// Wait for `resumed`, a `Resumer` signal's enabled `notified()`. Returns
+/// `false` on shutdown instead.
+async fn wait_resumed(
+    resumed: Pin<&mut Notified<'_>>,
+    shutdown: &mut broadcast::Receiver<()>,
+) -> bool {
+    tokio::select! {
+        _ = resumed => true,
+        _ = shutdown.recv() => false,
+    }
+}
+
+/// State shared by every slot booking task.
+#[derive(Clone)]
+struct TaskContext {
+    config: Arc<Config>,
+    entries: SchedulerState,
+    booked: Arc<Mutex<HashSet<String>>>,
+    state_path: Arc<PathBuf>,
+    history: History,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<Metrics>,
+    /// Announces every entry change to the dashboard's live view.
+    events: LiveEvents,
+    /// Caps concurrent booking attempts to stay under the gym's rate limit.
+    booking_slots: Arc<Semaphore>,
+    /// One lock per user login, so a user's attempts run one at a time and
+    /// in `priority` order.
+    user_locks: Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>,
+    /// Booking errors in a row per user login, across that user's tasks.
+    error_streaks: Arc<Mutex<HashMap<String, u32>>>,
+    resumer: Resumer,
 }
 
-fn save_booked_slots(path: &Path, slots: &HashSet<String>) {
-    if let Ok(json) = serde_json::to_string_pretty(slots) {
-        if let Err(e) = std::fs::write(path, json) {
-            error!(
-                "Failed to save scheduler state to {}: {}",
+impl TaskContext {
+    /// The context for the days of `config`, resuming from the state saved at
+    /// `state_path`.
+    fn load(
+        config: Arc<Config>,
+        entries: SchedulerState,
+        state_path: PathBuf,
+        history: History,
+        clock: Arc<dyn Clock>,
+        metrics: Arc<Metrics>,
+        events: LiveEvents,
+    ) -> Self {
+        let state = load_state(&state_path);
+        info!(
+            "Scheduler: loaded {} booked slots and {} entries from {}",
+            state.booked.len(),
+            state.entries.len(),
+            state_path.display()
+        );
+        // Drop entries for users or days no longer in the config
+        let configured = configured_keys(&config);
+        entries.lock().unwrap().extend(
+            state
+                .entries
+                .into_iter()
+                .filter(|(key, _)| configured.contains(key)),
+        );
+        Self {
+            booking_slots: Arc::new(Semaphore::new(config.app.max_concurrent_bookings)),
+            config,
+            entries,
+            booked: Arc::new(Mutex::new(state.booked)),
+            state_path: Arc::new(state_path),
+            history,
+            clock,
+            metrics,
+            events,
+            user_locks: Arc::default(),
+            error_streaks: Arc::default(),
+            resumer: Resumer::default(),
+        }
+    }
+
+    fn user_lock(&self, login: &str) -> Arc<AsyncMutex<()>> {
+        Arc::clone(
+            self.user_locks
+                .lock()
+                .unwrap()
+                .entry(login.to_string())
+                .or_default(),
+        )
+    }
+
+    fn is_booked(&self, slot_key: &str) -> bool {
+        self.booked.lock().unwrap().contains(slot_key)
+    }
+
+    fn mark_booked(&self, slot_key: String) {
+        self.booked.lock().unwrap().insert(slot_key);
+        self.persist();
+    }
+
+    fn update_entry(&self, key: &str, entry: SchedulerEntry) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), entry.clone());
+        self.persist();
+        self.events.send(LiveEvent::Scheduler(entry));
+    }
+
+    /// Count an error for `login` and return how many came in a row.
+    fn record_error(&self, login: &str) -> u32 {
+        let mut streaks = self.error_streaks.lock().unwrap();
+        let streak = streaks.entry(login.to_string()).or_default();
+        *streak += 1;
+        *streak
+    }
+
+    fn reset_errors(&self, login: &str) {
+        self.error_streaks.lock().unwrap().remove(login);
+    }
+
+    fn persist(&self) {
+        let booked = self.booked.lock().unwrap();
+        let entries = self.entries.lock().unwrap();
+        save_state(&self.state_path, &booked, &entries);
+    }
+}
+
+/// On-disk scheduler state: booked slot keys plus the last-known dashboard entries.
+#[derive(Deserialize, Default)]
+struct PersistedState {
+    #[serde(default)]
+    booked: HashSet<String>,
+    #[serde(default)]
+    entries: HashMap<String, SchedulerEntry>,
+}
+
+/// Older versions stored only the booked set as a bare JSON array.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StateFile {
+    Current(PersistedState),
+    Legacy(HashSet<String>),
+}
+
+fn load_state(path: &Path) -> PersistedState {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(_) => return PersistedState::default(),
+    };
+    match serde_json::from_str(&contents) {
+        Ok(StateFile::Current(state)) => state,
+        Ok(StateFile::Legacy(booked)) => PersistedState {
+            booked,
+            entries: HashMap::new(),
+        },
+        Err(e) => {
+            warn!(
+                "Ignoring unreadable scheduler state {}: {}",
                 path.display(),
                 e
             );
+            PersistedState::default()
         }
     }
 }
 
+/// Write state via a temp file + rename so a crash never leaves a truncated file.
+fn save_state(path: &Path, booked: &HashSet<String>, entries: &HashMap<String, SchedulerEntry>) {
+    let state = serde_json::json!({ "booked": booked, "entries": entries });
+    let json = match serde_json::to_string_pretty(&state) {
+        Ok(j) => j,
+        Err(e) => {
+            error!("Failed to serialize scheduler state: {}", e);
+            return;
+        }
+    };
+    let tmp_path = path.with_extension("json.tmp");
+    if let Err(e) = std::fs::write(&tmp_path, json).and_then(|_| std::fs::rename(&tmp_path, path)) {
+        error!(
+            "Failed to save scheduler state to {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
 pub(crate) fn spawn_slot_schedulers(
     config: Arc<Config>,
     entries: SchedulerState,
     state_path: PathBuf,
-) {
-    let existing = load_booked_slots(&state_path);
-    info!(
-        "Scheduler: loaded {} booked slots from {}",
-        existing.len(),
-        state_path.display()
+    history: History,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<Metrics>,
+    events: LiveEvents,
+) -> Schedulers {
+    let ctx = TaskContext::load(
+        Arc::clone(&config),
+        entries,
+        state_path,
+        history,
+        clock,
+        metrics,
+        events,
     );
-    let booked: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(existing));
-    let state_path = Arc::new(state_path);
+    let mut schedulers = Schedulers {
+        tasks: HashMap::new(),
+        stopping: Vec::new(),
+        ctx,
+    };
+    for (key, spec) in wanted_tasks(&config, &schedulers.ctx.entries) {
+        schedulers.spawn(key, spec);
+    }
+    schedulers
+}
 
+/// The `user:day` keys of every day a user has in the config.
+fn configured_keys(config: &Config) -> HashSet<String> {
+    config
+        .users
+        .iter()
+        .flat_map(|u| u.slots.iter().map(move |d| format!("{}:{}", u.name, d)))
+        .collect()
+}
+
+/// What one booking task books: a user's day and that day's slot.
+#[derive(Clone, PartialEq)]
+struct TaskSpec {
+    user: User,
+    day_name: String,
+    slot_cfg: SlotConfig,
+}
+
+/// The booking tasks `config` asks for, keyed `user:day`. Paused days get a
+/// greyed-out dashboard entry in `entries` instead of a task.
+fn wanted_tasks(config: &Config, entries: &SchedulerState) -> HashMap<String, TaskSpec> {
+    let mut wanted = HashMap::new();
     for user in &config.users {
         for day_name in &user.slots {
             let slot_cfg = match config.slots.get(day_name) {
@@ -66,203 +321,763 @@ pub(crate) fn spawn_slot_schedulers(
                     continue;
                 }
             };
-            if scheduler::parse_weekday(day_name).is_none() {
+            if !slot_cfg.enabled {
+                info!(
+                    "Scheduler: slot for '{}' is paused, skipping {}",
+                    day_name, user.name
+                );
+                // Keep the day visible on the dashboard, greyed out
+                entries.lock().unwrap().insert(
+                    format!("{}:{}", user.name, day_name),
+                    SchedulerEntry {
+                        user_name: user.name.clone(),
+                        day: capitalize(day_name),
+                        time: slot_cfg.time.to_string(),
+                        target_date: "—".into(),
+                        books_at: "—".into(),
+                        status: "paused".into(),
+                    },
+                );
+                continue;
+            }
+            if scheduler::slot_weekday(day_name).is_none() {
                 warn!("Scheduler: unknown day '{}', skipping", day_name);
                 continue;
             }
+            wanted.insert(
+                format!("{}:{}", user.name, day_name),
+                TaskSpec {
+                    user: user.clone(),
+                    day_name: day_name.clone(),
+                    slot_cfg,
+                },
+            );
+        }
+    }
+    wanted
+}
 
-            info!(
-                "Scheduler: spawning task for {} — {} {} ({})",
-                user.name,
-                day_name,
-                slot_cfg.time,
-                slot_cfg.activity.as_deref().unwrap_or("any")
+/// Whether a task started for `old_spec` under `old` must restart to follow
+/// `new`: its own user or slot changed, or something it read when it started
+/// did — the shared sections, the slots of the user's other days (priorities)
+/// or the user's position in the config (stagger).
+fn needs_restart(old: &Config, new: &Config, old_spec: &TaskSpec, new_spec: &TaskSpec) -> bool {
+    old_spec != new_spec
+        || old.app != new.app
+        || old.gyms != new.gyms
+        || old.api != new.api
+        || old.notifications != new.notifications
+        || stagger_for(old, &old_spec.user) != stagger_for(new, &new_spec.user)
+        || new_spec
+            .user
+            .slots
+            .iter()
+            .any(|d| old.slots.get(d) != new.slots.get(d))
+}
+
+/// A running booking task and the channel that stops it.
+struct SlotTask {
+    spec: TaskSpec,
+    stop: broadcast::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+/// Handles to the running booking tasks, used to follow config reloads and
+/// to stop the tasks cleanly. Each task has its own stop channel, signalled
+/// on shutdown or when a reload drops or replaces it.
+pub(crate) struct Schedulers {
+    ctx: TaskContext,
+    tasks: HashMap<String, SlotTask>,
+    /// Tasks stopped by a reload that may still be finishing an attempt.
+    stopping: Vec<JoinHandle<()>>,
+}
+
+impl Schedulers {
+    fn spawn(&mut self, key: String, spec: TaskSpec) {
+        info!(
+            "Scheduler: spawning task for {} — {} {} ({})",
+            spec.user.name,
+            spec.day_name,
+            spec.slot_cfg.time,
+            spec.slot_cfg.activity_label()
+        );
+        let (stop, stop_rx) = broadcast::channel(1);
+        let handle = tokio::spawn(supervise_slot_task(
+            self.ctx.clone(),
+            spec.user.clone(),
+            spec.day_name.clone(),
+            spec.slot_cfg.clone(),
+            stop_rx,
+        ));
+        self.tasks.insert(key, SlotTask { spec, stop, handle });
+    }
+
+    fn stop(&mut self, key: &str) {
+        if let Some(task) = self.tasks.remove(key) {
+            let _ = task.stop.send(());
+            self.stopping.push(task.handle);
+        }
+    }
+
+    /// Switch to a reloaded `config`: start tasks for new days, stop those no
+    /// longer configured and restart the ones whose settings changed. The
+    /// booked set is kept, so a restarted task for an unchanged slot doesn't
+    /// book it again.
+    pub(crate) fn reconcile(&mut self, config: Arc<Config>) {
+        let old = std::mem::replace(&mut self.ctx.config, Arc::clone(&config));
+        if old.app.max_concurrent_bookings != config.app.max_concurrent_bookings {
+            warn!("Scheduler: a new max_concurrent_bookings takes effect after a restart");
+        }
+        let wanted = wanted_tasks(&config, &self.ctx.entries);
+        let mut running: Vec<String> = self.tasks.keys().cloned().collect();
+        running.sort();
+        for key in running {
+            let current = &self.tasks[&key].spec;
+            match wanted.get(&key) {
+                None => {
+                    info!(
+                        "Scheduler: {} is no longer scheduled, stopping its task",
+                        key
+                    );
+                    self.stop(&key);
+                }
+                Some(spec) if needs_restart(&old, &config, current, spec) => {
+                    if current.slot_cfg.time != spec.slot_cfg.time {
+                        info!(
+                            "Scheduler: {} moved from {} to {}, restarting its task",
+                            key, current.slot_cfg.time, spec.slot_cfg.time
+                        );
+                    } else {
+                        info!(
+                            "Scheduler: settings for {} changed, restarting its task",
+                            key
+                        );
+                    }
+                    self.stop(&key);
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, spec) in wanted {
+            if !self.tasks.contains_key(&key) {
+                self.spawn(key, spec);
+            }
+        }
+        let configured = configured_keys(&config);
+        self.ctx
+            .entries
+            .lock()
+            .unwrap()
+            .retain(|key, _| configured.contains(key));
+        self.ctx.persist();
+        self.stopping.retain(|handle| !handle.is_finished());
+        // Editing the config is one way to say a membership is sorted out
+        self.ctx.resumer.resume_all();
+    }
+
+    /// The handle resuming tasks paused on a membership issue.
+    pub(crate) fn resumer(&self) -> Resumer {
+        self.ctx.resumer.clone()
+    }
+
+    /// Tell every task to stop after its current iteration.
+    pub(crate) fn stop_all(&self) {
+        for task in self.tasks.values() {
+            let _ = task.stop.send(());
+        }
+    }
+
+    /// Stop the tasks and wait (up to `grace`) for them to finish their
+    /// current iteration, then save the state one last time.
+    pub(crate) async fn finish(self, grace: Duration) {
+        self.stop_all();
+        let handles = self
+            .tasks
+            .into_values()
+            .map(|task| task.handle)
+            .chain(self.stopping);
+        let tasks = async {
+            for handle in handles {
+                let _ = handle.await;
+            }
+        };
+        if tokio::time::timeout(grace, tasks).await.is_err() {
+            warn!(
+                "Scheduler: tasks still busy after {}s, stopping anyway",
+                grace.as_secs()
             );
-            tokio::spawn(slot_booking_task(
-                Arc::clone(&config),
-                user.clone(),
-                day_name.clone(),
-                slot_cfg.time.clone(),
-                slot_cfg.activity.clone(),
-                Arc::clone(&entries),
-                Arc::clone(&booked),
-                Arc::clone(&state_path),
-            ));
         }
+        self.ctx.persist();
     }
 }
 
-fn update_scheduler_entry(entries: &SchedulerState, key: &str, entry: SchedulerEntry) {
-    entries.lock().unwrap().insert(key.to_string(), entry);
+/// Delay before a dead booking task is respawned.
+const RESPAWN_DELAY: Duration = Duration::from_secs(60);
+
+/// Run a slot booking task, respawning it whenever it panics or returns.
+/// The task loops until shutdown, so any other exit is unexpected.
+async fn supervise_slot_task(
+    ctx: TaskContext,
+    user: User,
+    day_name: String,
+    slot_cfg: SlotConfig,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    let entry_key = format!("{}:{}", user.name, day_name);
+    loop {
+        let handle = tokio::spawn(slot_booking_task(
+            ctx.clone(),
+            user.clone(),
+            day_name.clone(),
+            slot_cfg.clone(),
+            shutdown.resubscribe(),
+        ));
+        match handle.await {
+            Ok(()) if shutdown_requested(&mut shutdown) => return,
+            Ok(()) => error!("Scheduler: task {} exited unexpectedly", entry_key),
+            Err(e) if e.is_panic() => error!("Scheduler: task {} panicked: {}", entry_key, e),
+            Err(e) => error!("Scheduler: task {} was cancelled: {}", entry_key, e),
+        }
+        let crashed = ctx.entries.lock().unwrap().get(&entry_key).cloned();
+        if let Some(entry) = crashed {
+            let status = "restarting...".into();
+            ctx.update_entry(&entry_key, SchedulerEntry { status, ..entry });
+        }
+        warn!(
+            "Scheduler: respawning task {} in {}s",
+            entry_key,
+            RESPAWN_DELAY.as_secs()
+        );
+        if !sleep_or_shutdown(RESPAWN_DELAY, &mut shutdown).await {
+            return;
+        }
+    }
 }
 
+/// The next target date for `weekday` as seen from `now`, and when its
+/// booking window opens. See [`scheduler::next_class_date`] for `same_day_until`.
+fn next_booking_window(
+    now: DateTime<Tz>,
+    weekday: Weekday,
+    window: BookingWindow,
+    same_day_until: Option<NaiveTime>,
+) -> (NaiveDate, DateTime<Tz>) {
+    let target_date = scheduler::next_class_date(now.naive_local(), weekday, same_day_until);
+    (
+        target_date,
+        booking_opens_at(target_date, window, now.timezone()),
+    )
+}
+
+/// Windows of one user opening at most this far apart count as simultaneous.
+const PRIORITY_WINDOW_SECS: i64 = 60;
+
+/// Delay per higher-priority day, so the preferred attempt takes the user lock first.
+const PRIORITY_STEP_MS: i64 = 1000;
+
+/// The user's other days with a better `priority` than `day_name` whose
+/// booking windows open together with `opens_at`, as seen from `now`.
+fn preferred_days<'a>(
+    config: &Config,
+    user: &'a User,
+    day_name: &str,
+    opens_at: DateTime<Tz>,
+    now: DateTime<Tz>,
+) -> Vec<&'a str> {
+    let own = config.slots.get(day_name).and_then(|s| s.priority);
+    user.slots
+        .iter()
+        .filter(|d| d.as_str() != day_name)
+        .filter(|d| {
+            let Some(slot_cfg) = config.slots.get(d.as_str()).filter(|s| s.enabled) else {
+                return false;
+            };
+            let better = match (slot_cfg.priority, own) {
+                (Some(p), Some(o)) => p < o,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            let (Some(weekday), Some(window)) = (
+                scheduler::slot_weekday(d),
+                window_schedule(config, slot_cfg),
+            ) else {
+                return false;
+            };
+            let same_day = scheduler::same_day_until(&config.app, slot_cfg);
+            let (_, other_opens) = next_booking_window(now, weekday, window, same_day);
+            better && (other_opens - opens_at).num_seconds().abs() <= PRIORITY_WINDOW_SECS
+        })
+        .map(String::as_str)
+        .collect()
+}
+
+/// How long until `at` according to `clock`, or `None` if it is already due.
+fn time_until(clock: &dyn Clock, at: DateTime<Tz>) -> Option<Duration> {
+    let now = clock.now();
+    if at > now {
+        Some((at - now).to_std().unwrap_or(Duration::from_secs(60)))
+    } else {
+        None
+    }
+}
+
+/// How long before a window opens the coarse sleep hands over to short steps.
+const PRECISE_LEAD: Duration = Duration::from_secs(2);
+
+/// Step of the final approach to a window, re-reading the clock each time.
+const PRECISE_STEP: Duration = Duration::from_millis(2);
+
+/// Sleep until `at`, waking up `PRECISE_LEAD` early to finish in short steps:
+/// a sleep of days drifts from the wall clock, and a window opening is a
+/// race decided in milliseconds. Returns `false` on shutdown.
+async fn sleep_until_precise(
+    clock: &dyn Clock,
+    at: DateTime<Tz>,
+    shutdown: &mut broadcast::Receiver<()>,
+) -> bool {
+    if let Some(dur) = time_until(clock, at) {
+        if dur > PRECISE_LEAD && !sleep_or_shutdown(dur - PRECISE_LEAD, shutdown).await {
+            return false;
+        }
+    }
+    while let Some(dur) = time_until(clock, at) {
+        tokio::time::sleep(dur.min(PRECISE_STEP)).await;
+    }
+    true
+}
+
+/// Try each of the slot's times in order, and at each time each of its activities,
+/// joining the waiting list only for the last slot found when none of them
+/// could be booked directly.
 async fn attempt_slot_booking(
     config: &Config,
     user: &User,
-    slot_time_str: &str,
-    activity: Option<&str>,
+    slot_cfg: &SlotConfig,
     target_date: chrono::NaiveDate,
 ) -> Result<BookingOutcome> {
-    let mut nubapp =
-        NubappClient::new(&config.app.application_id, &config.app.category_activity_id)?;
+    let times = slot_cfg.time.all();
+    let activities = slot_cfg.activities();
+    let mut nubapp = NubappClient::from_config(config, user.gym.as_deref())?;
+    let jitter = login_jitter(&config.app);
+    if !jitter.is_zero() {
+        debug!(
+            "Scheduler: {} logs in after {}ms of jitter",
+            user.name,
+            jitter.as_millis()
+        );
+        tokio::time::sleep(jitter).await;
+    }
     nubapp.login(&user.login, &user.password).await?;
 
     // Check existing bookings to avoid double-booking
-    let bookings_resp = nubapp.get_bookings().await?;
-    let data = bookings_resp.get("data");
+    let bookings = nubapp.get_bookings_typed().await?.bookings;
     let target_ymd = target_date.format("%Y-%m-%d").to_string();
-    let activity_filter = activity.filter(|a| !a.is_empty());
-
-    if let Some(arr) = data
-        .and_then(|d| d.get("bookings"))
-        .and_then(|v| v.as_array())
-    {
-        for b in arr {
-            let start = b
-                .get("start_timestamp")
-                .and_then(|v| v.as_str())
-                .unwrap_or("");
-            if start.contains(&target_ymd) && start.contains(slot_time_str) {
-                if let Some(af) = activity_filter {
-                    let name = b
-                        .get("name_activity")
-                        .or_else(|| b.get("name"))
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("");
-                    if name.to_lowercase().contains(&af.to_lowercase()) {
-                        return Ok(BookingOutcome::AlreadyBooked);
-                    }
-                } else {
-                    return Ok(BookingOutcome::AlreadyBooked);
-                }
+    for b in &bookings {
+        if b.date() == Some(target_date) && times.iter().any(|t| scheduler::starts_at(&b.start, t))
+        {
+            let name = b.name.as_deref().unwrap_or_default().to_lowercase();
+            let wanted = |a: &Option<&str>| {
+                a.filter(|a| !a.is_empty())
+                    .is_none_or(|a| name.contains(&a.to_lowercase()))
+            };
+            if activities.iter().any(wanted) {
+                return Ok(BookingOutcome::AlreadyBooked);
             }
         }
     }
 
+    // The gym cancels bookings beyond the membership's weekly allowance
+    if let Some(cap) = user.max_per_week {
+        let booked = bookings_in_week(&bookings, target_date);
+        if booked >= cap {
+            info!(
+                "Scheduler: {} has {} of {} weekly bookings in the week of {}",
+                user.name, booked, cap, target_ymd
+            );
+            return Ok(BookingOutcome::WeeklyCapReached);
+        }
+    }
+
     // Fetch available slots for the target date
     let api_date = target_date.format("%d-%m-%Y").to_string();
-    let slots = nubapp.get_slots(&api_date).await?;
+    let slots = nubapp
+        .get_slots_in(&api_date, slot_cfg.category.as_deref())
+        .await?;
 
-    let slot = match NubappClient::find_slot(&slots, slot_time_str, activity) {
-        Some(s) => s,
-        None => return Ok(BookingOutcome::SlotNotFound),
+    // (slot_id, message, waiting list full)
+    let mut last_full: Option<(String, String, bool)> = None;
+    let mut tried: Vec<String> = Vec::new();
+    let mut choices = Vec::new();
+    for time in times {
+        choices.extend(activities.iter().map(|a| (time, *a)));
+    }
+    for (time, activity) in choices {
+        let filter = SlotFilter::for_slot(slot_cfg, activity);
+        let Some(slot) = NubappClient::find_slot(&slots, time, &filter) else {
+            continue;
+        };
+
+        let slot_id = slot.calendar_id();
+        // A loose activity name can find the same class twice
+        if tried.contains(&slot_id) {
+            continue;
+        }
+        tried.push(slot_id.clone());
+
+        // Too few places left to take one: treat the class as full
+        if let Some(min) = slot_cfg.min_free.filter(|min| !slot.has_free_spots(*min)) {
+            info!(
+                "Scheduler: {} at {} has fewer than {} free spots, not booking it for {}",
+                slot_id, time, min, user.name
+            );
+            let msg = format!("fewer than {min} free spots");
+            last_full = Some((slot_id, msg, slot.waiting_list_full()));
+            continue;
+        }
+
+        // Try direct booking, again after a refusal because the gym was busy
+        let mut busy_retries = 0;
+        let (success, msg) = loop {
+            let resp = nubapp.book(&slot_id, Some(slot_cfg.guests)).await?;
+            let success = is_success(&resp);
+            let msg = resp
+                .get("message")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string();
+            if success || busy_retries == BUSY_RETRIES || !is_transient_refusal(&msg) {
+                break (success, msg);
+            }
+            busy_retries += 1;
+            info!(
+                "Scheduler: booking {} for {} refused while the gym is busy ({}), retrying",
+                slot_id, user.name, msg
+            );
+            tokio::time::sleep(BUSY_RETRY_DELAY).await;
+        };
+
+        // The answer isn't always what the gym did: a confirmed booking can be
+        // dropped again, and a failure can have gone through
+        let listed = match is_listed(&mut nubapp, &slot_id).await {
+            Ok(listed) => listed,
+            Err(e) => {
+                warn!(
+                    "Scheduler: could not confirm booking for {}: {:#}",
+                    user.name, e
+                );
+                success
+            }
+        };
+        if listed {
+            if !success {
+                warn!(
+                    "Scheduler: booking {} for {} was refused ({}) but is listed, treating it as booked",
+                    slot_id, user.name, msg
+                );
+            }
+            return Ok(BookingOutcome::Booked(slot_id));
+        }
+        if success {
+            warn!(
+                "Scheduler: booking {} for {} was confirmed but isn't listed",
+                slot_id, user.name
+            );
+            return Ok(BookingOutcome::Failed {
+                slot_id,
+                message: "booking confirmed but not listed by the gym".into(),
+            });
+        }
+
+        if is_membership_issue(&msg) {
+            return Ok(BookingOutcome::MembershipIssue {
+                slot_id,
+                message: msg,
+            });
+        }
+        info!(
+            "Scheduler: direct book failed for {} at {} ({})",
+            user.name, time, msg
+        );
+        last_full = Some((slot_id, msg, slot.waiting_list_full()));
+    }
+
+    let Some((slot_id, msg, waiting_list_full)) = last_full else {
+        return Ok(BookingOutcome::SlotNotFound);
     };
+    if !user.joins_waiting_list(&config.app) {
+        info!(
+            "Scheduler: every choice is full and {} doesn't join waiting lists",
+            user.name
+        );
+        return Ok(BookingOutcome::WaitingListDisabled(slot_id));
+    }
+    // Joining would only be refused; retries still try the direct booking
+    if waiting_list_full {
+        info!(
+            "Scheduler: waiting list for {} is full, not joining",
+            user.name
+        );
+        return Ok(BookingOutcome::WaitingListClosed(slot_id));
+    }
 
-    let slot_id = slot
-        .id_activity_calendar
-        .to_string()
-        .trim_matches('"')
-        .to_string();
-
-    // Try direct booking
-    let resp = nubapp.book(&slot_id).await?;
-    let success = resp
-        .get("success")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false);
-
-    if success {
-        return Ok(BookingOutcome::Booked);
-    }
-
-    let msg = resp
-        .get("message")
-        .and_then(|v| v.as_str())
-        .unwrap_or("")
-        .to_string();
-
-    // Slot full — try waiting list
-    info!(
-        "Scheduler: direct book failed for {} ({}), trying waiting list",
-        user.name, msg
-    );
+    // Every choice full — try waiting list for the last one
+    info!("Scheduler: trying waiting list for {}", user.name);
     let wl_resp = nubapp.book_waiting_list(&slot_id).await?;
-    let wl_ok = wl_resp
-        .get("success")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false);
+    let wl_ok = is_success(&wl_resp);
 
     if wl_ok {
-        return Ok(BookingOutcome::WaitingList);
+        return Ok(BookingOutcome::WaitingList(slot_id));
     }
 
-    Ok(BookingOutcome::Failed(msg))
+    Ok(BookingOutcome::Failed {
+        slot_id,
+        message: msg,
+    })
+}
+
+/// How many more times a direct booking refused as transient (see
+/// [`is_transient_refusal`]) is tried before the class counts as full.
+const BUSY_RETRIES: u32 = 2;
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Whether the user's bookings list `slot_id` right now.
+async fn is_listed(nubapp: &mut NubappClient, slot_id: &str) -> Result<bool> {
+    Ok(nubapp
+        .get_bookings_typed()
+        .await?
+        .bookings
+        .iter()
+        .any(|b| b.calendar_id().as_deref() == Some(slot_id)))
+}
+
+/// How many of `bookings` start in the Monday-to-Sunday week of `date`.
+fn bookings_in_week(bookings: &[Booking], date: NaiveDate) -> u32 {
+    let week = date.iso_week();
+    bookings
+        .iter()
+        .filter_map(Booking::date)
+        .filter(|d| d.iso_week() == week)
+        .count() as u32
+}
+
+/// How long after a window opens `user` books: `[app].stagger_ms` times the
+/// user's position in the config, so users don't all hit the API in the same second.
+fn stagger_for(config: &Config, user: &User) -> chrono::Duration {
+    let position = config
+        .users
+        .iter()
+        .position(|u| u.name == user.name)
+        .unwrap_or(0);
+    chrono::Duration::milliseconds(position as i64 * config.app.stagger_ms as i64)
+}
+
+/// A random delay of at most `[app].login_jitter_ms` before a booking logs in.
+fn login_jitter(app: &AppConfig) -> Duration {
+    Duration::from_millis(fastrand::u64(0..=app.login_jitter_ms))
+}
+
+/// How long to wait after the `streak`-th error in a row, once past
+/// `[app].error_backoff_after`; `None` keeps the normal one-minute retry.
+fn error_backoff(app: &AppConfig, streak: u32) -> Option<Duration> {
+    if app.error_backoff_after == 0 || streak < app.error_backoff_after {
+        return None;
+    }
+    let step = (streak - app.error_backoff_after) as usize;
+    let minutes = app
+        .error_backoff_minutes
+        .get(step)
+        .or(app.error_backoff_minutes.last())?;
+    Some(Duration::from_secs(minutes * 60))
+}
+
+fn record_outcome(metrics: &Metrics, user: &str, outcome: &Result<BookingOutcome>) {
+    metrics.inc(metrics::BOOKINGS_ATTEMPTED, &[("user", user)]);
+    let error = match outcome {
+        Ok(BookingOutcome::Booked(_)) => {
+            metrics.inc(metrics::BOOKINGS_SUCCEEDED, &[("user", user)]);
+            return;
+        }
+        Ok(BookingOutcome::WaitingList(_)) => {
+            metrics.inc(metrics::WAITING_LIST_JOINED, &[("user", user)]);
+            return;
+        }
+        Ok(BookingOutcome::AlreadyBooked | BookingOutcome::WeeklyCapReached) => return,
+        Ok(BookingOutcome::WaitingListClosed(_)) => "waiting_list_closed",
+        Ok(BookingOutcome::WaitingListDisabled(_)) => "waiting_list_disabled",
+        Ok(BookingOutcome::SlotNotFound) => "slot_not_found",
+        Ok(BookingOutcome::Failed { .. }) => "failed",
+        Ok(BookingOutcome::MembershipIssue { .. }) => "membership_issue",
+        Err(_) => "error",
+    };
+    metrics.inc(
+        metrics::BOOKING_ERRORS,
+        &[("user", user), ("outcome", error)],
+    );
+}
+
+/// The history line for one attempt of `user` for their `day` class on
+/// `target_date`, which ended `at`, `latency` after the booking window opened.
+fn history_entry(
+    at: DateTime<Tz>,
+    user: &str,
+    day: &str,
+    target_date: NaiveDate,
+    outcome: &Result<BookingOutcome>,
+    latency: chrono::Duration,
+) -> HistoryEntry {
+    match outcome {
+        Ok(BookingOutcome::Booked(slot_id)) => {
+            HistoryEntry::new(at, user, day, target_date, "booked")
+                .with_slot(slot_id)
+                .with_latency(latency)
+        }
+        Ok(BookingOutcome::AlreadyBooked) => {
+            HistoryEntry::new(at, user, day, target_date, "already_booked")
+        }
+        Ok(BookingOutcome::WaitingList(slot_id)) => {
+            HistoryEntry::new(at, user, day, target_date, "waiting_list").with_slot(slot_id)
+        }
+        Ok(BookingOutcome::WaitingListClosed(slot_id)) => {
+            HistoryEntry::new(at, user, day, target_date, "waiting_list_closed").with_slot(slot_id)
+        }
+        Ok(BookingOutcome::WaitingListDisabled(slot_id)) => {
+            HistoryEntry::new(at, user, day, target_date, "waiting_list_disabled")
+                .with_slot(slot_id)
+        }
+        Ok(BookingOutcome::SlotNotFound) => {
+            HistoryEntry::new(at, user, day, target_date, "slot_not_found")
+        }
+        Ok(BookingOutcome::WeeklyCapReached) => {
+            HistoryEntry::new(at, user, day, target_date, "weekly_cap_reached")
+        }
+        Ok(BookingOutcome::Failed { slot_id, message }) => {
+            HistoryEntry::new(at, user, day, target_date, "failed")
+                .with_slot(slot_id)
+                .with_message(message.as_str())
+        }
+        Ok(BookingOutcome::MembershipIssue { slot_id, message }) => {
+            HistoryEntry::new(at, user, day, target_date, "membership_issue")
+                .with_slot(slot_id)
+                .with_message(message.as_str())
+        }
+        Err(e) => {
+            HistoryEntry::new(at, user, day, target_date, "error").with_message(format!("{e:#}"))
+        }
+    }
 }
 
 async fn slot_booking_task(
-    config: Arc<Config>,
+    ctx: TaskContext,
     user: User,
     day_name: String,
-    slot_time_str: String,
-    activity: Option<String>,
-    entries: SchedulerState,
-    booked: Arc<Mutex<HashSet<String>>>,
-    state_path: Arc<PathBuf>,
+    slot_cfg: SlotConfig,
+    mut shutdown: broadcast::Receiver<()>,
 ) {
-    let weekday = scheduler::parse_weekday(&day_name).unwrap();
-    let time_trimmed = slot_time_str.trim();
-    let slot_time = NaiveTime::parse_from_str(time_trimmed, "%H:%M:%S")
-        .or_else(|_| NaiveTime::parse_from_str(time_trimmed, "%H:%M"))
-        .unwrap_or_else(|e| {
-            panic!("Cannot parse slot time '{}': {}", slot_time_str, e);
-        });
-    let booking_time = slot_time + chrono::Duration::minutes(1);
+    let _active = ctx.metrics.task_started();
+    let config = Arc::clone(&ctx.config);
+    let clock = Arc::clone(&ctx.clock);
+    let slot_time_str = slot_cfg.time.to_string();
+    // Shown on the dashboard and in notifications, e.g. "18:00 (+1 guest)"
+    let slot_display = format!("{}{}", slot_time_str, guest_suffix(slot_cfg.guests));
     let entry_key = format!("{}:{}", user.name, day_name);
+    // The booking window follows the most preferred time
+    let schedule = scheduler::slot_weekday(&day_name).zip(window_schedule(&config, &slot_cfg));
+    let same_day = scheduler::same_day_until(&config.app, &slot_cfg);
+    let Some((weekday, window)) = schedule else {
+        // Respawning would fail the same way: park until a reload replaces the task
+        error!(
+            "Scheduler: cannot schedule {} for {}: bad time '{}'",
+            day_name, user.name, slot_time_str
+        );
+        ctx.update_entry(
+            &entry_key,
+            SchedulerEntry {
+                user_name: user.name.clone(),
+                day: capitalize(&day_name),
+                time: slot_display,
+                target_date: "—".into(),
+                books_at: "—".into(),
+                status: "config error: bad time".into(),
+            },
+        );
+        let _ = shutdown.recv().await;
+        return;
+    };
+    let stagger = stagger_for(&config, &user);
+    let label = format!("{} {}", capitalize(&day_name), slot_display);
+    // Retries repeat the same failure every minute — only alert on the first one
+    let mut alerted_failure = false;
+    // Lookups in a row that didn't find the slot, shown on the dashboard
+    let mut not_found_retries = 0u32;
+    let burst = chrono::Duration::seconds(config.app.slot_retry_burst_secs as i64);
+    let burst_interval = Duration::from_millis(config.app.slot_retry_interval_ms);
 
     loop {
-        let now = scheduler::now();
-        let today = now.date_naive();
-        let target_date = scheduler::next_weekday(today, weekday);
+        // Booking window: `window.days` before target, by default at slot_time + booking offset
+        let now = clock.now();
+        let (target_date, opens_at) = next_booking_window(now, weekday, window, same_day);
+        let preferred = preferred_days(&config, &user, &day_name, opens_at, now);
+        if !preferred.is_empty() {
+            info!(
+                "Scheduler: {} books {} after {} (higher priority, same booking window)",
+                user.name,
+                day_name,
+                preferred.join(", ")
+            );
+        }
+        let offset =
+            stagger + chrono::Duration::milliseconds(preferred.len() as i64 * PRIORITY_STEP_MS);
+        let opens_at = opens_at + offset;
+        // When the window for the following week's class opens
+        let next_window = booking_opens_at(
+            target_date + chrono::Duration::days(7),
+            window,
+            now.timezone(),
+        ) + stagger;
         let slot_key = format!("{}:{}:{}", user.login, target_date, slot_time_str);
 
-        // Booking window: 7 days before target at slot_time + 1 min
-        let opens_date = target_date - chrono::Duration::days(7);
-        let opens_naive = NaiveDateTime::new(opens_date, booking_time);
-        let opens_at = opens_naive
-            .and_local_timezone(scheduler::CET)
-            .earliest()
-            .unwrap();
-
         let target_str = target_date.format("%Y-%m-%d").to_string();
-        let opens_str = opens_at.format("%Y-%m-%d %H:%M").to_string();
+        let opens_str = if opens_at.second() == 0 && opens_at.nanosecond() == 0 {
+            opens_at.format("%Y-%m-%d %H:%M %Z").to_string()
+        } else {
+            opens_at.format("%Y-%m-%d %H:%M:%S %Z").to_string()
+        };
 
         // Already booked for this target — advance to next window
-        if booked.lock().unwrap().contains(&slot_key) {
-            let next_window = NaiveDateTime::new(target_date, booking_time)
-                .and_local_timezone(scheduler::CET)
-                .earliest()
-                .unwrap();
-            update_scheduler_entry(
-                &entries,
+        if ctx.is_booked(&slot_key) {
+            // Keep the latency shown since this target was booked
+            let status = ctx
+                .entries
+                .lock()
+                .unwrap()
+                .get(&entry_key)
+                .filter(|e| e.target_date == target_str && e.status.starts_with("booked"))
+                .map_or_else(|| "booked".to_string(), |e| e.status.clone());
+            ctx.update_entry(
                 &entry_key,
                 SchedulerEntry {
                     user_name: user.name.clone(),
                     day: capitalize(&day_name),
-                    time: slot_time_str.clone(),
+                    time: slot_display.clone(),
                     target_date: target_str,
                     books_at: opens_str,
-                    status: "booked".into(),
+                    status,
                 },
             );
-            if next_window > scheduler::now() {
-                let dur = (next_window - scheduler::now())
-                    .to_std()
-                    .unwrap_or(Duration::from_secs(60));
-                tokio::time::sleep(dur).await;
-            } else {
-                tokio::time::sleep(Duration::from_secs(60)).await;
+            let dur = time_until(clock.as_ref(), next_window).unwrap_or(Duration::from_secs(60));
+            if !sleep_or_shutdown(dur, &mut shutdown).await {
+                return;
             }
             continue;
         }
 
         // Update dashboard: scheduled
-        update_scheduler_entry(
-            &entries,
+        ctx.update_entry(
             &entry_key,
             SchedulerEntry {
                 user_name: user.name.clone(),
                 day: capitalize(&day_name),
-                time: slot_time_str.clone(),
+                time: slot_display.clone(),
                 target_date: target_str.clone(),
                 books_at: opens_str.clone(),
                 status: "scheduled".into(),
@@ -270,59 +1085,93 @@ async fn slot_booking_task(
         );
 
         // Sleep until booking window opens
-        if opens_at > now {
+        if time_until(clock.as_ref(), opens_at).is_some() {
             info!(
                 "Scheduler: {} {} for {} — booking at {} for {}",
                 day_name, slot_time_str, user.name, opens_str, target_str
             );
-            let dur = (opens_at - now)
-                .to_std()
-                .unwrap_or(Duration::from_secs(60));
-            tokio::time::sleep(dur).await;
+            if !sleep_until_precise(clock.as_ref(), opens_at, &mut shutdown).await {
+                return;
+            }
         }
 
         // Attempt booking
-        update_scheduler_entry(
-            &entries,
+        ctx.update_entry(
             &entry_key,
             SchedulerEntry {
                 user_name: user.name.clone(),
                 day: capitalize(&day_name),
-                time: slot_time_str.clone(),
+                time: slot_display.clone(),
                 target_date: target_str.clone(),
                 books_at: opens_str.clone(),
                 status: "booking...".into(),
             },
         );
 
-        match attempt_slot_booking(
-            &config,
-            &user,
-            &slot_time_str,
-            activity.as_deref(),
-            target_date,
-        )
-        .await
-        {
-            Ok(BookingOutcome::Booked) => {
+        // One attempt per user at a time, and at most `max_concurrent_bookings`
+        // in flight across all tasks
+        let user_lock = ctx.user_lock(&user.login);
+        let user_guard = user_lock.lock().await;
+        let permit = ctx.booking_slots.acquire().await;
+        let outcome = attempt_slot_booking(&config, &user, &slot_cfg, target_date).await;
+        // From the window opening to the gym's answer
+        let finished = clock.now();
+        let latency = finished - opens_at;
+        drop(permit);
+        drop(user_guard);
+        record_outcome(&ctx.metrics, &user.name, &outcome);
+        // A missing slot is polled for repeatedly; log only the first miss
+        if !(matches!(outcome, Ok(BookingOutcome::SlotNotFound)) && not_found_retries > 0) {
+            let line = history_entry(
+                finished,
+                &user.name,
+                &day_name,
+                target_date,
+                &outcome,
+                latency,
+            );
+            ctx.history.record(&line);
+        }
+        if !matches!(outcome, Ok(BookingOutcome::SlotNotFound)) {
+            not_found_retries = 0;
+        }
+        // Any answer from the gym ends a run of errors
+        let backoff = match &outcome {
+            Ok(_) => {
+                ctx.reset_errors(&user.login);
+                None
+            }
+            Err(_) => error_backoff(&config.app, ctx.record_error(&user.login)),
+        };
+
+        match outcome {
+            Ok(BookingOutcome::Booked(_)) => {
+                let latency = format_latency(latency.num_milliseconds());
                 info!(
-                    "Scheduler: booked {} {} for {} on {}",
-                    day_name, slot_time_str, user.name, target_str
+                    "Scheduler: booked {} {} for {} on {}, {} after the window opened",
+                    day_name, slot_display, user.name, target_str, latency
                 );
-                let mut set = booked.lock().unwrap();
-                set.insert(slot_key);
-                save_booked_slots(&state_path, &set);
-                drop(set);
-                update_scheduler_entry(
-                    &entries,
+                ctx.mark_booked(slot_key);
+                alerted_failure = false;
+                notify_user(
+                    &config.notifications,
+                    &user,
+                    &format_outcome(
+                        &user.name,
+                        &format!("{label} on {target_str}"),
+                        &Outcome::Booked,
+                    ),
+                )
+                .await;
+                ctx.update_entry(
                     &entry_key,
                     SchedulerEntry {
                         user_name: user.name.clone(),
                         day: capitalize(&day_name),
-                        time: slot_time_str.clone(),
+                        time: slot_display.clone(),
                         target_date: target_str,
                         books_at: opens_str,
-                        status: "booked".into(),
+                        status: format!("booked {latency}"),
                     },
                 );
             }
@@ -331,78 +1180,209 @@ async fn slot_booking_task(
                     "Scheduler: {} already booked {} {} on {}",
                     user.name, day_name, slot_time_str, target_str
                 );
-                booked.lock().unwrap().insert(slot_key);
-                update_scheduler_entry(
-                    &entries,
+                ctx.mark_booked(slot_key);
+                ctx.update_entry(
                     &entry_key,
                     SchedulerEntry {
                         user_name: user.name.clone(),
                         day: capitalize(&day_name),
-                        time: slot_time_str.clone(),
+                        time: slot_display.clone(),
                         target_date: target_str,
                         books_at: opens_str,
                         status: "already booked".into(),
                     },
                 );
             }
-            Ok(BookingOutcome::WaitingList) => {
+            Ok(BookingOutcome::WeeklyCapReached) => {
+                // Not marked booked: next week's window gets a fresh count
+                ctx.update_entry(
+                    &entry_key,
+                    SchedulerEntry {
+                        user_name: user.name.clone(),
+                        day: capitalize(&day_name),
+                        time: slot_display.clone(),
+                        target_date: target_str,
+                        books_at: opens_str,
+                        status: "skipped: weekly cap reached".into(),
+                    },
+                );
+            }
+            Ok(BookingOutcome::WaitingList(_)) => {
                 info!(
                     "Scheduler: {} added to waiting list for {} {} on {}",
                     user.name, day_name, slot_time_str, target_str
                 );
-                booked.lock().unwrap().insert(slot_key);
-                update_scheduler_entry(
-                    &entries,
+                ctx.mark_booked(slot_key);
+                alerted_failure = false;
+                notify_user(
+                    &config.notifications,
+                    &user,
+                    &format_outcome(
+                        &user.name,
+                        &format!("{label} on {target_str}"),
+                        &Outcome::WaitingList,
+                    ),
+                )
+                .await;
+                ctx.update_entry(
                     &entry_key,
                     SchedulerEntry {
                         user_name: user.name.clone(),
                         day: capitalize(&day_name),
-                        time: slot_time_str.clone(),
+                        time: slot_display.clone(),
                         target_date: target_str,
                         books_at: opens_str,
                         status: "full, joined waiting list".into(),
                     },
                 );
             }
+            Ok(
+                ref full @ (BookingOutcome::WaitingListClosed(_)
+                | BookingOutcome::WaitingListDisabled(_)),
+            ) => {
+                let (status, notice) = match full {
+                    BookingOutcome::WaitingListClosed(_) => {
+                        ("full, waiting list closed", Outcome::WaitingListClosed)
+                    }
+                    _ => ("full, waiting list disabled", Outcome::WaitingListDisabled),
+                };
+                warn!(
+                    "Scheduler: {} {} for {} on {} is {}",
+                    day_name, slot_time_str, user.name, target_str, status
+                );
+                if !alerted_failure {
+                    alerted_failure = true;
+                    notify_user(
+                        &config.notifications,
+                        &user,
+                        &format_outcome(&user.name, &format!("{label} on {target_str}"), &notice),
+                    )
+                    .await;
+                }
+                ctx.update_entry(
+                    &entry_key,
+                    SchedulerEntry {
+                        user_name: user.name.clone(),
+                        day: capitalize(&day_name),
+                        time: slot_display.clone(),
+                        target_date: target_str,
+                        books_at: opens_str,
+                        status: status.into(),
+                    },
+                );
+                // Someone may still cancel: keep trying the booking itself
+                if !sleep_or_shutdown(Duration::from_secs(60), &mut shutdown).await {
+                    return;
+                }
+                continue;
+            }
             Ok(BookingOutcome::SlotNotFound) => {
                 warn!(
                     "Scheduler: slot not found {} {} for {} on {}",
                     day_name, slot_time_str, user.name, target_str
                 );
-                update_scheduler_entry(
-                    &entries,
+                let status = if not_found_retries == 0 {
+                    "slot not found".to_string()
+                } else {
+                    format!("slot not found (retry {not_found_retries})")
+                };
+                not_found_retries += 1;
+                ctx.update_entry(
                     &entry_key,
                     SchedulerEntry {
                         user_name: user.name.clone(),
                         day: capitalize(&day_name),
-                        time: slot_time_str.clone(),
+                        time: slot_display.clone(),
                         target_date: target_str,
                         books_at: opens_str,
-                        status: "slot not found".into(),
+                        status,
                     },
                 );
-                // Retry in 60s
-                tokio::time::sleep(Duration::from_secs(60)).await;
+                // Slots are sometimes listed a moment after the window opens:
+                // poll quickly at first, then fall back to once a minute
+                let retry_in = if clock.now() < opens_at + burst {
+                    burst_interval
+                } else {
+                    Duration::from_secs(60)
+                };
+                if !sleep_or_shutdown(retry_in, &mut shutdown).await {
+                    return;
+                }
                 continue;
             }
-            Ok(BookingOutcome::Failed(msg)) => {
+            Ok(BookingOutcome::Failed { message: msg, .. }) => {
                 warn!(
                     "Scheduler: failed {} {} for {}: {}",
                     day_name, slot_time_str, user.name, msg
                 );
-                update_scheduler_entry(
-                    &entries,
+                if !alerted_failure {
+                    alerted_failure = true;
+                    notify_user(
+                        &config.notifications,
+                        &user,
+                        &format_outcome(
+                            &user.name,
+                            &format!("{label} on {target_str}"),
+                            &Outcome::Failed(&msg),
+                        ),
+                    )
+                    .await;
+                }
+                ctx.update_entry(
                     &entry_key,
                     SchedulerEntry {
                         user_name: user.name.clone(),
                         day: capitalize(&day_name),
-                        time: slot_time_str.clone(),
+                        time: slot_display.clone(),
                         target_date: target_str,
                         books_at: opens_str,
                         status: format!("failed: {msg}"),
                     },
                 );
-                tokio::time::sleep(Duration::from_secs(60)).await;
+                if !sleep_or_shutdown(Duration::from_secs(60), &mut shutdown).await {
+                    return;
+                }
+                continue;
+            }
+            Ok(BookingOutcome::MembershipIssue { message: msg, .. }) => {
+                // Listen before showing as paused: a Resume clicked in between
+                // would otherwise wake nobody
+                let signal = ctx.resumer.signal(&user.login);
+                let resumed = signal.notified();
+                tokio::pin!(resumed);
+                resumed.as_mut().enable();
+                warn!(
+                    "Scheduler: membership issue booking {} {} for {}: {}; paused until resumed",
+                    day_name, slot_time_str, user.name, msg
+                );
+                notify_user(
+                    &config.notifications,
+                    &user,
+                    &format_outcome(
+                        &user.name,
+                        &format!("{label} on {target_str}"),
+                        &Outcome::MembershipIssue(&msg),
+                    ),
+                )
+                .await;
+                ctx.update_entry(
+                    &entry_key,
+                    SchedulerEntry {
+                        user_name: user.name.clone(),
+                        day: capitalize(&day_name),
+                        time: slot_display.clone(),
+                        target_date: target_str,
+                        books_at: opens_str,
+                        status: format!("membership issue — paused: {msg}"),
+                    },
+                );
+                // Every retry would be refused the same way: wait for the
+                // dashboard's Resume button or a config reload
+                if !wait_resumed(resumed, &mut shutdown).await {
+                    return;
+                }
+                info!("Scheduler: resuming {} for {}", day_name, user.name);
+                alerted_failure = false;
                 continue;
             }
             Err(e) => {
@@ -410,35 +1390,1236 @@ async fn slot_booking_task(
                     "Scheduler: error {} {} for {}: {:#}",
                     day_name, slot_time_str, user.name, e
                 );
-                update_scheduler_entry(
-                    &entries,
+                if !alerted_failure {
+                    alerted_failure = true;
+                    notify_user(
+                        &config.notifications,
+                        &user,
+                        &format_outcome(
+                            &user.name,
+                            &format!("{label} on {target_str}"),
+                            &Outcome::Error(&format!("{e:#}")),
+                        ),
+                    )
+                    .await;
+                }
+                let status = match backoff {
+                    Some(wait) => {
+                        warn!(
+                            "Scheduler: repeated errors for {}, backing off {} minutes",
+                            user.name,
+                            wait.as_secs() / 60
+                        );
+                        format!("degraded (backing off {}m): {e}", wait.as_secs() / 60)
+                    }
+                    None => format!("error: {e}"),
+                };
+                ctx.update_entry(
                     &entry_key,
                     SchedulerEntry {
                         user_name: user.name.clone(),
                         day: capitalize(&day_name),
-                        time: slot_time_str.clone(),
+                        time: slot_display.clone(),
                         target_date: target_str,
                         books_at: opens_str,
-                        status: format!("error: {e}"),
+                        status,
                     },
                 );
-                tokio::time::sleep(Duration::from_secs(60)).await;
+                let retry_in = backoff.unwrap_or(Duration::from_secs(60));
+                if !sleep_or_shutdown(retry_in, &mut shutdown).await {
+                    return;
+                }
                 continue;
             }
         }
 
         // Successfully handled — sleep until next booking window opens
-        let next_window = NaiveDateTime::new(target_date, booking_time)
-            .and_local_timezone(scheduler::CET)
-            .earliest()
+        let dur = time_until(clock.as_ref(), next_window).unwrap_or(Duration::from_secs(60));
+        if !sleep_or_shutdown(dur, &mut shutdown).await {
+            return;
+        }
+    }
+}
+
+/// `run-schedulers --once`: book, a single time each, every user's day whose
+/// booking window opens within `tolerance` of now, early or late, then save
+/// the state and return. Meant to be run from cron at the window's time;
+/// nothing is retried. Fails when any of the bookings didn't go through.
+pub(crate) async fn run_once(
+    config: Arc<Config>,
+    state_path: PathBuf,
+    history: History,
+    clock: Arc<dyn Clock>,
+    tolerance: Duration,
+) -> Result<()> {
+    let ctx = TaskContext::load(
+        Arc::clone(&config),
+        SchedulerState::default(),
+        state_path,
+        history,
+        clock,
+        Arc::new(Metrics::default()),
+        LiveEvents::default(),
+    );
+    let now = ctx.clock.now();
+    let mut due: Vec<_> = wanted_tasks(&config, &ctx.entries)
+        .into_values()
+        .filter_map(|spec| {
+            let (target_date, opens_at) = due_window(&config, &spec, now, tolerance)?;
+            Some((spec, target_date, opens_at))
+        })
+        .collect();
+    due.sort_by_key(|(_, _, opens_at)| *opens_at);
+    if due.is_empty() {
+        info!(
+            "Scheduler: no booking window opens within {}s of now",
+            tolerance.as_secs()
+        );
+        ctx.persist();
+        return Ok(());
+    }
+
+    let total = due.len();
+    let mut attempts = tokio::task::JoinSet::new();
+    for (spec, target_date, opens_at) in due {
+        attempts.spawn(book_once(ctx.clone(), spec, target_date, opens_at));
+    }
+    let mut failed = 0;
+    while let Some(result) = attempts.join_next().await {
+        match result {
+            Ok(true) => {}
+            Ok(false) => failed += 1,
+            Err(e) => {
+                error!("Scheduler: booking attempt crashed: {}", e);
+                failed += 1;
+            }
+        }
+    }
+    ctx.persist();
+    if failed > 0 {
+        bail!("{failed} of {total} bookings did not go through");
+    }
+    Ok(())
+}
+
+/// The booking window of `spec` opening within `tolerance` of `now`, as the
+/// class date and the time to book at (stagger and priority included).
+fn due_window(
+    config: &Config,
+    spec: &TaskSpec,
+    now: DateTime<Tz>,
+    tolerance: Duration,
+) -> Option<(NaiveDate, DateTime<Tz>)> {
+    let weekday = scheduler::slot_weekday(&spec.day_name)?;
+    let window = window_schedule(config, &spec.slot_cfg)?;
+    let tolerance = chrono::Duration::from_std(tolerance).ok()?;
+    // Searching from `tolerance` ago also finds a window that just opened
+    let same_day = scheduler::same_day_until(&config.app, &spec.slot_cfg);
+    let (target_date, opens_at) = next_booking_window(now - tolerance, weekday, window, same_day);
+    if (opens_at - now).abs() > tolerance {
+        return None;
+    }
+    Some((
+        target_date,
+        opens_at + booking_delay(config, &spec.user, &spec.day_name, opens_at, now),
+    ))
+}
+
+/// How long after its window opens `user` books `day_name`: the user's
+/// stagger plus a step per higher-priority day opening at the same time.
+fn booking_delay(
+    config: &Config,
+    user: &User,
+    day_name: &str,
+    opens_at: DateTime<Tz>,
+    now: DateTime<Tz>,
+) -> chrono::Duration {
+    let preferred = preferred_days(config, user, day_name, opens_at, now);
+    stagger_for(config, user)
+        + chrono::Duration::milliseconds(preferred.len() as i64 * PRIORITY_STEP_MS)
+}
+
+/// One user's day in the booking plan.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PlannedBooking {
+    pub(crate) user: String,
+    pub(crate) day: String,
+    /// The configured time(s) and guests, e.g. "18:00 (+1 guest)".
+    pub(crate) time: String,
+    pub(crate) activity: String,
+    /// The class date and when the scheduler books it, or why it won't.
+    pub(crate) window: std::result::Result<(NaiveDate, DateTime<Tz>), &'static str>,
+}
+
+/// What the schedulers would book next for every user and day, as seen from
+/// `now`, without calling the API.
+pub(crate) fn plan(config: &Config, now: DateTime<Tz>) -> Vec<PlannedBooking> {
+    let mut planned = Vec::new();
+    for user in &config.users {
+        for day_name in &user.slots {
+            let slot_cfg = config.slots.get(day_name);
+            let window = match slot_cfg {
+                None => Err("no [slots] entry"),
+                Some(s) if !s.enabled => Err("paused"),
+                Some(s) => scheduler::slot_weekday(day_name)
+                    .zip(window_schedule(config, s))
+                    .map(|(weekday, window)| {
+                        let same_day = scheduler::same_day_until(&config.app, s);
+                        let (target_date, opens_at) =
+                            next_booking_window(now, weekday, window, same_day);
+                        (
+                            target_date,
+                            opens_at + booking_delay(config, user, day_name, opens_at, now),
+                        )
+                    })
+                    .ok_or("bad time"),
+            };
+            planned.push(PlannedBooking {
+                user: user.name.clone(),
+                day: day_name.clone(),
+                time: slot_cfg.map_or_else(
+                    || "—".to_string(),
+                    |s| format!("{}{}", s.time, guest_suffix(s.guests)),
+                ),
+                activity: slot_cfg.map_or_else(|| "—".to_string(), SlotConfig::activity_label),
+                window,
+            });
+        }
+    }
+    planned
+}
+
+/// One attempt of `run_once`, at `opens_at`. Returns whether it went
+/// through: booked, on the waiting list, or skipped on purpose.
+async fn book_once(
+    ctx: TaskContext,
+    spec: TaskSpec,
+    target_date: NaiveDate,
+    opens_at: DateTime<Tz>,
+) -> bool {
+    let TaskSpec {
+        user,
+        day_name,
+        slot_cfg,
+    } = spec;
+    let slot_time_str = slot_cfg.time.to_string();
+    let slot_display = format!("{}{}", slot_time_str, guest_suffix(slot_cfg.guests));
+    let entry_key = format!("{}:{}", user.name, day_name);
+    let slot_key = format!("{}:{}:{}", user.login, target_date, slot_time_str);
+    let target_str = target_date.format("%Y-%m-%d").to_string();
+    let label = format!(
+        "{} {} on {}",
+        capitalize(&day_name),
+        slot_display,
+        target_str
+    );
+    let entry = |status: &str| SchedulerEntry {
+        user_name: user.name.clone(),
+        day: capitalize(&day_name),
+        time: slot_display.clone(),
+        target_date: target_str.clone(),
+        books_at: opens_at.format("%Y-%m-%d %H:%M:%S %Z").to_string(),
+        status: status.to_string(),
+    };
+
+    if ctx.is_booked(&slot_key) {
+        info!("Scheduler: {} already booked {}", user.name, label);
+        ctx.update_entry(&entry_key, entry("booked"));
+        return true;
+    }
+    // Nothing stops a one-off run early
+    let (_stop, mut never) = broadcast::channel(1);
+    sleep_until_precise(ctx.clock.as_ref(), opens_at, &mut never).await;
+    ctx.update_entry(&entry_key, entry("booking..."));
+
+    let user_lock = ctx.user_lock(&user.login);
+    let user_guard = user_lock.lock().await;
+    let permit = ctx.booking_slots.acquire().await;
+    let outcome = attempt_slot_booking(&ctx.config, &user, &slot_cfg, target_date).await;
+    let finished = ctx.clock.now();
+    let latency = finished - opens_at;
+    drop(permit);
+    drop(user_guard);
+    record_outcome(&ctx.metrics, &user.name, &outcome);
+    let line = history_entry(
+        finished,
+        &user.name,
+        &day_name,
+        target_date,
+        &outcome,
+        latency,
+    );
+    ctx.history.record(&line);
+
+    let error = outcome.as_ref().err().map(|e| format!("{e:#}"));
+    let (status, notice, went_through) = match &outcome {
+        Ok(BookingOutcome::Booked(_)) => (
+            format!("booked {}", format_latency(latency.num_milliseconds())),
+            Some(Outcome::Booked),
+            true,
+        ),
+        Ok(BookingOutcome::AlreadyBooked) => ("already booked".to_string(), None, true),
+        Ok(BookingOutcome::WeeklyCapReached) => {
+            ("skipped: weekly cap reached".to_string(), None, true)
+        }
+        Ok(BookingOutcome::WaitingList(_)) => (
+            "full, joined waiting list".to_string(),
+            Some(Outcome::WaitingList),
+            true,
+        ),
+        Ok(BookingOutcome::WaitingListClosed(_)) => (
+            "full, waiting list closed".to_string(),
+            Some(Outcome::WaitingListClosed),
+            false,
+        ),
+        Ok(BookingOutcome::WaitingListDisabled(_)) => (
+            "full, waiting list disabled".to_string(),
+            Some(Outcome::WaitingListDisabled),
+            false,
+        ),
+        Ok(BookingOutcome::SlotNotFound) => ("slot not found".to_string(), None, false),
+        Ok(BookingOutcome::Failed { message, .. }) => (
+            format!("failed: {message}"),
+            Some(Outcome::Failed(message)),
+            false,
+        ),
+        Ok(BookingOutcome::MembershipIssue { message, .. }) => (
+            format!("membership issue: {message}"),
+            Some(Outcome::MembershipIssue(message)),
+            false,
+        ),
+        Err(e) => (
+            format!("error: {e}"),
+            Some(Outcome::Error(error.as_deref().unwrap_or_default())),
+            false,
+        ),
+    };
+    if matches!(
+        outcome,
+        Ok(BookingOutcome::Booked(_)
+            | BookingOutcome::AlreadyBooked
+            | BookingOutcome::WaitingList(_))
+    ) {
+        ctx.mark_booked(slot_key);
+    }
+    if went_through {
+        info!("Scheduler: {} for {}: {}", label, user.name, status);
+    } else {
+        warn!("Scheduler: {} for {}: {}", label, user.name, status);
+    }
+    if let Some(notice) = notice {
+        let message = format_outcome(&user.name, &label, &notice);
+        notify_user(&ctx.config.notifications, &user, &message).await;
+    }
+    ctx.update_entry(&entry_key, entry(&status));
+    went_through
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::MockClock;
+    use chrono::TimeZone;
+
+    fn hm(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn test_state_round_trip_and_legacy_format() {
+        let path = std::env::temp_dir().join(format!("resawod-state-{}.json", std::process::id()));
+
+        std::fs::write(&path, r#"["bob:2024-01-01:18:00:00"]"#).unwrap();
+        let legacy = load_state(&path);
+        assert!(legacy.booked.contains("bob:2024-01-01:18:00:00"));
+        assert!(legacy.entries.is_empty());
+
+        let entry = SchedulerEntry {
+            user_name: "Bob".into(),
+            day: "Monday".into(),
+            time: "18:00:00".into(),
+            target_date: "2024-01-08".into(),
+            books_at: "2024-01-01 18:01".into(),
+            status: "booked".into(),
+        };
+        let entries = HashMap::from([("Bob:monday".to_string(), entry)]);
+        save_state(&path, &legacy.booked, &entries);
+        let reloaded = load_state(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(reloaded.booked, legacy.booked);
+        assert_eq!(reloaded.entries["Bob:monday"].status, "booked");
+    }
+
+    #[test]
+    fn test_next_booking_window() {
+        // Monday 2024-01-01 10:00 — next Wednesday is 2024-01-03, window opened 2023-12-27
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 1, 10, 0, 0)
+            .unwrap();
+        let (target, opens) =
+            next_booking_window(now, Weekday::Wed, BookingWindow::at(7, hm(18, 31)), None);
+        assert_eq!(target, NaiveDate::from_ymd_opt(2024, 1, 3).unwrap());
+        assert_eq!(
+            opens,
+            scheduler::CET
+                .with_ymd_and_hms(2023, 12, 27, 18, 31, 0)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_next_booking_window_same_day() {
+        // Wednesday 10:00, bookings open on the day at 08:00: today's 18:00
+        // class is still ahead and its window already open
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 3, 10, 0, 0)
             .unwrap();
-        if next_window > scheduler::now() {
-            let dur = (next_window - scheduler::now())
-                .to_std()
-                .unwrap_or(Duration::from_secs(60));
-            tokio::time::sleep(dur).await;
+        let (target, opens) = next_booking_window(
+            now,
+            Weekday::Wed,
+            BookingWindow::at(0, hm(8, 0)),
+            Some(hm(18, 0)),
+        );
+        assert_eq!(target, NaiveDate::from_ymd_opt(2024, 1, 3).unwrap());
+        assert_eq!(
+            opens,
+            scheduler::CET
+                .with_ymd_and_hms(2024, 1, 3, 8, 0, 0)
+                .unwrap()
+        );
+
+        // After the class has started: next week's, whose window is ahead
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 3, 18, 30, 0)
+            .unwrap();
+        let (target, opens) = next_booking_window(
+            now,
+            Weekday::Wed,
+            BookingWindow::at(0, hm(8, 0)),
+            Some(hm(18, 0)),
+        );
+        assert_eq!(target, NaiveDate::from_ymd_opt(2024, 1, 10).unwrap());
+        assert_eq!(
+            opens,
+            scheduler::CET
+                .with_ymd_and_hms(2024, 1, 10, 8, 0, 0)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_same_day_window_from_config() {
+        let config: Config = toml::from_str(
+            r#"
+            users = []
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            booking_window_days = 0
+            booking_opens_at = "08:00"
+            allow_same_day = true
+            [slots.wednesday]
+            time = "18:00"
+            "#,
+        )
+        .unwrap();
+        let slot_cfg = &config.slots["wednesday"];
+        let window = window_schedule(&config, slot_cfg).unwrap();
+        let same_day = scheduler::same_day_until(&config.app, slot_cfg);
+        let at = |d, h| {
+            scheduler::CET
+                .with_ymd_and_hms(2024, 1, d, h, 0, 0)
+                .unwrap()
+        };
+        let today = NaiveDate::from_ymd_opt(2024, 1, 3).unwrap();
+
+        // Early on Wednesday the task waits for today's window
+        let (target, opens) = next_booking_window(at(3, 7), Weekday::Wed, window, same_day);
+        assert_eq!((target, opens), (today, at(3, 8)));
+        // Once it opened, today's class is still the target, booked right away
+        let (target, opens) = next_booking_window(at(3, 10), Weekday::Wed, window, same_day);
+        assert_eq!((target, opens), (today, at(3, 8)));
+        // After the class, next Wednesday's window on that day
+        let (target, opens) = next_booking_window(at(3, 19), Weekday::Wed, window, same_day);
+        assert_eq!(target, NaiveDate::from_ymd_opt(2024, 1, 10).unwrap());
+        assert_eq!(opens, at(10, 8));
+    }
+
+    #[test]
+    fn test_next_booking_window_custom_offset() {
+        // Bookings open 2 days ahead at a fixed 20:00
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 1, 10, 0, 0)
+            .unwrap();
+        let (target, opens) =
+            next_booking_window(now, Weekday::Fri, BookingWindow::at(2, hm(20, 0)), None);
+        assert_eq!(target, NaiveDate::from_ymd_opt(2024, 1, 5).unwrap());
+        assert_eq!(
+            opens,
+            scheduler::CET
+                .with_ymd_and_hms(2024, 1, 3, 20, 0, 0)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_next_booking_window_in_dst_gap() {
+        // A window at 02:30 on 2024-03-31, when clocks jump 02:00 → 03:00,
+        // opens at 03:30 CEST instead of being skipped
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 3, 30, 12, 0, 0)
+            .unwrap();
+        let (target, opens) =
+            next_booking_window(now, Weekday::Tue, BookingWindow::at(2, hm(2, 30)), None);
+        assert_eq!(target, NaiveDate::from_ymd_opt(2024, 4, 2).unwrap());
+        assert_eq!(
+            opens,
+            scheduler::CET
+                .with_ymd_and_hms(2024, 3, 31, 3, 30, 0)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_stagger_by_user_position() {
+        let config: Config = toml::from_str(
+            r#"
+            slots = {}
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            stagger_ms = 1500
+            [[users]]
+            name = "Bob"
+            login = "bob"
+            password = "x"
+            slots = []
+            [[users]]
+            name = "Alice"
+            login = "alice"
+            password = "y"
+            slots = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            stagger_for(&config, &config.users[0]),
+            chrono::Duration::zero()
+        );
+        assert_eq!(
+            stagger_for(&config, &config.users[1]),
+            chrono::Duration::milliseconds(1500)
+        );
+    }
+
+    #[test]
+    fn test_reload_restarts_only_affected_tasks() {
+        let parse = |extra_slot: &str| -> Config {
+            toml::from_str(&format!(
+                r#"
+                [app]
+                application_id = "1"
+                category_activity_id = "2"
+                [[users]]
+                name = "Bob"
+                login = "bob"
+                password = "x"
+                slots = ["monday", "tuesday"]
+                [[users]]
+                name = "Alice"
+                login = "alice"
+                password = "y"
+                slots = ["friday"]
+                [slots]
+                monday = {{ time = "18:00" }}
+                friday = {{ time = "09:00" }}
+                {extra_slot}
+                "#
+            ))
+            .unwrap()
+        };
+        let old = parse(r#"tuesday = { time = "18:00" }"#);
+        let new = parse(r#"tuesday = { time = "19:00" }"#);
+        let entries = SchedulerState::default();
+        let (before, after) = (wanted_tasks(&old, &entries), wanted_tasks(&new, &entries));
+        assert_eq!(after.len(), 3);
+
+        let restarts = |key: &str| needs_restart(&old, &new, &before[key], &after[key]);
+        assert!(restarts("Bob:tuesday"));
+        // Monday's priority can depend on Tuesday's slot
+        assert!(restarts("Bob:monday"));
+        assert!(!restarts("Alice:friday"));
+
+        let paused = parse(r#"tuesday = { time = "18:00", enabled = false }"#);
+        assert!(!wanted_tasks(&paused, &entries).contains_key("Bob:tuesday"));
+        assert_eq!(entries.lock().unwrap()["Bob:tuesday"].status, "paused");
+    }
+
+    #[test]
+    fn test_preferred_days_share_a_window() {
+        // Next week's Monday and Tuesday classes both open on 2024-01-02 at 20:00
+        let config: Config = toml::from_str(
+            r#"
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            booking_opens_at = "20:00"
+            [slots.monday]
+            time = "18:00:00"
+            booking_window_days = 6
+            priority = 2
+            [slots.tuesday]
+            time = "18:00:00"
+            priority = 1
+            [slots.friday]
+            time = "18:00:00"
+            priority = 0
+            [[users]]
+            name = "Bob"
+            login = "bob"
+            password = "x"
+            slots = ["monday", "tuesday", "friday"]
+            "#,
+        )
+        .unwrap();
+        let user = &config.users[0];
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 2, 10, 0, 0)
+            .unwrap();
+        let opens = |day: &str, wd| {
+            let window = window_schedule(&config, &config.slots[day]).unwrap();
+            next_booking_window(now, wd, window, None).1
+        };
+
+        let monday = opens("monday", Weekday::Mon);
+        assert_eq!(monday, opens("tuesday", Weekday::Tue));
+        assert_eq!(
+            preferred_days(&config, user, "monday", monday, now),
+            ["tuesday"]
+        );
+        // Friday's better priority doesn't matter, its window opens on another day
+        assert!(preferred_days(&config, user, "tuesday", monday, now).is_empty());
+    }
+
+    #[test]
+    fn test_login_jitter_stays_within_max() {
+        let off: AppConfig = toml::from_str("").unwrap();
+        assert!(login_jitter(&off).is_zero());
+        let app: AppConfig = toml::from_str("login_jitter_ms = 500").unwrap();
+        assert!((0..100).all(|_| login_jitter(&app) <= Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_error_backoff_widens() {
+        let app: AppConfig = toml::from_str("").unwrap();
+        assert_eq!(error_backoff(&app, 1), None);
+        assert_eq!(error_backoff(&app, 2), None);
+        assert_eq!(error_backoff(&app, 3), Some(Duration::from_secs(5 * 60)));
+        assert_eq!(error_backoff(&app, 4), Some(Duration::from_secs(15 * 60)));
+        assert_eq!(error_backoff(&app, 5), Some(Duration::from_secs(60 * 60)));
+        assert_eq!(error_backoff(&app, 9), Some(Duration::from_secs(60 * 60)));
+
+        let off: AppConfig = toml::from_str("error_backoff_after = 0").unwrap();
+        assert_eq!(error_backoff(&off, 10), None);
+    }
+
+    #[test]
+    fn test_bookings_in_week_counts_monday_to_sunday() {
+        let bookings: Vec<Booking> = serde_json::from_value(serde_json::json!([
+            {"start_timestamp": "2024-01-07 10:00:00"}, // Sunday before
+            {"start_timestamp": "2024-01-08 18:00:00"}, // Monday
+            {"start": "2024-01-10 18:00:00"},
+            {"start_timestamp": "2024-01-14 10:00:00"}, // Sunday
+            {"start_timestamp": "2024-01-15 18:00:00"}, // next Monday
+            {"name": "no start"},
+        ]))
+        .unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 12).unwrap();
+        assert_eq!(bookings_in_week(&bookings, date), 3);
+        assert_eq!(bookings_in_week(&[], date), 0);
+    }
+
+    #[test]
+    fn test_booking_offset_sets_firing_instant() {
+        let config: Config = toml::from_str(
+            r#"
+            users = []
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            booking_offset_secs = 0
+            [slots.monday]
+            time = "18:00:00"
+            [slots.tuesday]
+            time = "18:00:00"
+            booking_offset_secs = -30
+            [slots.friday]
+            time = "18:00:00"
+            booking_opens_at = "20:00"
+            booking_offset_secs = 90
+            "#,
+        )
+        .unwrap();
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 2, 10, 0, 0)
+            .unwrap();
+        let fires = |day: &str, wd| {
+            let window = window_schedule(&config, &config.slots[day]).unwrap();
+            next_booking_window(now, wd, window, None).1
+        };
+
+        let at = |d, h, m, s| {
+            scheduler::CET
+                .with_ymd_and_hms(2024, 1, d, h, m, s)
+                .unwrap()
+        };
+        assert_eq!(fires("monday", Weekday::Mon), at(1, 18, 0, 0));
+        assert_eq!(fires("tuesday", Weekday::Tue), at(2, 17, 59, 30));
+        // A fixed opening time wins over the offset
+        assert_eq!(
+            window_schedule(&config, &config.slots["friday"]),
+            Some(BookingWindow::at(7, hm(20, 0)))
+        );
+
+        let default: AppConfig = toml::from_str("").unwrap();
+        assert_eq!(default.booking_offset_secs, 60);
+    }
+
+    #[test]
+    fn test_negative_offset_crosses_midnight() {
+        let config: Config = toml::from_str(
+            r#"
+            users = []
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            [slots.wednesday]
+            time = "00:00:00"
+            booking_window_days = 2
+            booking_offset_secs = -30
+            "#,
+        )
+        .unwrap();
+        let window = window_schedule(&config, &config.slots["wednesday"]).unwrap();
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 2, 10, 0, 0)
+            .unwrap();
+        let (target, opens) = next_booking_window(now, Weekday::Wed, window, None);
+
+        // Two days before Wednesday the 3rd at midnight, less 30s: the Sunday evening
+        assert_eq!(target, NaiveDate::from_ymd_opt(2024, 1, 3).unwrap());
+        assert_eq!(
+            opens,
+            scheduler::CET
+                .with_ymd_and_hms(2023, 12, 31, 23, 59, 30)
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_task_fires_when_window_opens() {
+        let (server, config) = gym(true, true).await;
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            [api]
+            base_url = "{}"
+            [[users]]
+            name = "Bob"
+            login = "bob@example.com"
+            password = "x"
+            slots = ["wednesday"]
+            [slots]
+            wednesday = {{ time = "18:00" }}
+            "#,
+            config.api.base_url
+        ))
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("resawod-fires-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Wednesday 2024-01-03, a second before next Wednesday's window opens at 18:01
+        let start = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 3, 18, 0, 59)
+            .unwrap();
+        let clock = Arc::new(MockClock::new(start));
+        let entries = SchedulerState::default();
+        let schedulers = spawn_slot_schedulers(
+            Arc::new(config),
+            Arc::clone(&entries),
+            dir.join("state.json"),
+            History::at(dir.join("history.jsonl")),
+            Arc::clone(&clock) as Arc<dyn Clock>,
+            Arc::new(Metrics::default()),
+            LiveEvents::default(),
+        );
+        let books = || async {
+            let requests = server.received_requests().await.unwrap();
+            requests
+                .iter()
+                .filter(|r| r.url.path().ends_with("bookActivityCalendar.php"))
+                .count()
+        };
+
+        // The mock clock stands still: the task keeps waiting for the window
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(books().await, 0);
+        let status = || {
+            entries
+                .lock()
+                .unwrap()
+                .get("Bob:wednesday")
+                .map(|e| e.status.clone())
+        };
+        assert_eq!(status().as_deref(), Some("scheduled"));
+
+        clock.advance(chrono::Duration::seconds(1));
+        for _ in 0..200 {
+            if status().is_some_and(|s| s.starts_with("booked")) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(books().await, 1);
+        assert!(status().is_some_and(|s| s.starts_with("booked")));
+
+        schedulers.finish(Duration::from_secs(1)).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// A gym whose booking endpoint answers `book_success`, and whose
+    /// bookings list slot 1 only after the booking request when `listed`.
+    async fn gym(book_success: bool, listed: bool) -> (wiremock::MockServer, Config) {
+        use base64::prelude::*;
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let token = format!(
+            "e30.{}.sig",
+            BASE64_URL_SAFE_NO_PAD.encode(r#"{"id_user":7}"#)
+        );
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"token": token})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/users/getUserFutureBookings.php"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"data": {"bookings": []}})),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        let after: Vec<serde_json::Value> = if listed {
+            vec![
+                serde_json::json!({"id_activity_calendar": 1, "start_timestamp": "2024-01-10 18:00:00"}),
+            ]
         } else {
-            tokio::time::sleep(Duration::from_secs(60)).await;
+            Vec::new()
+        };
+        Mock::given(method("POST"))
+            .and(path("/users/getUserFutureBookings.php"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"data": {"bookings": after}})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/activities/getActivitiesCalendar.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": {"activities_calendar": [
+                    {"start_timestamp": "2024-01-10 18:00:00", "end_timestamp": "2024-01-10 19:00:00", "id_activity_calendar": 1}
+                ]}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/activities/bookActivityCalendar.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": book_success, "message": "Class is full"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/activities/bookWaitingActivityCalendar.php"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": false})),
+            )
+            .mount(&server)
+            .await;
+
+        let config: Config = toml::from_str(&format!(
+            r#"
+            slots = {{}}
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            [api]
+            base_url = "{}"
+            [[users]]
+            name = "Bob"
+            login = "bob@example.com"
+            password = "x"
+            slots = []
+            "#,
+            server.uri()
+        ))
+        .unwrap();
+        (server, config)
+    }
+
+    #[tokio::test]
+    async fn test_booking_answer_is_checked_against_bookings() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        for (book_success, listed) in [(false, true), (true, false), (true, true)] {
+            let (_server, config) = gym(book_success, listed).await;
+            let outcome =
+                attempt_slot_booking(&config, &config.users[0], &SlotConfig::new("18:00"), date)
+                    .await;
+            match outcome {
+                // A refusal that went through anyway counts as booked
+                Ok(BookingOutcome::Booked(id)) => assert!(listed && id == "1"),
+                Ok(BookingOutcome::Failed { message, .. }) => {
+                    assert!(book_success && !listed);
+                    assert!(message.contains("not listed"), "{message}");
+                }
+                _ => panic!("unexpected outcome for ({book_success}, {listed})"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_min_free_goes_to_waiting_list() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let (server, config) = gym(true, false).await;
+        Mock::given(method("POST"))
+            .and(path("/activities/getActivitiesCalendar.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": {"activities_calendar": [{
+                    "start_timestamp": "2024-01-10 18:00:00", "end_timestamp": "2024-01-10 19:00:00",
+                    "id_activity_calendar": 1, "n_inscribed": 9, "n_capacity": 10
+                }]}
+            })))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/activities/bookActivityCalendar.php"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": true})),
+            )
+            .with_priority(1)
+            .expect(0)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/activities/bookWaitingActivityCalendar.php"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": true})),
+            )
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let slot_cfg = SlotConfig {
+            min_free: Some(2),
+            ..SlotConfig::new("18:00")
+        };
+        let outcome = attempt_slot_booking(&config, &config.users[0], &slot_cfg, date).await;
+        assert!(matches!(outcome, Ok(BookingOutcome::WaitingList(id)) if id == "1"));
+    }
+
+    #[tokio::test]
+    async fn test_membership_refusal_is_not_retried_as_full() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let (server, config) = gym(false, false).await;
+        Mock::given(method("POST"))
+            .and(path("/activities/bookActivityCalendar.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": false, "message": "Your membership has expired"
+            })))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/activities/bookWaitingActivityCalendar.php"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": true})),
+            )
+            .with_priority(1)
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let outcome =
+            attempt_slot_booking(&config, &config.users[0], &SlotConfig::new("18:00"), date).await;
+        match outcome {
+            Ok(BookingOutcome::MembershipIssue { slot_id, message }) => {
+                assert_eq!(
+                    (slot_id.as_str(), message.as_str()),
+                    ("1", "Your membership has expired")
+                );
+            }
+            _ => panic!("expected a membership issue"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_busy_refusal_is_retried_before_waiting_list() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let (server, config) = gym(true, true).await;
+        Mock::given(method("POST"))
+            .and(path("/activities/bookActivityCalendar.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": false, "message": "Booking already processing, try again"
+            })))
+            .with_priority(1)
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+
+        let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let outcome =
+            attempt_slot_booking(&config, &config.users[0], &SlotConfig::new("18:00"), date).await;
+        assert!(matches!(outcome, Ok(BookingOutcome::Booked(id)) if id == "1"));
+        let requests = server.received_requests().await.unwrap();
+        let books = requests
+            .iter()
+            .filter(|r| r.url.path().ends_with("bookActivityCalendar.php"));
+        assert_eq!(books.count(), 2);
+        assert!(!requests.iter().any(|r| r.url.path().contains("Waiting")));
+    }
+
+    #[tokio::test]
+    async fn test_resume_before_waiting_is_kept() {
+        let resumer = Resumer::default();
+        let (_tx, mut shutdown) = broadcast::channel(1);
+        let signal = resumer.signal("bob");
+        let resumed = signal.notified();
+        tokio::pin!(resumed);
+        resumed.as_mut().enable();
+
+        // Clicked after the task showed as paused, before it started waiting
+        resumer.resume("bob");
+        let woke =
+            tokio::time::timeout(Duration::from_secs(1), wait_resumed(resumed, &mut shutdown));
+        assert_eq!(woke.await.ok(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_bad_time_marks_entry_instead_of_panicking() {
+        let config: Config = toml::from_str(
+            r#"
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            [[users]]
+            name = "Bob"
+            login = "bob"
+            password = "x"
+            slots = ["monday"]
+            [slots]
+            monday = { time = "25:99" }
+            "#,
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("resawod-bad-time-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let entries = SchedulerState::default();
+        let start = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 1, 10, 0, 0)
+            .unwrap();
+        let schedulers = spawn_slot_schedulers(
+            Arc::new(config),
+            Arc::clone(&entries),
+            dir.join("state.json"),
+            History::at(dir.join("history.jsonl")),
+            Arc::new(MockClock::new(start)),
+            Arc::new(Metrics::default()),
+            LiveEvents::default(),
+        );
+
+        let status = || {
+            entries
+                .lock()
+                .unwrap()
+                .get("Bob:monday")
+                .map(|e| e.status.clone())
+        };
+        for _ in 0..100 {
+            if status().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
+        assert_eq!(status().as_deref(), Some("config error: bad time"));
+        // The task is still alive, waiting for shutdown
+        assert!(schedulers.tasks.values().all(|t| !t.handle.is_finished()));
+
+        schedulers.finish(Duration::from_secs(1)).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_due_window_within_tolerance() {
+        let config: Config = toml::from_str(
+            r#"
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            booking_opens_at = "20:00"
+            booking_window_days = 2
+            [[users]]
+            name = "Bob"
+            login = "bob"
+            password = "x"
+            slots = ["wednesday"]
+            [slots]
+            wednesday = { time = "18:00" }
+            "#,
+        )
+        .unwrap();
+        let spec = &wanted_tasks(&config, &SchedulerState::default())["Bob:wednesday"];
+        let at = |d, h, m| {
+            scheduler::CET
+                .with_ymd_and_hms(2024, 1, d, h, m, 0)
+                .unwrap()
+        };
+        let due = |now| due_window(&config, spec, now, Duration::from_secs(300));
+
+        // Wednesday 2024-01-10's window opens on Monday at 20:00
+        let window = Some((NaiveDate::from_ymd_opt(2024, 1, 10).unwrap(), at(8, 20, 0)));
+        assert_eq!(due(at(8, 20, 2)), window);
+        assert_eq!(due(at(8, 19, 58)), window);
+        assert_eq!(due(at(8, 20, 10)), None);
+        assert_eq!(due(at(9, 10, 0)), None);
+    }
+
+    #[test]
+    fn test_plan_lists_every_user_day() {
+        let config: Config = toml::from_str(
+            r#"
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            booking_window_days = 2
+            booking_offset_secs = 5
+            stagger_ms = 500
+            [[users]]
+            name = "Alice"
+            login = "alice"
+            password = "x"
+            slots = ["wednesday", "friday"]
+            [[users]]
+            name = "Bob"
+            login = "bob"
+            password = "x"
+            slots = ["wednesday", "sunday"]
+            [slots]
+            wednesday = { time = "18:00", activity = "WOD", guests = 1 }
+            friday = { time = "07:00", enabled = false }
+            "#,
+        )
+        .unwrap();
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 8, 12, 0, 0)
+            .unwrap();
+        let planned = plan(&config, now);
+
+        let rows: Vec<_> = planned
+            .iter()
+            .map(|p| (p.user.as_str(), p.day.as_str()))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("Alice", "wednesday"),
+                ("Alice", "friday"),
+                ("Bob", "wednesday"),
+                ("Bob", "sunday")
+            ]
+        );
+        assert_eq!(planned[0].time, "18:00:00 (+1 guest)");
+        assert_eq!(planned[0].activity, "WOD");
+        // Wednesday 2024-01-10 opens two days before at 18:00:05, Bob half a second later
+        let wednesday = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let opens = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 8, 18, 0, 5)
+            .unwrap();
+        assert_eq!(planned[0].window, Ok((wednesday, opens)));
+        assert_eq!(
+            planned[2].window,
+            Ok((wednesday, opens + chrono::Duration::milliseconds(500)))
+        );
+        assert_eq!(planned[1].window, Err("paused"));
+        assert_eq!(planned[3].window, Err("no [slots] entry"));
+    }
+
+    #[test]
+    fn test_two_classes_on_one_weekday() {
+        let config: Config = toml::from_str(
+            r#"
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            booking_window_days = 2
+            booking_offset_secs = 0
+            [[users]]
+            name = "Bob"
+            login = "bob"
+            password = "x"
+            slots = ["monday", "monday-evening"]
+            [slots]
+            monday = "07:00"
+            monday-evening = "18:00"
+            "#,
+        )
+        .unwrap();
+        let entries = SchedulerState::default();
+        let tasks = wanted_tasks(&config, &entries);
+        assert!(tasks.contains_key("Bob:monday") && tasks.contains_key("Bob:monday-evening"));
+
+        // Both book the same Monday, each when its own window opens
+        let now = scheduler::CET
+            .with_ymd_and_hms(2024, 1, 3, 12, 0, 0)
+            .unwrap();
+        let planned = plan(&config, now);
+        let monday = NaiveDate::from_ymd_opt(2024, 1, 8).unwrap();
+        let opens = |h| {
+            scheduler::CET
+                .with_ymd_and_hms(2024, 1, 6, h, 0, 0)
+                .unwrap()
+        };
+        assert_eq!(planned[0].window, Ok((monday, opens(7))));
+        assert_eq!(planned[1].window, Ok((monday, opens(18))));
+    }
+
+    #[tokio::test]
+    async fn test_waiting_list_skipped_when_disabled() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let (server, mut config) = gym(false, false).await;
+        config.users[0].join_waiting_list = Some(false);
+        let outcome =
+            attempt_slot_booking(&config, &config.users[0], &SlotConfig::new("18:00"), date).await;
+        assert!(matches!(outcome, Ok(BookingOutcome::WaitingListDisabled(id)) if id == "1"));
+        let requests = server.received_requests().await.unwrap();
+        assert!(!requests.iter().any(|r| r.url.path().contains("Waiting")));
     }
 }
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use chrono::DateTime;
+use chrono::{DateTime, NaiveDateTime};
 use chrono_tz::Tz;
 use leptos::prelude::*;
 
@@ -9,6 +9,8 @@ use super::SchedulerEntry;
 use crate::models::{self, Config};
 
 const STYLE: &str = include_str!("../style.css");
+/// Applies `/events` to the page as they arrive.
+const LIVE_SCRIPT: &str = include_str!("../live.js");
 
 pub(super) fn capitalize(s: &str) -> String {
     let mut c = s.chars();
@@ -21,16 +23,33 @@ pub(super) fn capitalize(s: &str) -> String {
 pub(super) fn render_page(
     cfg: &Config,
     users: &[UserDashboard],
+    fetched_at: DateTime<Tz>,
     last_watcher_check: Option<DateTime<Tz>>,
     scheduler_entries: &[SchedulerEntry],
+    clock_skew: Option<chrono::Duration>,
+    cancel_token: Option<&str>,
 ) -> String {
     let slots_html = render_slots_table(&cfg.slots);
-    let scheduler_html = render_scheduler_table(scheduler_entries);
-    let users_html: String = users.iter().map(render_user_section).collect();
-    let now = crate::scheduler::now().format("%Y-%m-%d %H:%M:%S %Z").to_string();
-    let watcher_status = match last_watcher_check {
-        Some(t) => format!("Last watcher check: {}", t.format("%Y-%m-%d %H:%M:%S")),
-        None => "Watcher: waiting for first check...".to_string(),
+    let now = crate::scheduler::now(cfg.app.tz());
+    let scheduler_html = render_scheduler_table(scheduler_entries, now, cancel_token);
+    let users_html: String = users
+        .iter()
+        .map(|u| render_user_section(u, cancel_token))
+        .collect();
+    let now = now.format("%Y-%m-%d %H:%M:%S %Z").to_string();
+    let data_as_of = format!("Bookings data as of {}", fetched_at.format("%H:%M:%S"));
+    let watcher_status = watcher_status(last_watcher_check);
+    let clock_status = match clock_skew {
+        Some(skew) => format!(
+            "Gym server clock: {} from this machine's{}",
+            crate::scheduler::format_offset(skew),
+            if cfg.app.follow_server_clock {
+                ", bookings follow it"
+            } else {
+                ""
+            }
+        ),
+        None => "Gym server clock: could not be read".to_string(),
     };
 
     view! {
@@ -45,6 +64,8 @@ pub(super) fn render_page(
                 <h1>"RESAWOD Dashboard"</h1>
                 <p class="timestamp">"Updated: " {now}</p>
                 <p class="watcher-status">{watcher_status}</p>
+                <p class="data-as-of">{data_as_of}</p>
+                <p class="clock-skew">{clock_status}</p>
                 <section>
                     <h2>"Configured Slots"</h2>
                     <div inner_html=slots_html />
@@ -54,46 +75,64 @@ pub(super) fn render_page(
                     <div inner_html=scheduler_html />
                 </section>
                 <div inner_html=users_html />
+                <script inner_html=LIVE_SCRIPT />
             </body>
         </html>
     }
     .to_html()
 }
 
+/// The dashboard's watcher line, also sent to `/events` after each check.
+pub(super) fn watcher_status(last_check: Option<DateTime<Tz>>) -> String {
+    match last_check {
+        Some(t) => format!("Last watcher check: {}", t.format("%Y-%m-%d %H:%M:%S %Z")),
+        None => "Watcher: waiting for first check...".to_string(),
+    }
+}
+
 fn render_slots_table(slots: &HashMap<String, models::SlotConfig>) -> String {
     if slots.is_empty() {
         return view! { <p class="empty">"No slots configured."</p> }.to_html();
     }
 
-    let days = [
-        "monday",
-        "tuesday",
-        "wednesday",
-        "thursday",
-        "friday",
-        "saturday",
-        "sunday",
-    ];
-    let rows: Vec<(String, String, String)> = days
+    // Monday first; a weekday's further classes ("monday-2") after its own
+    let mut days: Vec<(chrono::Weekday, &String, &models::SlotConfig)> = slots
+        .iter()
+        .filter_map(|(key, c)| crate::scheduler::slot_weekday(key).map(|wd| (wd, key, c)))
+        .collect();
+    days.sort_by_key(|(wd, key, _)| (wd.num_days_from_monday(), key.len(), key.as_str()));
+    let rows: Vec<(String, String, String, bool)> = days
         .iter()
-        .filter_map(|d| {
-            slots
-                .get(*d)
-                .map(|c| (capitalize(d), c.time.clone(), c.activity.clone().unwrap_or_default()))
+        .map(|(_, key, c)| {
+            (
+                capitalize(key),
+                c.time.to_string(),
+                c.activity
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+                c.enabled,
+            )
         })
         .collect();
 
     let rows_html: String = rows
         .iter()
-        .map(|(day, time, activity)| {
+        .map(|(day, time, activity, enabled)| {
             let day = day.clone();
             let time = time.clone();
             let activity = activity.clone();
+            let (status, css) = if *enabled {
+                ("enabled", "")
+            } else {
+                ("paused", "status-disabled")
+            };
             view! {
                 <tr>
                     <td>{day}</td>
                     <td>{time}</td>
                     <td>{activity}</td>
+                    <td class=css>{status}</td>
                 </tr>
             }
             .to_html()
@@ -103,7 +142,7 @@ fn render_slots_table(slots: &HashMap<String, models::SlotConfig>) -> String {
     view! {
         <table>
             <thead>
-                <tr><th>"Day"</th><th>"Time"</th><th>"Activity"</th></tr>
+                <tr><th>"Day"</th><th>"Time"</th><th>"Activity"</th><th>"Status"</th></tr>
             </thead>
             <tbody inner_html=rows_html />
         </table>
@@ -111,7 +150,7 @@ fn render_slots_table(slots: &HashMap<String, models::SlotConfig>) -> String {
     .to_html()
 }
 
-fn render_user_section(user: &UserDashboard) -> String {
+fn render_user_section(user: &UserDashboard, cancel_token: Option<&str>) -> String {
     let name = user.name.clone();
 
     if let Some(ref err) = user.error {
@@ -125,8 +164,8 @@ fn render_user_section(user: &UserDashboard) -> String {
         .to_html();
     }
 
-    let bookings_html = render_bookings_table(&user.bookings);
-    let waiting_html = render_waiting_table(&user.waiting_list);
+    let bookings_html = render_bookings_table(&user.name, &user.bookings, cancel_token);
+    let waiting_html = render_waiting_table(&user.name, &user.waiting_list, cancel_token);
 
     view! {
         <section>
@@ -140,7 +179,12 @@ fn render_user_section(user: &UserDashboard) -> String {
     .to_html()
 }
 
-fn render_bookings_table(bookings: &[BookingRow]) -> String {
+/// With a `cancel_token` (dashboard auth enabled), each booking gets a Cancel button.
+fn render_bookings_table(
+    user: &str,
+    bookings: &[BookingRow],
+    cancel_token: Option<&str>,
+) -> String {
     if bookings.is_empty() {
         return view! { <p class="empty">"No upcoming bookings."</p> }.to_html();
     }
@@ -155,13 +199,22 @@ fn render_bookings_table(bookings: &[BookingRow]) -> String {
             let start = b.start.clone();
             let end = b.end.clone();
             let name = b.name.clone();
+            let coach = b.coach.clone().unwrap_or_default();
+            let cancel_html = match (cancel_token, &b.id) {
+                (Some(token), Some(id)) => {
+                    render_action_form("/cancel", "Cancel this booking?", "Cancel", user, id, token)
+                }
+                _ => String::new(),
+            };
 
             view! {
                 <tr>
                     <td>{start}</td>
                     <td>{end}</td>
                     <td>{name}</td>
+                    <td>{coach}</td>
                     <td class="capacity">{capacity_text}</td>
+                    <td inner_html=cancel_html />
                 </tr>
             }
             .to_html()
@@ -171,7 +224,7 @@ fn render_bookings_table(bookings: &[BookingRow]) -> String {
     view! {
         <table>
             <thead>
-                <tr><th>"Start"</th><th>"End"</th><th>"Activity"</th><th>"Capacity"</th></tr>
+                <tr><th>"Start"</th><th>"End"</th><th>"Activity"</th><th>"Coach"</th><th>"Capacity"</th><th></th></tr>
             </thead>
             <tbody inner_html=rows_html />
         </table>
@@ -179,7 +232,45 @@ fn render_bookings_table(bookings: &[BookingRow]) -> String {
     .to_html()
 }
 
-fn render_waiting_table(entries: &[WaitingRow]) -> String {
+/// A row's button posting `user`, the slot `id` and the form token to
+/// `action`, after the browser confirms `prompt`.
+fn render_action_form(
+    action: &str,
+    prompt: &str,
+    label: &str,
+    user: &str,
+    id: &str,
+    token: &str,
+) -> String {
+    let onsubmit = format!("return confirm('{prompt}')");
+    let (action, label) = (action.to_string(), label.to_string());
+    let (user, id, token) = (user.to_string(), id.to_string(), token.to_string());
+    view! {
+        <form method="post" action=action onsubmit=onsubmit>
+            <input type="hidden" name="user" value=user />
+            <input type="hidden" name="id_activity_calendar" value=id />
+            <input type="hidden" name="token" value=token />
+            <button type="submit" class="cancel">{label}</button>
+        </form>
+    }
+    .to_html()
+}
+
+/// The Resume button posting `user` and the form token to `/resume`.
+fn render_resume_form(user: &str, token: &str) -> String {
+    let (user, token) = (user.to_string(), token.to_string());
+    view! {
+        <form method="post" action="/resume" class="inline">
+            <input type="hidden" name="user" value=user />
+            <input type="hidden" name="token" value=token />
+            <button type="submit" class="cancel">"Resume"</button>
+        </form>
+    }
+    .to_html()
+}
+
+/// With a `cancel_token` (dashboard auth enabled), each entry gets a Leave button.
+fn render_waiting_table(user: &str, entries: &[WaitingRow], cancel_token: Option<&str>) -> String {
     if entries.is_empty() {
         return view! { <p class="empty">"Not on any waiting lists."</p> }.to_html();
     }
@@ -199,10 +290,25 @@ fn render_waiting_table(entries: &[WaitingRow]) -> String {
                 }
                 _ => (String::new(), "capacity"),
             };
+            let queue_text = w
+                .position
+                .map(|p| format!("#{p} in queue"))
+                .unwrap_or_default();
             let start = w.start.clone();
             let end = w.end.clone();
             let name = w.name.clone();
             let css = css.to_string();
+            let leave_html = match cancel_token {
+                Some(token) if !w.id.is_empty() => render_action_form(
+                    "/leave-waitlist",
+                    "Leave this waiting list?",
+                    "Leave",
+                    user,
+                    &w.id,
+                    token,
+                ),
+                _ => String::new(),
+            };
 
             view! {
                 <tr>
@@ -210,6 +316,8 @@ fn render_waiting_table(entries: &[WaitingRow]) -> String {
                     <td>{end}</td>
                     <td>{name}</td>
                     <td class=css>{capacity_text}</td>
+                    <td>{queue_text}</td>
+                    <td inner_html=leave_html />
                 </tr>
             }
             .to_html()
@@ -219,7 +327,7 @@ fn render_waiting_table(entries: &[WaitingRow]) -> String {
     view! {
         <table>
             <thead>
-                <tr><th>"Start"</th><th>"End"</th><th>"Activity"</th><th>"Capacity"</th></tr>
+                <tr><th>"Start"</th><th>"End"</th><th>"Activity"</th><th>"Capacity"</th><th>"Queue"</th><th></th></tr>
             </thead>
             <tbody inner_html=rows_html />
         </table>
@@ -227,42 +335,54 @@ fn render_waiting_table(entries: &[WaitingRow]) -> String {
     .to_html()
 }
 
-fn render_scheduler_table(entries: &[SchedulerEntry]) -> String {
+/// When a "Books At" value such as "2024-01-08 18:01 CET" or
+/// "2024-01-08 18:00:05 CET" is, in the gym's timezone `tz`.
+fn books_at_instant(books_at: &str, tz: Tz) -> Option<DateTime<Tz>> {
+    let mut parts = books_at.split_whitespace();
+    let stamp = format!("{} {}", parts.next()?, parts.next()?);
+    NaiveDateTime::parse_from_str(&stamp, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(&stamp, "%Y-%m-%d %H:%M"))
+        .ok()
+        .map(|t| crate::scheduler::localize(t, tz))
+}
+
+/// "in 2h 13m" until `opens`, "opening now" once it's due. `None` for a
+/// window that has passed on an entry no longer waiting for it.
+fn countdown(opens: DateTime<Tz>, now: DateTime<Tz>, waiting: bool) -> Option<String> {
+    let left = opens - now;
+    if left <= chrono::Duration::zero() {
+        return waiting.then(|| "opening now".to_string());
+    }
+    let (days, hours, minutes) = (
+        left.num_days(),
+        left.num_hours() % 24,
+        left.num_minutes() % 60,
+    );
+    Some(match (days, hours, minutes) {
+        (0, 0, 0) => "in under a minute".to_string(),
+        (0, 0, m) => format!("in {m}m"),
+        (0, h, m) => format!("in {h}h {m}m"),
+        (d, h, _) => format!("in {d}d {h}h"),
+    })
+}
+
+/// With a `cancel_token`, days paused on a membership issue get a Resume button.
+fn render_scheduler_table(
+    entries: &[SchedulerEntry],
+    now: DateTime<Tz>,
+    cancel_token: Option<&str>,
+) -> String {
     if entries.is_empty() {
         return view! { <p class="empty">"No scheduled bookings yet."</p> }.to_html();
     }
 
     let rows_html: String = entries
         .iter()
-        .map(|e| {
-            let user = e.user_name.clone();
-            let slot = format!("{} {}", e.day, e.time);
-            let target = e.target_date.clone();
-            let books_at = e.books_at.clone();
-            let status = e.status.clone();
-            let css = match status.as_str() {
-                "booked" | "already booked" => "status-booked",
-                s if s.starts_with("error") || s.starts_with("failed") => "status-error",
-                "booking..." => "status-active",
-                _ => "status-pending",
-            }
-            .to_string();
-
-            view! {
-                <tr>
-                    <td>{user}</td>
-                    <td>{slot}</td>
-                    <td>{target}</td>
-                    <td>{books_at}</td>
-                    <td class=css>{status}</td>
-                </tr>
-            }
-            .to_html()
-        })
+        .map(|e| render_scheduler_row(e, now, cancel_token))
         .collect();
 
     view! {
-        <table>
+        <table id="scheduler-table">
             <thead>
                 <tr>
                     <th>"User"</th>
@@ -277,3 +397,181 @@ fn render_scheduler_table(entries: &[SchedulerEntry]) -> String {
     }
     .to_html()
 }
+
+/// One row of the scheduler table, also sent to `/events` when it changes.
+/// `data-key` tells the page which row to replace.
+pub(super) fn render_scheduler_row(
+    e: &SchedulerEntry,
+    now: DateTime<Tz>,
+    cancel_token: Option<&str>,
+) -> String {
+    let key = format!("{}:{}", e.user_name, e.day);
+    let user = e.user_name.clone();
+    let slot = format!("{} {}", e.day, e.time);
+    let target = e.target_date.clone();
+    let books_at = e.books_at.clone();
+    let status = e.status.clone();
+    let waiting = matches!(status.as_str(), "scheduled" | "booking...");
+    let opens = books_at_instant(&books_at, now.timezone());
+    let relative = opens.and_then(|t| countdown(t, now, waiting));
+    let countdown_css = match opens {
+        Some(t) if t - now <= chrono::Duration::hours(1) => "countdown opens-soon",
+        _ => "countdown",
+    };
+    let css = match status.as_str() {
+        s if s.starts_with("booked") || s == "already booked" => "status-booked",
+        s if [
+            "error",
+            "failed",
+            "degraded",
+            "config error",
+            "membership issue",
+        ]
+        .iter()
+        .any(|p| s.starts_with(p)) =>
+        {
+            "status-error"
+        }
+        "booking..." => "status-active",
+        "full, waiting list closed" | "full, waiting list disabled" => "status-full",
+        s if s == "paused" || s.starts_with("skipped") => "status-disabled",
+        _ => "status-pending",
+    }
+    .to_string();
+    let resume_html = match cancel_token {
+        Some(token) if status.starts_with("membership issue") => render_resume_form(&user, token),
+        _ => String::new(),
+    };
+
+    view! {
+        <tr data-key=key>
+            <td>{user}</td>
+            <td>{slot}</td>
+            <td>{target}</td>
+            <td>
+                {books_at}
+                {relative.map(|r| view! { " " <span class=countdown_css>{r}</span> })}
+            </td>
+            <td class=css>{status} <span inner_html=resume_html /></td>
+        </tr>
+    }
+    .to_html()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_table_in_weekday_order() {
+        let slots: HashMap<String, models::SlotConfig> = [
+            ("tuesday", "18:00"),
+            ("monday-2", "18:00"),
+            ("monday", "07:00"),
+            ("someday", "09:00"),
+        ]
+        .into_iter()
+        .map(|(day, time)| (day.to_string(), models::SlotConfig::new(time)))
+        .collect();
+        let html = render_slots_table(&slots);
+        let at = |day: &str| html.find(&format!("<td>{day}</td>")).unwrap();
+        assert!(
+            at("Monday") < at("Monday-2") && at("Monday-2") < at("Tuesday"),
+            "{html}"
+        );
+        assert!(!html.contains("Someday"));
+    }
+
+    #[test]
+    fn test_cancel_button_only_with_token() {
+        let rows = [BookingRow {
+            id: Some("123".into()),
+            start: "2024-01-15 18:30:00".into(),
+            end: "2024-01-15 19:30:00".into(),
+            name: "CrossFit".into(),
+            coach: Some("Ana".into()),
+            inscribed: None,
+            capacity: None,
+        }];
+        assert!(!render_bookings_table("Bob", &rows, None).contains("<form"));
+        assert!(render_bookings_table("Bob", &rows, None).contains("<td>Ana</td>"));
+
+        let html = render_bookings_table("Bob", &rows, Some("tok"));
+        assert!(html.contains(r#"action="/cancel""#));
+        assert!(html.contains(r#"name="id_activity_calendar" value="123""#));
+        assert!(html.contains(r#"name="token" value="tok""#));
+        assert!(html.contains("onsubmit="));
+    }
+
+    #[test]
+    fn test_scheduler_table_countdown() {
+        let now = crate::scheduler::parse_timestamp("2024-01-08 15:47:00", crate::scheduler::CET)
+            .unwrap();
+        let entry = |books_at: &str, status: &str| SchedulerEntry {
+            user_name: "Bob".into(),
+            day: "Monday".into(),
+            time: "18:00".into(),
+            target_date: "2024-01-15".into(),
+            books_at: books_at.into(),
+            status: status.into(),
+        };
+        let html = render_scheduler_table(&[entry("2024-01-08 18:00 CET", "scheduled")], now, None);
+        assert!(html.contains("2024-01-08 18:00 CET"));
+        assert!(
+            html.contains(r#"<span class="countdown">in 2h 13m</span>"#),
+            "{html}"
+        );
+        let html =
+            render_scheduler_table(&[entry("2024-01-08 16:01:30 CET", "scheduled")], now, None);
+        assert!(
+            html.contains(r#"<span class="countdown opens-soon">in 14m</span>"#),
+            "{html}"
+        );
+        let html = render_scheduler_table(&[entry("2024-01-10 18:00 CET", "scheduled")], now, None);
+        assert!(html.contains("in 2d 2h"));
+
+        let html =
+            render_scheduler_table(&[entry("2024-01-08 15:00 CET", "booking...")], now, None);
+        assert!(html.contains("opening now"));
+        let html =
+            render_scheduler_table(&[entry("2024-01-08 15:00 CET", "booked +3.2s")], now, None);
+        assert!(!html.contains("countdown"));
+        assert!(html.contains("status-booked"));
+
+        // Paused days get a Resume button, only with dashboard auth
+        let paused = [entry(
+            "2024-01-08 15:00 CET",
+            "membership issue — paused: Membership expired",
+        )];
+        assert!(!render_scheduler_table(&paused, now, None).contains("<form"));
+        let html = render_scheduler_table(&paused, now, Some("tok"));
+        assert!(html.contains(r#"action="/resume""#), "{html}");
+        assert!(html.contains(r#"name="user" value="Bob""#));
+        assert!(html.contains("status-error"));
+
+        // Live updates replace rows by user and day
+        let row = render_scheduler_row(&paused[0], now, Some("tok"));
+        assert!(row.starts_with(r#"<tr data-key="Bob:Monday""#), "{row}");
+        assert!(html.contains(&row));
+    }
+
+    #[test]
+    fn test_waiting_table_queue_position() {
+        let row = |position| WaitingRow {
+            id: "123".into(),
+            start: "2024-01-15 18:30:00".into(),
+            end: "2024-01-15 19:30:00".into(),
+            name: "CrossFit".into(),
+            inscribed: Some(15),
+            capacity: Some(15),
+            position,
+        };
+        assert!(render_waiting_table("Bob", &[row(Some(3))], None).contains("#3 in queue"));
+        assert!(!render_waiting_table("Bob", &[row(None)], None).contains("in queue"));
+        assert!(!render_waiting_table("Bob", &[row(None)], None).contains("<form"));
+
+        let html = render_waiting_table("Bob", &[row(None)], Some("tok"));
+        assert!(html.contains(r#"action="/leave-waitlist""#));
+        assert!(html.contains(r#"name="id_activity_calendar" value="123""#));
+    }
+}
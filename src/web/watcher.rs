@@ -1,30 +1,48 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
 
 use anyhow::Result;
-use chrono::DateTime;
+use chrono::{DateTime, Datelike, NaiveDate};
 use chrono_tz::Tz;
+use tokio::sync::broadcast;
 use tracing::{error, info, warn};
 
-use crate::client::NubappClient;
-use crate::models::{Config, User};
-
-const INTERVAL_IDLE: Duration = Duration::from_secs(3600); // no waiting-list entries
-const INTERVAL_ACTIVE: Duration = Duration::from_secs(60); // has waiting-list entries
+use super::metrics::{self, Metrics};
+use super::{sleep_or_shutdown, LiveConfig, LiveEvent, LiveEvents};
+use crate::client::{is_success, NubappClient, SlotCache};
+use crate::models::{Config, SlotConfig, User};
+use crate::notify::{format_outcome, notify_user, Outcome};
+use crate::scheduler;
 
 pub(crate) async fn waiting_list_watcher(
-    config: Arc<Config>,
+    live_config: LiveConfig,
     last_check: Arc<Mutex<Option<DateTime<Tz>>>>,
+    metrics: Arc<Metrics>,
+    events: LiveEvents,
+    mut shutdown: broadcast::Receiver<()>,
 ) {
-    info!("Waiting-list watcher started (idle: {}s, active: {}s)", INTERVAL_IDLE.as_secs(), INTERVAL_ACTIVE.as_secs());
-    let mut interval = INTERVAL_ACTIVE;
+    let watcher = live_config.get().watcher.clone();
+    info!(
+        "Waiting-list watcher started (idle: {}s, active: {}s)",
+        watcher.idle_interval_secs, watcher.active_interval_secs
+    );
+    let mut interval = watcher.active_interval();
+    // Free spots already alerted about (watch-only, or a failed grab), so each
+    // opening is reported once
+    let mut alerted: HashSet<String> = HashSet::new();
     loop {
-        tokio::time::sleep(interval).await;
+        if !sleep_or_shutdown(interval, &mut shutdown).await {
+            return;
+        }
         info!("Waiting-list watcher: running check");
+        let config = live_config.get();
         let mut any_waiting = false;
+        // Users waiting on the same date share one slot lookup per check
+        let slot_cache = SlotCache::default();
         for user in &config.users {
-            match try_book_from_waiting_list(&config, user).await {
+            match try_book_from_waiting_list(&config, user, &metrics, &slot_cache, &mut alerted)
+                .await
+            {
                 Ok(has_entries) => {
                     any_waiting |= has_entries;
                 }
@@ -33,63 +51,51 @@ pub(crate) async fn waiting_list_watcher(
                 }
             }
         }
-        interval = if any_waiting { INTERVAL_ACTIVE } else { INTERVAL_IDLE };
+        // Read after the check so a reloaded [watcher] applies from the next sleep
+        interval = if any_waiting {
+            config.watcher.active_interval()
+        } else {
+            config.watcher.idle_interval()
+        };
         info!("Waiting-list watcher: next check in {}s", interval.as_secs());
-        *last_check.lock().unwrap() = Some(crate::scheduler::now());
+        let checked_at = scheduler::now(config.app.tz());
+        *last_check.lock().unwrap() = Some(checked_at);
+        events.send(LiveEvent::WatcherCheck(checked_at));
     }
 }
 
 /// Returns `Ok(true)` when the user has waiting-list entries, `Ok(false)` otherwise.
-async fn try_book_from_waiting_list(config: &Config, user: &User) -> Result<bool> {
+/// Watch-only users get a notification for a free spot instead of a booking.
+pub(crate) async fn try_book_from_waiting_list(
+    config: &Config,
+    user: &User,
+    metrics: &Metrics,
+    slot_cache: &SlotCache,
+    alerted: &mut HashSet<String>,
+) -> Result<bool> {
     let mut nubapp =
-        NubappClient::new(&config.app.application_id, &config.app.category_activity_id)?;
+        NubappClient::from_config(config, user.gym.as_deref())?.with_slot_cache(slot_cache);
     nubapp.login(&user.login, &user.password).await?;
 
-    let resp = nubapp.get_bookings().await?;
-    let data = resp.get("data");
-
-    let wl_entries: Vec<&serde_json::Value> = data
-        .and_then(|d| d.get("in_waiting_list"))
-        .and_then(|v| v.as_array())
-        .map(|arr| arr.iter().collect())
-        .unwrap_or_default();
+    let wl_entries = nubapp.get_bookings_typed().await?.waiting_list;
 
     if wl_entries.is_empty() {
         return Ok(false);
     }
 
-    // Collect unique dates (YYYY-MM-DD) from waiting list timestamps
-    let mut dates: Vec<String> = wl_entries
-        .iter()
-        .filter_map(|b| {
-            b.get("start_timestamp")
-                .and_then(|v| v.as_str())
-                .and_then(|s| s.get(..10))
-                .map(|s| s.to_string())
-        })
-        .collect();
+    // Collect unique dates from waiting list timestamps
+    let mut dates: Vec<NaiveDate> = wl_entries.iter().filter_map(|e| e.class.date()).collect();
     dates.sort();
     dates.dedup();
 
     // Fetch current capacity for all relevant slots
     let mut capacity_map: HashMap<String, (u32, u32)> = HashMap::new();
     for date in &dates {
-        if let Some(api_date) = date
-            .get(8..10)
-            .zip(date.get(5..7))
-            .zip(date.get(0..4))
-            .map(|((d, m), y)| format!("{d}-{m}-{y}"))
-        {
-            if let Ok(slots) = nubapp.get_slots(&api_date).await {
-                for slot in &slots {
-                    let id = slot
-                        .id_activity_calendar
-                        .to_string()
-                        .trim_matches('"')
-                        .to_string();
-                    if let (Some(ins), Some(cap)) = (slot.n_inscribed, slot.n_capacity) {
-                        capacity_map.insert(id, (ins, cap));
-                    }
+        if let Ok(slots) = nubapp.get_slots(&date.format("%d-%m-%Y").to_string()).await {
+            for slot in &slots {
+                let id = slot.calendar_id();
+                if let (Some(ins), Some(cap)) = (slot.n_inscribed, slot.n_capacity) {
+                    capacity_map.insert(id, (ins, cap));
                 }
             }
         }
@@ -97,50 +103,114 @@ async fn try_book_from_waiting_list(config: &Config, user: &User) -> Result<bool
 
     // For each waiting list entry, if there's a free spot, try to book it
     for entry in &wl_entries {
-        let slot_id = match entry.get("id_activity_calendar") {
-            Some(v) => v.to_string().trim_matches('"').to_string(),
-            None => continue,
+        let Some(slot_id) = entry.class.calendar_id() else {
+            continue;
         };
 
-        let start = entry
-            .get("start_timestamp")
-            .and_then(|v| v.as_str())
-            .unwrap_or("?");
+        let start = if entry.class.start.is_empty() {
+            "?"
+        } else {
+            entry.class.start.as_str()
+        };
 
         if let Some(&(inscribed, capacity)) = capacity_map.get(&slot_id) {
             let free = capacity.saturating_sub(inscribed);
-            if free > 0 {
+            let alert_key = format!("{}:{}", user.login, slot_id);
+            if free == 0 {
+                alerted.remove(&alert_key);
+            } else if user.watch_only(&config.app) {
+                if alerted.insert(alert_key) {
+                    info!(
+                        "Watcher: free spot for {} (slot {}, {} at {}/{}) — watch-only, notifying",
+                        user.name, slot_id, start, inscribed, capacity
+                    );
+                    notify_user(
+                        &config.notifications,
+                        user,
+                        &format_outcome(
+                            &user.name,
+                            start,
+                            &Outcome::SpotFree {
+                                inscribed,
+                                capacity,
+                            },
+                        ),
+                    )
+                    .await;
+                }
+            } else if too_close_to_start(config, start, scheduler::now(config.app.tz())) {
+                info!(
+                    "Watcher: free spot for {} (slot {}, {}) skipped — past the booking cutoff",
+                    user.name, slot_id, start
+                );
+            } else {
                 info!(
                     "Watcher: free spot for {} (slot {}, {} at {}/{}) — booking",
                     user.name, slot_id, start, inscribed, capacity
                 );
-                match nubapp.book(&slot_id).await {
+                let labels = [("user", user.name.as_str())];
+                metrics.inc(metrics::BOOKINGS_ATTEMPTED, &labels);
+                // The guests the class was booked with as configured
+                let guests = class_slot(config, start, config.app.tz()).map(|s| s.guests);
+                match nubapp.book(&slot_id, guests).await {
                     Ok(resp) => {
-                        let success = resp
-                            .get("success")
-                            .and_then(|v| v.as_bool())
-                            .unwrap_or(false);
+                        let success = is_success(&resp);
                         if success {
+                            metrics.inc(metrics::BOOKINGS_SUCCEEDED, &labels);
                             info!(
                                 "Watcher: booked slot {} for {} (was on waiting list)",
                                 slot_id, user.name
                             );
+                            notify_user(
+                                &config.notifications,
+                                user,
+                                &format_outcome(&user.name, start, &Outcome::BookedFromWaitingList),
+                            )
+                            .await;
                         } else {
                             let msg = resp
                                 .get("message")
                                 .and_then(|v| v.as_str())
                                 .unwrap_or("unknown");
+                            metrics
+                                .inc(metrics::BOOKING_ERRORS, &[labels[0], ("outcome", "failed")]);
                             warn!(
                                 "Watcher: booking slot {} for {} failed: {}",
                                 slot_id, user.name, msg
                             );
+                            // Someone was faster — tell the user once so they can try by hand
+                            if alerted.insert(alert_key) {
+                                notify_user(
+                                    &config.notifications,
+                                    user,
+                                    &format_outcome(
+                                        &user.name,
+                                        start,
+                                        &Outcome::FreedSpotFailed(msg),
+                                    ),
+                                )
+                                .await;
+                            }
                         }
                     }
                     Err(e) => {
+                        metrics.inc(metrics::BOOKING_ERRORS, &[labels[0], ("outcome", "error")]);
                         warn!(
                             "Watcher: booking request failed for {} slot {}: {:#}",
                             user.name, slot_id, e
                         );
+                        if alerted.insert(alert_key) {
+                            notify_user(
+                                &config.notifications,
+                                user,
+                                &format_outcome(
+                                    &user.name,
+                                    start,
+                                    &Outcome::FreedSpotFailed(&format!("{e:#}")),
+                                ),
+                            )
+                            .await;
+                        }
                     }
                 }
             }
@@ -149,3 +219,109 @@ async fn try_book_from_waiting_list(config: &Config, user: &User) -> Result<bool
 
     Ok(true)
 }
+
+/// The `[slots]` entry of a class starting at `start` in `tz`: the one for
+/// that weekday and time, or else just the weekday.
+fn class_slot<'a>(config: &'a Config, start: &str, tz: Tz) -> Option<&'a SlotConfig> {
+    let starts = scheduler::parse_timestamp(start, tz)?;
+    let day = starts.format("%A").to_string().to_lowercase();
+    let same_class = config.slots.iter().find_map(|(key, s)| {
+        let at_time = s.time.all().iter().any(|t| scheduler::starts_at(start, t));
+        (scheduler::slot_weekday(key) == Some(starts.weekday()) && at_time).then_some(s)
+    });
+    same_class.or_else(|| config.slots.get(&day))
+}
+
+/// Whether a class starting at `start` is within its `book_until_minutes_before`
+/// cutoff — the value of its [`class_slot`], or else the `[app]` one.
+fn too_close_to_start(config: &Config, start: &str, now: DateTime<Tz>) -> bool {
+    let Some(starts) = scheduler::parse_timestamp(start, now.timezone()) else {
+        return false;
+    };
+    let cutoff = class_slot(config, start, now.timezone())
+        .and_then(|s| s.book_until_minutes_before)
+        .or(config.app.book_until_minutes_before);
+    cutoff.is_some_and(|m| starts - now < chrono::Duration::minutes(m as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_cutoff_before_class_start() {
+        let config: Config = toml::from_str(
+            r#"
+            users = []
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            book_until_minutes_before = 30
+            [slots.saturday]
+            time = "09:00:00"
+            book_until_minutes_before = 10
+            [slots.saturday-2]
+            time = "11:00:00"
+            book_until_minutes_before = 60
+            "#,
+        )
+        .unwrap();
+        let at = |h, m| {
+            scheduler::CET
+                .with_ymd_and_hms(2024, 1, 13, h, m, 0)
+                .unwrap()
+        };
+
+        // Saturday uses its own 10-minute cutoff
+        assert!(too_close_to_start(
+            &config,
+            "2024-01-13 09:00:00",
+            at(8, 55)
+        ));
+        assert!(!too_close_to_start(
+            &config,
+            "2024-01-13 09:00:00",
+            at(8, 45)
+        ));
+        // The second Saturday class has its own
+        assert!(too_close_to_start(
+            &config,
+            "2024-01-13 11:00:00",
+            at(10, 15)
+        ));
+        // Other days fall back to [app]
+        assert!(too_close_to_start(
+            &config,
+            "2024-01-14 09:00:00",
+            at(8, 45) + chrono::Duration::days(1)
+        ));
+        assert!(!too_close_to_start(&config, "bogus", at(8, 59)));
+    }
+
+    #[test]
+    fn test_freed_spot_keeps_configured_guests() {
+        let config: Config = toml::from_str(
+            r#"
+            users = []
+            [app]
+            application_id = "1"
+            category_activity_id = "2"
+            [slots.saturday]
+            time = "09:00:00"
+            guests = 1
+            [slots.saturday-2]
+            time = "11:00:00"
+            guests = 2
+            "#,
+        )
+        .unwrap();
+        let guests = |start| class_slot(&config, start, scheduler::CET).map(|s| s.guests);
+
+        assert_eq!(guests("2024-01-13 09:00:00"), Some(1));
+        assert_eq!(guests("2024-01-13 11:00:00"), Some(2));
+        // A class at another time that Saturday still falls back to the weekday's
+        assert_eq!(guests("2024-01-13 17:00:00"), Some(1));
+        assert_eq!(guests("2024-01-14 09:00:00"), None);
+    }
+}
@@ -0,0 +1,138 @@
+//! Commands meant to be piped print only their output on stdout.
+
+use std::path::PathBuf;
+use std::process::Output;
+
+use base64::prelude::*;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// A gym that signs in user 7, who has one booking, and offers one slot in
+/// its one activity category.
+async fn gym() -> MockServer {
+    let server = MockServer::start().await;
+    let token = format!(
+        "e30.{}.sig",
+        BASE64_URL_SAFE_NO_PAD.encode(r#"{"id_user":7}"#)
+    );
+    Mock::given(method("POST"))
+        .and(path("/login"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"token": token})))
+        .mount(&server)
+        .await;
+    let class = serde_json::json!({
+        "start_timestamp": "2030-01-10 18:00:00",
+        "end_timestamp": "2030-01-10 19:00:00",
+        "id_activity_calendar": 1,
+        "name_activity": "WOD",
+    });
+    Mock::given(method("POST"))
+        .and(path("/users/getUserFutureBookings.php"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({"data": {"bookings": [class]}})),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/activities/getActivitiesCalendar.php"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({"data": {"activities_calendar": [class]}})),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/categories/getCategories.php"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            serde_json::json!({"data": [{"id_category_activity": 2, "name": "CrossFit"}]}),
+        ))
+        .mount(&server)
+        .await;
+    server
+}
+
+/// A config for `server` in a directory of its own, named after `test`.
+fn config(server: &MockServer, test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("resawod-cli-{test}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    let toml = format!(
+        r#"
+        slots = {{}}
+        [app]
+        application_id = "1"
+        category_activity_id = "2"
+        [api]
+        base_url = "{}"
+        [[users]]
+        name = "Bob"
+        login = "bob@example.com"
+        password = "x"
+        slots = []
+        "#,
+        server.uri()
+    );
+    std::fs::write(&path, toml).unwrap();
+    path
+}
+
+/// Run the binary with `args` and the config at `config`.
+async fn run(config: &PathBuf, args: &[&str]) -> Output {
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_resawod-scheduler"))
+        .args(args)
+        .arg("--config")
+        .arg(config)
+        .output()
+        .await
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // The login is logged, just not on stdout
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Logged in"));
+    output
+}
+
+#[tokio::test]
+async fn test_json_commands_print_only_json() {
+    let server = gym().await;
+    let config = config(&server, "json");
+    for args in [
+        &["bookings", "--json"][..],
+        &["list-slots", "10-01-2030", "--json"],
+    ] {
+        let output = run(&config, args).await;
+        let rows: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_else(|e| {
+            panic!("{args:?}: {e}: {}", String::from_utf8_lossy(&output.stdout))
+        });
+        assert_eq!(rows.as_array().map(Vec::len), Some(1), "{args:?}");
+    }
+    let _ = std::fs::remove_dir_all(config.parent().unwrap());
+}
+
+#[tokio::test]
+async fn test_export_prints_only_ics() {
+    let server = gym().await;
+    let config = config(&server, "ics");
+    let output = run(&config, &["export"]).await;
+    let ics = String::from_utf8(output.stdout).unwrap();
+    assert!(ics.starts_with("BEGIN:VCALENDAR"), "{ics}");
+    assert!(ics.trim_end().ends_with("END:VCALENDAR"), "{ics}");
+    assert_eq!(ics.matches("BEGIN:VEVENT").count(), 1);
+    let _ = std::fs::remove_dir_all(config.parent().unwrap());
+}
+
+#[tokio::test]
+async fn test_discover_to_stdout_prints_only_toml() {
+    let server = gym().await;
+    let config = config(&server, "discover");
+    let output = run(&config, &["discover", "--output", "-"]).await;
+    let section: toml::Table =
+        toml::from_str(std::str::from_utf8(&output.stdout).unwrap()).unwrap();
+    assert_eq!(section["app"]["category_activity_id"].as_str(), Some("2"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Logging in as bob@example.com"));
+    let _ = std::fs::remove_dir_all(config.parent().unwrap());
+}